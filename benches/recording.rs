@@ -0,0 +1,77 @@
+//! Compares recording through the page cache with [`DirectFileWriter`].
+//!
+//! Each run writes 256 MiB in 4 MiB blocks. The plain path ends with `sync_data`, as otherwise it would only measure copying
+//! into the page cache, not getting the data to the disk.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use psrdada::{
+    direct::{AlignedBuf, DirectFileWriter},
+    file::DadaFileWriter,
+    headers::DadaHeader,
+};
+
+const BLOCK_SIZE: usize = 4 << 20;
+const BLOCKS: usize = 64;
+
+fn header() -> DadaHeader {
+    DadaHeader::parse(b"UTC_START 2023-05-01-12:34:56\nOBS_OFFSET 0\n").unwrap()
+}
+
+fn plain(path: &Path, block: &[u8]) {
+    let mut writer =
+        DadaFileWriter::new(BufWriter::new(File::create(path).unwrap()), header()).unwrap();
+    for _ in 0..BLOCKS {
+        writer.write_all(block).unwrap();
+    }
+    let file = writer.finish().unwrap().into_inner().unwrap();
+    file.sync_data().unwrap();
+}
+
+fn direct(writer: DirectFileWriter, block: &[u8]) {
+    let mut writer = writer;
+    for _ in 0..BLOCKS {
+        writer.write_all(block).unwrap();
+    }
+    writer.finish().unwrap();
+}
+
+fn recording(c: &mut Criterion) {
+    let dir = tempfile::tempdir_in(env!("CARGO_TARGET_TMPDIR")).unwrap();
+    let path = dir.path().join("bench.dada");
+    let mut block = AlignedBuf::new(BLOCK_SIZE);
+    block.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+
+    let mut group = c.benchmark_group("recording");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes((BLOCK_SIZE * BLOCKS) as u64));
+    group.bench_function("buffered", |b| b.iter(|| plain(&path, &block)));
+    group.bench_function("direct", |b| {
+        b.iter(|| direct(DirectFileWriter::create(&path, header()).unwrap(), &block))
+    });
+    #[cfg(feature = "io-uring")]
+    if DirectFileWriter::create(&path, header())
+        .unwrap()
+        .io_uring(32)
+        .is_ok()
+    {
+        group.bench_function("direct_io_uring", |b| {
+            b.iter(|| {
+                let writer = DirectFileWriter::create(&path, header())
+                    .unwrap()
+                    .io_uring(32)
+                    .unwrap();
+                direct(writer, &block)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, recording);
+criterion_main!(benches);
@@ -16,13 +16,15 @@ fn main() {
     let (_, mut out_data) = out_client.split();
 
     // Create the readers and writers
-    let mut in_data_rdr = in_data.reader().unwrap();
-    let mut out_data_wdr = out_data.writer().unwrap();
+    let in_data_rdr = in_data.reader().unwrap();
+    let out_data_wdr = out_data.writer().unwrap();
 
-    // Loop forever on reading from the input, applying the transformation and writing to the output
-    while let Some(mut read_block) = in_data_rdr.next() {
-        // Get the next write block
-        if let Some(mut write_block) = out_data_wdr.next() {
+    // Loop forever on reading from the input, applying the transformation and writing to the output.
+    // Zipping the reader and writer pairs up each read block with a write block, stopping when either runs out
+    // (perhaps that buffer was destroyed?)
+    in_data_rdr
+        .zip(out_data_wdr)
+        .for_each(|(mut read_block, mut write_block)| {
             let read_bytes = read_block.block();
             let write_bytes = write_block.block();
             // Transform, these are just slices now, so you can do whatever you want!
@@ -33,9 +35,5 @@ fn main() {
                 *x = *y * 2;
             });
             // No need to lock, mark cleared, or anything like that. That's all implicit with RAII.
-        } else {
-            println!("Errored on getting the next write block, perhaps that buffer was destroyed?");
-            break;
-        }
-    }
+        });
 }
@@ -0,0 +1,79 @@
+//! Record transfers from a ring to `.dada` files, like `dada_dbdisk`
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use psrdada::{
+    cli::parse_key,
+    client::HduClient,
+    disks::{DiskPolicy, DiskSet},
+    recorder::DiskRecorder,
+};
+
+#[derive(Parser)]
+#[command(about = "Record transfers from a psrdada ring to .dada files", version)]
+struct Args {
+    /// Key of the ring to read from, in hex
+    #[arg(short, long, value_parser = parse_key, default_value = "dada")]
+    key: i32,
+    /// Directory to put the observation directories in, repeated to spread files over several disks
+    #[arg(short = 'D', long = "dir", default_value = ".")]
+    dirs: Vec<PathBuf>,
+    /// Take turns between the directories, rather than picking the one with the most free space
+    #[arg(short, long)]
+    round_robin: bool,
+    /// Bytes to leave free on every disk
+    #[arg(long, default_value = "0")]
+    reserve: u64,
+    /// Bytes of data per file, overriding FILE_SIZE from the header
+    #[arg(short = 'z', long)]
+    file_size: Option<u64>,
+    /// Stop after this many transfers, rather than running until the ring goes away
+    #[arg(short = 'n', long)]
+    transfers: Option<usize>,
+}
+
+fn main() {
+    let args = Args::parse();
+    let mut client = match HduClient::connect(args.key) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Couldn't connect to ring {:x}: {e:?}", args.key);
+            std::process::exit(1);
+        }
+    };
+    let policy = if args.round_robin {
+        DiskPolicy::RoundRobin
+    } else {
+        DiskPolicy::MostFree
+    };
+    let disks = DiskSet::new(args.dirs).policy(policy).reserve(args.reserve);
+    let mut recorder = DiskRecorder::with_disks(disks);
+    if let Some(file_size) = args.file_size {
+        recorder = recorder.file_size(file_size);
+    }
+    let mut count = 0;
+    while args.transfers.map_or(true, |n| count < n) {
+        match recorder.record(&mut client) {
+            Ok(recorded) if recorded.dropped > 0 => {
+                eprintln!(
+                    "Every disk is full, stopping after recording {} of {} bytes",
+                    recorded.bytes,
+                    recorded.bytes + recorded.dropped
+                );
+                std::process::exit(1);
+            }
+            Ok(recorded) => println!(
+                "Recorded {} bytes in {} files to {:?}",
+                recorded.bytes,
+                recorded.files.len(),
+                recorded.directories
+            ),
+            Err(e) => {
+                eprintln!("Stopping recording: {e:?}");
+                std::process::exit(1);
+            }
+        }
+        count += 1;
+    }
+}
@@ -0,0 +1,70 @@
+//! Send transfers from a ring to another host, like `dada_dbnic`
+
+use std::time::Duration;
+
+use clap::Parser;
+use psrdada::{
+    bridge::{is_network_error, RingSender},
+    cli::parse_key,
+    client::HduClient,
+};
+
+#[derive(Parser)]
+#[command(
+    about = "Send transfers from a psrdada ring to psrdada-nicdb on another host",
+    version
+)]
+struct Args {
+    /// Key of the ring to read from, in hex
+    #[arg(short, long, value_parser = parse_key, default_value = "dada")]
+    key: i32,
+    /// Address of the receiver, as host:port
+    addr: String,
+    /// Follow every frame with a CRC-32 for the receiver to check
+    #[arg(short, long)]
+    checksums: bool,
+    /// Seconds to wait between attempts to connect
+    #[arg(long, default_value = "1")]
+    retry_delay: f64,
+    /// Give up on a transfer after this many attempts to connect, rather than retrying forever
+    #[arg(long)]
+    max_attempts: Option<usize>,
+    /// Stop after this many transfers, rather than running until the ring goes away
+    #[arg(short = 'n', long)]
+    transfers: Option<usize>,
+}
+
+fn main() {
+    let args = Args::parse();
+    let mut client = match HduClient::connect(args.key) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Couldn't connect to ring {:x}: {e:?}", args.key);
+            std::process::exit(1);
+        }
+    };
+    let mut sender = match RingSender::new(&args.addr) {
+        Ok(sender) => sender
+            .checksums(args.checksums)
+            .retry_delay(Duration::from_secs_f64(args.retry_delay)),
+        Err(e) => {
+            eprintln!("Couldn't resolve {}: {e:?}", args.addr);
+            std::process::exit(1);
+        }
+    };
+    if let Some(attempts) = args.max_attempts {
+        sender = sender.max_attempts(attempts);
+    }
+    let mut count = 0;
+    while args.transfers.map_or(true, |n| count < n) {
+        match sender.send(&mut client) {
+            Ok(bytes) => println!("Sent {bytes} bytes to {}", args.addr),
+            Err(e) if is_network_error(&e) => eprintln!("Dropped a transfer: {e:?}"),
+            Err(e) => {
+                eprintln!("Stopping: {e:?}");
+                std::process::exit(1);
+            }
+        }
+        count += 1;
+    }
+}
@@ -0,0 +1,116 @@
+//! Convert between DADA and SIGPROC filterbanks, in files and rings
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+use clap::{Parser, Subcommand};
+use psrdada::{
+    cli::parse_key,
+    client::HduClient,
+    errors::PsrdadaResult,
+    file::DadaFileReader,
+    headers::{DadaHeader, StandardHeader},
+    sigproc::{
+        dada_to_filterbank, filterbank_to_dada, play_filterbank, record_filterbank,
+        FilterbankHeader,
+    },
+};
+
+#[derive(Parser)]
+#[command(about = "Convert between DADA and SIGPROC filterbank", version)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the header of a .dada or .fil file, converted to the other format
+    Header { input: PathBuf },
+    /// Convert a .dada file to a .fil file, or the other way around
+    Convert { input: PathBuf, output: PathBuf },
+    /// Record the next transfer from a ring to a .fil file
+    Record {
+        /// Key of the ring to read from, in hex
+        #[arg(short, long, value_parser = parse_key, default_value = "dada")]
+        key: i32,
+        output: PathBuf,
+    },
+    /// Write a .fil file into a ring as a single transfer
+    Play {
+        /// Key of the ring to write to, in hex
+        #[arg(short, long, value_parser = parse_key, default_value = "dada")]
+        key: i32,
+        input: PathBuf,
+    },
+}
+
+/// Whether a file starts like a filterbank, rather than a DADA header
+fn is_filterbank(reader: &mut impl BufRead) -> io::Result<bool> {
+    Ok(reader.fill_buf()?.starts_with(b"\x0c\0\0\0HEADER_START"))
+}
+
+fn header(input: PathBuf) -> PsrdadaResult<()> {
+    let mut reader = BufReader::new(File::open(input)?);
+    if is_filterbank(&mut reader)? {
+        let fil = FilterbankHeader::read(&mut reader)?;
+        let mut dada: Vec<_> = HashMap::from(StandardHeader::try_from(&fil)?)
+            .into_iter()
+            .collect();
+        dada.sort();
+        let dada: DadaHeader = dada.into_iter().collect();
+        io::stdout().write_all(&dada.to_bytes())?;
+    } else {
+        let reader = DadaFileReader::new(reader)?;
+        let dada = StandardHeader::try_from(HashMap::from(reader.header()))?;
+        print!("{}", FilterbankHeader::try_from(&dada)?);
+    }
+    Ok(())
+}
+
+fn convert(input: PathBuf, output: PathBuf) -> PsrdadaResult<()> {
+    let mut reader = BufReader::new(File::open(input)?);
+    let mut writer = BufWriter::new(File::create(output)?);
+    let bytes = if is_filterbank(&mut reader)? {
+        filterbank_to_dada(reader, &mut writer)?
+    } else {
+        dada_to_filterbank(reader, &mut writer)?
+    };
+    writer.flush()?;
+    println!("Converted {bytes} bytes of data");
+    Ok(())
+}
+
+fn run(command: Command) -> PsrdadaResult<()> {
+    match command {
+        Command::Header { input } => header(input),
+        Command::Convert { input, output } => convert(input, output),
+        Command::Record { key, output } => {
+            let mut client = HduClient::connect(key)?;
+            let (mut hc, mut dc) = client.split();
+            let mut writer = BufWriter::new(File::create(output)?);
+            let bytes = record_filterbank(&mut hc, &mut dc, &mut writer)?;
+            writer.flush()?;
+            println!("Recorded {bytes} bytes");
+            Ok(())
+        }
+        Command::Play { key, input } => {
+            let mut client = HduClient::connect(key)?;
+            let (mut hc, mut dc) = client.split();
+            let bytes = play_filterbank(File::open(input)?, &mut hc, &mut dc)?;
+            println!("Played {bytes} bytes");
+            Ok(())
+        }
+    }
+}
+
+fn main() {
+    if let Err(e) = run(Args::parse().command) {
+        eprintln!("{e:?}");
+        std::process::exit(1);
+    }
+}
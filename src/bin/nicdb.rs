@@ -0,0 +1,57 @@
+//! Receive transfers from another host into a ring, like `dada_nicdb`
+
+use std::net::SocketAddr;
+
+use clap::Parser;
+use psrdada::{
+    bridge::{is_network_error, RingReceiver},
+    cli::parse_key,
+    client::HduClient,
+};
+
+#[derive(Parser)]
+#[command(
+    about = "Receive transfers from psrdada-dbnic on another host into a psrdada ring",
+    version
+)]
+struct Args {
+    /// Key of the ring to write to, in hex
+    #[arg(short, long, value_parser = parse_key, default_value = "dada")]
+    key: i32,
+    /// Address to listen on
+    #[arg(short, long, default_value = "0.0.0.0:4002")]
+    bind: SocketAddr,
+    /// Stop after this many transfers, rather than running until killed
+    #[arg(short = 'n', long)]
+    transfers: Option<usize>,
+}
+
+fn main() {
+    let args = Args::parse();
+    let mut client = match HduClient::connect(args.key) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Couldn't connect to ring {:x}: {e:?}", args.key);
+            std::process::exit(1);
+        }
+    };
+    let mut receiver = match RingReceiver::bind(args.bind) {
+        Ok(receiver) => receiver,
+        Err(e) => {
+            eprintln!("Couldn't listen on {}: {e:?}", args.bind);
+            std::process::exit(1);
+        }
+    };
+    let mut count = 0;
+    while args.transfers.map_or(true, |n| count < n) {
+        match receiver.receive(&mut client) {
+            Ok(bytes) => println!("Received {bytes} bytes"),
+            Err(e) if is_network_error(&e) => eprintln!("Transfer ended early: {e:?}"),
+            Err(e) => {
+                eprintln!("Stopping: {e:?}");
+                std::process::exit(1);
+            }
+        }
+        count += 1;
+    }
+}
@@ -0,0 +1,55 @@
+//! Load `.dada` files into a ring, optionally at the rate they were recorded at
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use psrdada::{cli::parse_key, client::HduClient, replayer::Replayer};
+
+#[derive(Parser)]
+#[command(about = "Replay .dada files into a psrdada ring", version)]
+struct Args {
+    /// Key of the ring to write to, in hex
+    #[arg(short, long, value_parser = parse_key, default_value = "dada")]
+    key: i32,
+    /// Throttle to this multiple of BYTES_PER_SECOND, rather than writing as fast as possible
+    #[arg(short, long)]
+    speed: Option<f64>,
+    /// Play the files this many times over, or forever if 0
+    #[arg(short, long, default_value = "1")]
+    loops: usize,
+    /// Play consecutive files of an observation as a single transfer
+    #[arg(short = 'S', long)]
+    stitch: bool,
+    /// Files to play, in order
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+}
+
+fn main() {
+    let args = Args::parse();
+    let mut client = match HduClient::connect(args.key) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Couldn't connect to ring {:x}: {e:?}", args.key);
+            std::process::exit(1);
+        }
+    };
+    let mut replayer = Replayer::new(args.files).stitch(args.stitch);
+    replayer = match args.loops {
+        0 => replayer.loop_forever(),
+        n => replayer.loops(n),
+    };
+    if let Some(speed) = args.speed {
+        replayer = replayer.speed(speed);
+    }
+    match replayer.replay(&mut client) {
+        Ok(stats) => println!(
+            "Replayed {} bytes in {} transfers",
+            stats.bytes, stats.transfers
+        ),
+        Err(e) => {
+            eprintln!("Stopping replay: {e:?}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Capture UDP packets into a ring, like `udpdb`
+
+use std::{
+    collections::HashMap, fs, net::SocketAddr, path::PathBuf, sync::atomic::AtomicBool,
+    time::Duration,
+};
+
+use clap::Parser;
+use psrdada::{
+    cli::parse_key,
+    client::HduClient,
+    errors::PsrdadaResult,
+    headers::DadaHeader,
+    udp::{be_counter, le_counter, CaptureStats, UdpCapture},
+};
+
+#[derive(Parser)]
+#[command(about = "Capture UDP packets into a psrdada ring", version)]
+struct Args {
+    /// Key of the ring to write to, in hex
+    #[arg(short, long, value_parser = parse_key, default_value = "dada")]
+    key: i32,
+    /// Header file to write to the ring before the data
+    #[arg(short = 'H', long)]
+    header: PathBuf,
+    /// Address to listen on
+    #[arg(short, long, default_value = "0.0.0.0:4001")]
+    bind: SocketAddr,
+    /// Bytes of payload in every packet
+    #[arg(short, long)]
+    payload_size: usize,
+    /// Bytes of header in every packet, before the payload
+    #[arg(long, default_value = "8")]
+    header_len: usize,
+    /// Where the 64-bit sequence number starts in the packet
+    #[arg(long, default_value = "0")]
+    seq_offset: usize,
+    /// The sequence number is little-endian, rather than big-endian
+    #[arg(long)]
+    little_endian: bool,
+    /// Packets past the end of a block to wait for stragglers
+    #[arg(short, long, default_value = "0")]
+    window: u64,
+    /// Most packets to skip past the latest one, ignoring packets that jump further ahead
+    #[arg(long, default_value = "1048576")]
+    max_jump: u64,
+    /// Seconds without packets to end the capture, rather than running until killed
+    #[arg(short, long)]
+    idle_timeout: Option<f64>,
+}
+
+fn capture<F: FnMut(&[u8]) -> Option<u64>>(
+    args: &Args,
+    sequence: F,
+) -> PsrdadaResult<CaptureStats> {
+    let header = DadaHeader::parse(&fs::read(&args.header)?)?;
+    let mut capture = UdpCapture::bind(args.bind, args.payload_size, sequence)?
+        .header_len(args.header_len)
+        .window(args.window)
+        .max_jump(args.max_jump);
+    if let Some(secs) = args.idle_timeout {
+        capture = capture.idle_timeout(Duration::from_secs_f64(secs));
+    }
+    let mut client = HduClient::connect(args.key)?;
+    let (mut hc, mut dc) = client.split();
+    hc.write_header(&HashMap::from(&header))?;
+    capture.capture(&mut dc, &AtomicBool::new(false))
+}
+
+fn main() {
+    let args = Args::parse();
+    let result = if args.little_endian {
+        capture(&args, le_counter(args.seq_offset))
+    } else {
+        capture(&args, be_counter(args.seq_offset))
+    };
+    match result {
+        Ok(stats) => println!(
+            "Captured {} packets ({} bytes): {} missing ({:.3}%), {} late, {} ignored",
+            stats.packets,
+            stats.bytes,
+            stats.missing,
+            stats.loss() * 100.0,
+            stats.late,
+            stats.ignored
+        ),
+        Err(e) => {
+            eprintln!("Capture failed: {e:?}");
+            std::process::exit(1);
+        }
+    }
+}
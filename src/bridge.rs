@@ -0,0 +1,680 @@
+//! Moving transfers between rings on different hosts over TCP, like `dada_dbnic` and `dada_nicdb`.
+//!
+//! A [`RingSender`] reads transfers from a ring and streams them to a [`RingReceiver`], which writes them into its own ring.
+//! A connection starts with a hello from the sender (`DADANET`, a version byte and a flags byte), after which every transfer
+//! is a header frame, a data frame per block and an end-of-data frame. A frame is a kind byte, a big-endian `u64` length and
+//! that many bytes, followed by the CRC-32 of those bytes if the sender asked for checksums in its hello. The receiver answers
+//! every end-of-data frame with a single byte once the whole transfer is in its ring, so a transfer the sender has finished
+//! is one that made it.
+//!
+//! There's no flow control beyond TCP's own: the receiver only reads the next frame once the last one fits in its ring, so
+//! a full ring on the receiving end fills the socket buffers and blocks the sender, which in turn stops reading its ring.
+//!
+//! Either end can go away between transfers. The receiver goes back to waiting for a connection, and the sender reconnects
+//! (retrying for as long as it's allowed to) before the next transfer. A transfer that's cut off part way through is given
+//! up on by both ends: the receiver ends it early in its ring and returns an error, while the sender reads and drops the
+//! rest of it so its ring is ready for the next one.
+
+use std::{
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    os::unix::io::AsRawFd,
+    thread,
+    time::Duration,
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    client::{DataClient, HduClient, HeaderClient},
+    errors::{PsrdadaError, PsrdadaResult},
+    headers::DadaHeader,
+    io::DadaClient,
+    iter::DadaIterator,
+};
+
+const MAGIC: [u8; 7] = *b"DADANET";
+const VERSION: u8 = 1;
+/// Hello flag for frames followed by their CRC-32
+const CHECKSUMS: u8 = 1;
+
+const HEADER: u8 = b'H';
+const DATA: u8 = b'D';
+const EOD: u8 = b'E';
+/// The receiver's answer to a finished transfer
+const ACK: u8 = b'A';
+
+/// The largest frame a receiver will take, to not trust a garbled length with an allocation
+const MAX_FRAME: u64 = 1 << 30;
+
+/// How long a receiver waits for the hello of a new connection, unless set with [`RingReceiver::hello_timeout`]
+pub const DEFAULT_HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Seconds a connection sits idle before keepalive probes start, the seconds between them, and how many go unanswered
+/// before it's given up on
+const KEEPALIVE: (libc::c_int, libc::c_int, libc::c_int) = (10, 5, 6);
+
+/// Whether an error from [`RingSender::send`] or [`RingReceiver::receive`] came from the network (and is worth carrying on
+/// after), rather than the ring
+pub fn is_network_error(e: &PsrdadaError) -> bool {
+    matches!(
+        e,
+        PsrdadaError::IoError(_)
+            | PsrdadaError::BridgeMalformedFrame
+            | PsrdadaError::BridgeChecksumMismatch
+            | PsrdadaError::BridgeDisconnected
+    )
+}
+
+/// Have the kernel probe a connection that's gone quiet, so a peer that vanished without closing it shows up as an error
+/// rather than a read that never returns
+fn keepalive(stream: &TcpStream) -> io::Result<()> {
+    let fd = stream.as_raw_fd();
+    let set = |level, name, value: libc::c_int| {
+        // Safety: `fd` is an open socket, and `value` is an int of the size we pass
+        let result = unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    };
+    set(libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+    #[cfg(target_os = "linux")]
+    {
+        let (idle, interval, count) = KEEPALIVE;
+        set(libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, idle)?;
+        set(libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, interval)?;
+        set(libc::IPPROTO_TCP, libc::TCP_KEEPCNT, count)?;
+    }
+    Ok(())
+}
+
+fn write_frame(
+    stream: &mut impl Write,
+    kind: u8,
+    payload: &[u8],
+    checksums: bool,
+) -> PsrdadaResult<()> {
+    stream.write_all(&[kind])?;
+    stream.write_all(&(payload.len() as u64).to_be_bytes())?;
+    stream.write_all(payload)?;
+    if checksums {
+        stream.write_all(&crc32fast::hash(payload).to_be_bytes())?;
+    }
+    Ok(())
+}
+
+/// `read_exact`, where running out means the other end went away
+fn read_exact(stream: &mut impl Read, buf: &mut [u8]) -> PsrdadaResult<()> {
+    stream.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => PsrdadaError::BridgeDisconnected,
+        kind => PsrdadaError::IoError(kind),
+    })
+}
+
+/// Read the next frame into `payload`, returning its kind, or `None` if the connection was closed before it started
+fn read_frame(
+    stream: &mut impl BufRead,
+    checksums: bool,
+    payload: &mut Vec<u8>,
+) -> PsrdadaResult<Option<u8>> {
+    if stream.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let mut kind = [0u8];
+    let mut len = [0u8; 8];
+    read_exact(stream, &mut kind)?;
+    read_exact(stream, &mut len)?;
+    let len = u64::from_be_bytes(len);
+    if !matches!(kind[0], HEADER | DATA | EOD) || len > MAX_FRAME {
+        return Err(PsrdadaError::BridgeMalformedFrame);
+    }
+    payload.resize(len as usize, 0);
+    read_exact(stream, payload)?;
+    if checksums {
+        let mut crc = [0u8; 4];
+        read_exact(stream, &mut crc)?;
+        if u32::from_be_bytes(crc) != crc32fast::hash(payload) {
+            return Err(PsrdadaError::BridgeChecksumMismatch);
+        }
+    }
+    Ok(Some(kind[0]))
+}
+
+/// Whether the other end of an idle connection is still there
+fn is_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    // Nothing is sent unprompted, so anything other than having to wait means it's closed (or confused)
+    let open = matches!(stream.peek(&mut [0]), Err(e) if e.kind() == io::ErrorKind::WouldBlock);
+    stream.set_nonblocking(false).is_ok() && open
+}
+
+/// Streams transfers from a ring to a [`RingReceiver`], see the [module docs](self)
+pub struct RingSender {
+    addrs: Vec<SocketAddr>,
+    stream: Option<BufWriter<TcpStream>>,
+    checksums: bool,
+    retry_delay: Duration,
+    max_attempts: Option<usize>,
+}
+
+impl RingSender {
+    /// Send to the receiver at `addr`, connecting when the first transfer starts
+    pub fn new(addr: impl ToSocketAddrs) -> PsrdadaResult<Self> {
+        Ok(Self {
+            addrs: addr.to_socket_addrs()?.collect(),
+            stream: None,
+            checksums: false,
+            retry_delay: Duration::from_secs(1),
+            max_attempts: None,
+        })
+    }
+
+    /// Follow every frame with its CRC-32, for the receiver to check, off by default
+    pub fn checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// How long to wait between attempts to connect, 1 second by default
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Give up on a transfer after this many failed attempts to connect, rather than retrying forever
+    pub fn max_attempts(mut self, attempts: usize) -> Self {
+        self.max_attempts = Some(attempts.max(1));
+        self
+    }
+
+    /// The open connection, or a new one if there isn't one or the receiver closed it
+    fn connection(&mut self) -> PsrdadaResult<BufWriter<TcpStream>> {
+        if let Some(stream) = self.stream.take() {
+            if is_open(stream.get_ref()) {
+                return Ok(stream);
+            }
+            info!("Receiver closed the connection, reconnecting");
+        }
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match TcpStream::connect(&self.addrs[..]) {
+                Ok(stream) => {
+                    keepalive(&stream)?;
+                    let mut stream = BufWriter::new(stream);
+                    let flags = if self.checksums { CHECKSUMS } else { 0 };
+                    stream.write_all(&MAGIC)?;
+                    stream.write_all(&[VERSION, flags])?;
+                    info!(addr = ?stream.get_ref().peer_addr().ok(), "Connected to receiver");
+                    return Ok(stream);
+                }
+                Err(e) if self.max_attempts.map_or(true, |n| attempts < n) => {
+                    warn!(%e, attempts, "Couldn't connect to receiver, retrying");
+                    thread::sleep(self.retry_delay);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Send the next transfer, blocking until its header arrives and returning the number of data bytes once the receiver
+    /// has all of it.
+    ///
+    /// If the receiver can't be reached, or the connection fails part way through, the rest of the transfer is read and
+    /// dropped before returning the error, leaving the ring ready for the next transfer.
+    pub fn send_transfer(
+        &mut self,
+        header_client: &mut HeaderClient,
+        data_client: &mut DataClient,
+    ) -> PsrdadaResult<u64> {
+        let header = header_client.read_dada_header()?.to_bytes();
+        let checksums = self.checksums;
+        let mut stream = self.connection();
+        if let Ok(s) = &mut stream {
+            if let Err(e) = write_frame(s, HEADER, &header, checksums) {
+                stream = Err(e);
+            }
+        }
+        let mut bytes = 0;
+        let mut reader = data_client.reader()?;
+        while let Some(mut block) = reader.next() {
+            if let Ok(s) = &mut stream {
+                let data = block.block();
+                match write_frame(s, DATA, data, checksums) {
+                    Ok(()) => bytes += data.len() as u64,
+                    Err(e) => stream = Err(e),
+                }
+            }
+        }
+        let mut stream = stream.map_err(|e| {
+            warn!(?e, "Dropping the rest of a transfer the receiver can't get");
+            e
+        })?;
+        write_frame(&mut stream, EOD, &[], checksums)?;
+        stream.flush()?;
+        let mut ack = [0u8];
+        read_exact(stream.get_mut(), &mut ack)?;
+        if ack[0] != ACK {
+            return Err(PsrdadaError::BridgeMalformedFrame);
+        }
+        self.stream = Some(stream);
+        Ok(bytes)
+    }
+
+    /// Send the next transfer from a paired client
+    pub fn send(&mut self, client: &mut HduClient) -> PsrdadaResult<u64> {
+        let (mut header_client, mut data_client) = client.split();
+        self.send_transfer(&mut header_client, &mut data_client)
+    }
+}
+
+struct Connection {
+    stream: BufReader<TcpStream>,
+    checksums: bool,
+}
+
+/// The data frames of a transfer, as one stream of bytes that ends at its end-of-data frame, or at the first error (which is
+/// kept for later)
+struct Frames<'a> {
+    connection: &'a mut Connection,
+    payload: Vec<u8>,
+    pos: usize,
+    done: bool,
+    error: Option<PsrdadaError>,
+}
+
+impl BufRead for Frames<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.payload.len() && !self.done {
+            self.pos = 0;
+            let connection = &mut *self.connection;
+            let frame = read_frame(
+                &mut connection.stream,
+                connection.checksums,
+                &mut self.payload,
+            );
+            match frame {
+                Ok(Some(DATA)) => continue,
+                Ok(Some(EOD)) => {}
+                Ok(Some(_)) => self.error = Some(PsrdadaError::BridgeMalformedFrame),
+                Ok(None) => self.error = Some(PsrdadaError::BridgeDisconnected),
+                Err(e) => self.error = Some(e),
+            }
+            self.payload.clear();
+            self.done = true;
+        }
+        Ok(&self.payload[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+impl Read for Frames<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+/// Writes transfers from a [`RingSender`] into a ring, see the [module docs](self)
+pub struct RingReceiver {
+    listener: TcpListener,
+    connection: Option<Connection>,
+    hello_timeout: Duration,
+}
+
+impl RingReceiver {
+    /// Listen for a sender on `addr`
+    pub fn bind(addr: impl ToSocketAddrs) -> PsrdadaResult<Self> {
+        Ok(Self::new(TcpListener::bind(addr)?))
+    }
+
+    /// Listen for a sender on a socket that's already set up
+    pub fn new(listener: TcpListener) -> Self {
+        Self {
+            listener,
+            connection: None,
+            hello_timeout: DEFAULT_HELLO_TIMEOUT,
+        }
+    }
+
+    /// How long to wait for a new connection to say hello before dropping it, [`DEFAULT_HELLO_TIMEOUT`] by default
+    pub fn hello_timeout(mut self, timeout: Duration) -> Self {
+        self.hello_timeout = timeout;
+        self
+    }
+
+    /// The address the socket is bound to
+    pub fn local_addr(&self) -> PsrdadaResult<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Wait for the next sender, returning `None` if what connected wasn't one
+    fn accept(&self) -> PsrdadaResult<Option<Connection>> {
+        let (stream, peer) = self.listener.accept()?;
+        stream.set_read_timeout(Some(self.hello_timeout))?;
+        let mut stream = BufReader::new(stream);
+        let mut hello = [0u8; 9];
+        if stream.read_exact(&mut hello).is_err() || hello[..7] != MAGIC || hello[7] != VERSION {
+            warn!(%peer, "Dropping a connection that didn't say hello");
+            return Ok(None);
+        }
+        stream.get_ref().set_read_timeout(None)?;
+        keepalive(stream.get_ref())?;
+        info!(%peer, "Sender connected");
+        Ok(Some(Connection {
+            stream,
+            checksums: hello[8] & CHECKSUMS != 0,
+        }))
+    }
+
+    /// Write a transfer whose header frame is in `payload`
+    fn write_transfer(
+        connection: &mut Connection,
+        payload: Vec<u8>,
+        header_client: &mut HeaderClient,
+        data_client: &mut DataClient,
+    ) -> PsrdadaResult<u64> {
+        header_client.write_dada_header(&DadaHeader::parse(&payload)?)?;
+        let mut frames = Frames {
+            connection: &mut *connection,
+            payload,
+            pos: 0,
+            done: false,
+            error: None,
+        };
+        // The header is already used, so start at the first data frame
+        frames.payload.clear();
+        let bytes = data_client.writer()?.write_from(&mut frames, |_| ())?;
+        if let Some(e) = frames.error {
+            return Err(e);
+        }
+        connection.stream.get_mut().write_all(&[ACK])?;
+        Ok(bytes)
+    }
+
+    /// Receive the next transfer, waiting for a sender to connect if there isn't one, and returning the number of data bytes
+    /// once it's all in the ring.
+    ///
+    /// If the connection fails part way through, the transfer is ended in the ring with what arrived of it before returning
+    /// the error, and the next call waits for the sender to reconnect.
+    pub fn receive_transfer(
+        &mut self,
+        header_client: &mut HeaderClient,
+        data_client: &mut DataClient,
+    ) -> PsrdadaResult<u64> {
+        let mut payload = vec![];
+        loop {
+            if self.connection.is_none() {
+                self.connection = self.accept()?;
+            }
+            let Some(connection) = self.connection.as_mut() else {
+                continue;
+            };
+            let result =
+                match read_frame(&mut connection.stream, connection.checksums, &mut payload) {
+                    Ok(None) => {
+                        info!("Sender disconnected, waiting for it to come back");
+                        self.connection = None;
+                        continue;
+                    }
+                    Ok(Some(HEADER)) => {
+                        Self::write_transfer(connection, payload, header_client, data_client)
+                    }
+                    Ok(Some(_)) => Err(PsrdadaError::BridgeMalformedFrame),
+                    Err(e) => Err(e),
+                };
+            if result.is_err() {
+                self.connection = None;
+            }
+            return result;
+        }
+    }
+
+    /// Receive the next transfer into a paired client
+    pub fn receive(&mut self, client: &mut HduClient) -> PsrdadaResult<u64> {
+        let (mut header_client, mut data_client) = client.split();
+        self.receive_transfer(&mut header_client, &mut data_client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, thread::JoinHandle};
+
+    use test_log::test;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, tests::next_key};
+
+    fn header(n: usize) -> HashMap<String, String> {
+        HashMap::from([("TRANSFER".to_owned(), n.to_string())])
+    }
+
+    fn data(n: usize, len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + n) as u8).collect()
+    }
+
+    /// Write transfers of `len` bytes into a ring
+    fn write_transfers(client: &mut HduClient, transfers: std::ops::Range<usize>, len: usize) {
+        let (mut hc, mut dc) = client.split();
+        for n in transfers {
+            hc.write_header(&header(n)).unwrap();
+            let mut writer = dc.writer().unwrap();
+            writer.write_from(&mut &data(n, len)[..], |_| ()).unwrap();
+        }
+    }
+
+    type Transfer = (HashMap<String, String>, Vec<u8>);
+
+    /// Read `count` transfers from a ring on another thread
+    fn read_transfers(key: i32, count: usize) -> JoinHandle<Vec<Transfer>> {
+        thread::spawn(move || {
+            let mut client = HduClient::connect(key).unwrap();
+            let (mut hc, mut dc) = client.split();
+            (0..count)
+                .map(|_| {
+                    let header = hc.read_header().unwrap();
+                    let mut data = vec![];
+                    let mut reader = dc.reader().unwrap();
+                    while let Some(mut block) = reader.next() {
+                        data.extend_from_slice(block.block());
+                    }
+                    (header, data)
+                })
+                .collect()
+        })
+    }
+
+    /// Receive `count` transfers into a ring on another thread
+    fn receive_transfers(
+        mut receiver: RingReceiver,
+        key: i32,
+        count: usize,
+    ) -> JoinHandle<Vec<PsrdadaResult<u64>>> {
+        thread::spawn(move || {
+            let mut client = HduClient::connect(key).unwrap();
+            (0..count).map(|_| receiver.receive(&mut client)).collect()
+        })
+    }
+
+    #[test]
+    fn test_bridge() {
+        let mut source = DadaClientBuilder::new(next_key())
+            .num_bufs(8)
+            .buf_size(64)
+            .build()
+            .unwrap();
+        // Smaller blocks and fewer of them than the source, so the receiver has to wait for the reader
+        let dest_key = next_key();
+        let _dest = DadaClientBuilder::new(dest_key)
+            .num_bufs(2)
+            .buf_size(48)
+            .build()
+            .unwrap();
+        write_transfers(&mut source, 0..2, 200);
+
+        let reader = read_transfers(dest_key, 2);
+        let receiver = RingReceiver::bind("127.0.0.1:0").unwrap();
+        let mut sender = RingSender::new(receiver.local_addr().unwrap())
+            .unwrap()
+            .checksums(true);
+        let receiver = receive_transfers(receiver, dest_key, 2);
+        for _ in 0..2 {
+            assert_eq!(sender.send(&mut source).unwrap(), 200);
+        }
+        let received = receiver.join().unwrap();
+        assert_eq!(received, vec![Ok(200), Ok(200)]);
+        for (n, (header, bytes)) in reader.join().unwrap().into_iter().enumerate() {
+            assert_eq!(header["TRANSFER"], n.to_string());
+            assert_eq!(bytes, data(n, 200));
+        }
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let mut source = DadaClientBuilder::new(next_key())
+            .num_bufs(8)
+            .buf_size(64)
+            .build()
+            .unwrap();
+        let dest_key = next_key();
+        let _dest = DadaClientBuilder::new(dest_key)
+            .num_bufs(8)
+            .buf_size(64)
+            .build()
+            .unwrap();
+        write_transfers(&mut source, 0..3, 100);
+        let reader = read_transfers(dest_key, 3);
+
+        // The receiver goes away after the first transfer and comes back on the same address
+        let receiver = RingReceiver::bind("127.0.0.1:0").unwrap();
+        let addr = receiver.local_addr().unwrap();
+        let mut sender = RingSender::new(addr).unwrap();
+        let first = receive_transfers(receiver, dest_key, 1);
+        assert_eq!(sender.send(&mut source), Ok(100));
+        assert_eq!(first.join().unwrap(), vec![Ok(100)]);
+        let second = receive_transfers(RingReceiver::bind(addr).unwrap(), dest_key, 2);
+        assert_eq!(sender.send(&mut source), Ok(100));
+
+        // Then the sender is replaced by a new one
+        drop(sender);
+        let mut sender = RingSender::new(addr).unwrap();
+        assert_eq!(sender.send(&mut source), Ok(100));
+        assert_eq!(second.join().unwrap(), vec![Ok(100), Ok(100)]);
+
+        let transfers = reader.join().unwrap();
+        assert_eq!(transfers.len(), 3);
+        for (n, (header, bytes)) in transfers.into_iter().enumerate() {
+            assert_eq!(header["TRANSFER"], n.to_string());
+            assert_eq!(bytes, data(n, 100));
+        }
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let dest_key = next_key();
+        let _dest = DadaClientBuilder::new(dest_key)
+            .num_bufs(4)
+            .buf_size(64)
+            .build()
+            .unwrap();
+        let reader = read_transfers(dest_key, 1);
+        let receiver = RingReceiver::bind("127.0.0.1:0").unwrap();
+        let addr = receiver.local_addr().unwrap();
+        let receiver = receive_transfers(receiver, dest_key, 1);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&MAGIC).unwrap();
+        stream.write_all(&[VERSION, CHECKSUMS]).unwrap();
+        let header: DadaHeader = header(0).into_iter().collect();
+        write_frame(&mut stream, HEADER, &header.to_bytes(), true).unwrap();
+        write_frame(&mut stream, DATA, &[1; 64], true).unwrap();
+        // A frame with the right checksum for the wrong data
+        let mut frame = vec![];
+        write_frame(&mut frame, DATA, &[2; 64], true).unwrap();
+        frame[9] = 3;
+        stream.write_all(&frame).unwrap();
+
+        assert_eq!(receiver.join().unwrap(), vec![Err(
+            PsrdadaError::BridgeChecksumMismatch
+        )]);
+        // The transfer ends with what arrived intact, and the connection is dropped
+        let transfers = reader.join().unwrap();
+        assert_eq!(transfers[0].0["TRANSFER"], "0");
+        assert_eq!(transfers[0].1, vec![1; 64]);
+        assert_eq!(stream.read(&mut [0]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_silent_connection() {
+        let mut source = DadaClientBuilder::new(next_key())
+            .num_bufs(4)
+            .buf_size(64)
+            .build()
+            .unwrap();
+        let dest_key = next_key();
+        let _dest = DadaClientBuilder::new(dest_key)
+            .num_bufs(4)
+            .buf_size(64)
+            .build()
+            .unwrap();
+        write_transfers(&mut source, 0..1, 100);
+        let reader = read_transfers(dest_key, 1);
+        let receiver = RingReceiver::bind("127.0.0.1:0")
+            .unwrap()
+            .hello_timeout(Duration::from_millis(50));
+        let addr = receiver.local_addr().unwrap();
+
+        // Something connects and never says anything, which mustn't hold up the sender behind it
+        let _probe = TcpStream::connect(addr).unwrap();
+        let receiver = receive_transfers(receiver, dest_key, 1);
+        let mut sender = RingSender::new(addr).unwrap();
+        assert_eq!(sender.send(&mut source), Ok(100));
+        assert_eq!(receiver.join().unwrap(), vec![Ok(100)]);
+        assert_eq!(reader.join().unwrap()[0].1, data(0, 100));
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let mut source = DadaClientBuilder::new(next_key())
+            .num_bufs(4)
+            .buf_size(64)
+            .build()
+            .unwrap();
+        write_transfers(&mut source, 0..2, 100);
+        // Find an address with nothing listening on it
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut sender = RingSender::new(addr)
+            .unwrap()
+            .retry_delay(Duration::from_millis(1))
+            .max_attempts(2);
+        assert_eq!(
+            sender.send(&mut source),
+            Err(PsrdadaError::IoError(io::ErrorKind::ConnectionRefused))
+        );
+        // The failed transfer was dropped, so the next one is up
+        let (mut hc, _) = source.split();
+        assert_eq!(hc.read_header().unwrap()["TRANSFER"], "1");
+    }
+}
@@ -178,7 +178,7 @@ impl DadaClientBuilder {
 
         // Now we construct our client with these buffers we created
         // Safety: We just constructed these pointers and haven't shared them
-        let client = unsafe { HduClient::build(data, header) }?;
+        let client = unsafe { HduClient::build(self.key, data, header) }?;
         // Return built result
         Ok(client)
     }
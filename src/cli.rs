@@ -0,0 +1,20 @@
+//! Helpers shared by the command line tools.
+
+/// Parse a ring key given in hex, with or without a leading `0x`, as the C tools take them
+pub fn parse_key(s: &str) -> Result<i32, String> {
+    i32::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key("dada"), Ok(0xdada));
+        assert_eq!(parse_key("0xb0ba"), Ok(0xb0ba));
+        assert!(parse_key("ring").is_err());
+    }
+}
@@ -11,6 +11,7 @@ use crate::errors::{PsrdadaError, PsrdadaResult};
 /// The struct that stores the Header + Data ringbuffers
 pub struct HduClient {
     allocated: bool,
+    key: i32,
     pub(crate) data_buf: *const ipcbuf_t,
     pub(crate) header_buf: *const ipcbuf_t,
 }
@@ -24,13 +25,14 @@ pub struct HeaderClient<'a> {
 /// Client for working with the data ringbuffer
 pub struct DataClient<'a> {
     pub(crate) buf: *const ipcbuf_t,
+    pub(crate) key: i32,
     _phantom: PhantomData<&'a ipcbuf_t>,
 }
 
 // Splitting borrows
 impl HduClient {
     /// Split the DadaClient into header and data clients
-    pub fn split(&mut self) -> (HeaderClient, DataClient) {
+    pub fn split(&mut self) -> (HeaderClient<'_>, DataClient<'_>) {
         (
             HeaderClient {
                 buf: self.header_buf,
@@ -38,6 +40,7 @@ impl HduClient {
             },
             DataClient {
                 buf: self.data_buf,
+                key: self.key,
                 _phantom: PhantomData,
             },
         )
@@ -51,6 +54,7 @@ impl HduClient {
     /// The pointers passed to this build function must *not* be shared with anything else.
     /// Additionally, these pointers *must* come from Box::into_raw
     pub(crate) unsafe fn build(
+        key: i32,
         data_buf: *mut ipcbuf_t,
         header_buf: *mut ipcbuf_t,
     ) -> PsrdadaResult<Self> {
@@ -58,6 +62,7 @@ impl HduClient {
             data_buf: data_buf as *const _,
             header_buf: header_buf as *const _,
             allocated: true,
+            key,
         };
         // Clear our state, just to make sure
         s.reset()?;
@@ -72,6 +77,7 @@ impl HduClient {
             data_buf,
             header_buf,
             allocated: false,
+            key,
         };
         Ok(s)
     }
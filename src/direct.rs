@@ -0,0 +1,474 @@
+//! Writing `.dada` files with direct I/O, for streams too fast for the page cache.
+//!
+//! A [`DirectFileWriter`] opens its file with `O_DIRECT`, so data goes from memory straight to the disk instead of being copied
+//! into (and competing for) the page cache. Direct I/O needs the memory, length and file offset of every write to be aligned to
+//! [`DIRECT_ALIGN`]. Ring blocks are page aligned, so when their size is a multiple of [`DIRECT_ALIGN`] (and `HDR_SIZE` is too)
+//! they're written in place with no copies at all. Anything unaligned goes through an aligned staging buffer instead.
+//!
+//! Blocks are handed over by value with [`DirectFileWriter::write_block`], which only returns (and drops the block, which for a
+//! [`ReadBlock`](crate::io::ReadBlock) marks it cleared) once the write has completed, so the writer upstream can't reuse the
+//! memory while the disk is still reading from it.
+//!
+//! With the `io-uring` feature, [`DirectFileWriter::io_uring`] submits each block as many writes that are in flight together,
+//! instead of one `pwrite` at a time.
+
+use std::{
+    alloc::{self, Layout},
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind},
+    ops::{Deref, DerefMut},
+    os::unix::fs::{FileExt, OpenOptionsExt},
+    path::Path,
+    ptr::NonNull,
+    slice,
+};
+
+#[cfg(feature = "io-uring")]
+use io_uring::IoUring;
+use tracing::warn;
+
+use crate::{
+    client::DataClient,
+    errors::PsrdadaResult,
+    file::DadaFileWriter,
+    headers::DadaHeader,
+    io::{read::ReadableBlock, DadaClient},
+    iter::DadaIterator,
+};
+
+/// The alignment of memory, lengths and offsets for direct I/O, which covers the logical block size of any common disk
+pub const DIRECT_ALIGN: usize = 4096;
+
+/// The size of the staging buffer, and of the pieces blocks are split into for io_uring
+pub const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
+
+/// Round up to a multiple of [`DIRECT_ALIGN`]
+fn align_up(n: usize) -> usize {
+    (n + DIRECT_ALIGN - 1) / DIRECT_ALIGN * DIRECT_ALIGN
+}
+
+/// A zeroed buffer whose start is aligned to [`DIRECT_ALIGN`]
+pub struct AlignedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// Safety: We own the allocation, just like a `Vec`
+unsafe impl Send for AlignedBuf {}
+unsafe impl Sync for AlignedBuf {}
+
+impl AlignedBuf {
+    /// Allocate a zeroed buffer of `len` bytes
+    pub fn new(len: usize) -> Self {
+        let ptr = if len == 0 {
+            // Dangling, but aligned, as for an empty `Vec`
+            NonNull::new(DIRECT_ALIGN as *mut u8).unwrap()
+        } else {
+            // Safety: The layout isn't zero sized
+            let ptr = unsafe { alloc::alloc_zeroed(Self::layout(len)) };
+            NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(Self::layout(len)))
+        };
+        Self { ptr, len }
+    }
+
+    fn layout(len: usize) -> Layout {
+        Layout::from_size_align(len, DIRECT_ALIGN).expect("Buffer too large")
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // Safety: We allocated this with the same layout
+            unsafe { alloc::dealloc(self.ptr.as_ptr(), Self::layout(self.len)) }
+        }
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // Safety: We own `len` initialized bytes at `ptr`
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // Safety: We own `len` initialized bytes at `ptr`
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+/// Write `buf` at `offset` with io_uring, split into `chunk` sized writes that are all in flight at once (up to the queue depth)
+#[cfg(feature = "io-uring")]
+fn write_all_uring(
+    ring: &mut IoUring,
+    file: &File,
+    buf: &[u8],
+    offset: u64,
+    chunk: usize,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    use io_uring::{opcode, types};
+
+    let fd = types::Fd(file.as_raw_fd());
+    let chunks: Vec<_> = buf.chunks(chunk).collect();
+    let depth = ring.params().sq_entries() as usize;
+    let (mut next, mut in_flight) = (0, 0);
+    let mut short = vec![];
+    let mut error = None;
+    while in_flight > 0 || (next < chunks.len() && error.is_none()) {
+        while next < chunks.len() && in_flight < depth && error.is_none() {
+            let data = chunks[next];
+            let entry = opcode::Write::new(fd, data.as_ptr(), data.len() as u32)
+                .offset(offset + (next * chunk) as u64)
+                .build()
+                .user_data(next as u64);
+            // Safety: `buf` outlives the write, as we wait for every submitted write to complete before returning
+            if unsafe { ring.submission().push(&entry) }.is_err() {
+                break;
+            }
+            next += 1;
+            in_flight += 1;
+        }
+        match ring.submit_and_wait(1) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            // Nothing more can be submitted, but the writes in flight still have to finish before `buf` can go
+            Err(e) if in_flight > 0 => {
+                error.get_or_insert(e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+        for cqe in ring.completion() {
+            in_flight -= 1;
+            let index = cqe.user_data() as usize;
+            match cqe.result() {
+                res if res < 0 => {
+                    error.get_or_insert(io::Error::from_raw_os_error(-res));
+                }
+                res if (res as usize) < chunks[index].len() => short.push((index, res as usize)),
+                _ => (),
+            }
+        }
+    }
+    if let Some(e) = error {
+        return Err(e);
+    }
+    // Short writes are rare enough to finish off one at a time
+    for (index, written) in short {
+        let pos = offset + (index * chunk + written) as u64;
+        file.write_all_at(&chunks[index][written..], pos)?;
+    }
+    Ok(())
+}
+
+/// Writes a `.dada` file with `O_DIRECT`, see the [module docs](self)
+pub struct DirectFileWriter {
+    file: File,
+    header: DadaHeader,
+    staging: AlignedBuf,
+    staged: usize,
+    /// The offset of the next write to the file, always aligned
+    offset: u64,
+    /// The number of data bytes written, not counting the header
+    bytes_written: u64,
+    hdr_size: u64,
+    chunk_size: usize,
+    #[cfg(feature = "io-uring")]
+    ring: Option<IoUring>,
+}
+
+impl DirectFileWriter {
+    /// Create (or truncate) a file at `path` and write the header to it, padded with zeros to `HDR_SIZE`.
+    ///
+    /// If the filesystem doesn't support `O_DIRECT` (tmpfs, for one), the file is opened normally, with a warning. The header is
+    /// checked like [`DadaFileWriter::new`] does.
+    pub fn create(path: impl AsRef<Path>, header: DadaHeader) -> PsrdadaResult<Self> {
+        let path = path.as_ref();
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        let file = match options.clone().custom_flags(libc::O_DIRECT).open(path) {
+            Err(e) if e.kind() == ErrorKind::InvalidInput => {
+                warn!(
+                    ?path,
+                    "Filesystem doesn't support O_DIRECT, writing through the page cache"
+                );
+                options.open(path)?
+            }
+            file => file?,
+        };
+        // Let the plain writer check and pad the header for us
+        let header_writer = DadaFileWriter::new(vec![], header)?;
+        let header = header_writer.header().clone();
+        let header_bytes = header_writer.finish()?;
+        if header_bytes.len() % DIRECT_ALIGN != 0 {
+            warn!(
+                hdr_size = header_bytes.len(),
+                "HDR_SIZE isn't aligned for direct I/O, so every block will be copied"
+            );
+        }
+        let mut writer = Self {
+            file,
+            header,
+            staging: AlignedBuf::new(DEFAULT_CHUNK_SIZE),
+            staged: 0,
+            offset: 0,
+            bytes_written: 0,
+            hdr_size: header_bytes.len() as u64,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            #[cfg(feature = "io-uring")]
+            ring: None,
+        };
+        writer.write_all(&header_bytes)?;
+        writer.bytes_written = 0;
+        Ok(writer)
+    }
+
+    /// Set the size of the staging buffer and of the writes submitted to io_uring, rounded up to a multiple of [`DIRECT_ALIGN`]
+    pub fn chunk_size(mut self, bytes: usize) -> Self {
+        let bytes = align_up(bytes.max(1));
+        // Anything already staged has to fit, with room to pad it out to the alignment at the end
+        let mut staging = AlignedBuf::new(align_up(bytes.max(self.staged)));
+        staging[..self.staged].copy_from_slice(&self.staging[..self.staged]);
+        self.staging = staging;
+        self.chunk_size = bytes;
+        self
+    }
+
+    /// Write with io_uring, with up to `queue_depth` writes in flight at once
+    #[cfg(feature = "io-uring")]
+    pub fn io_uring(mut self, queue_depth: u32) -> PsrdadaResult<Self> {
+        self.ring = Some(IoUring::new(queue_depth)?);
+        Ok(self)
+    }
+
+    /// The header of this file, as written
+    pub fn header(&self) -> &DadaHeader {
+        &self.header
+    }
+
+    /// The number of data bytes written so far, not counting the header
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Write an aligned buffer at the current offset, returning once it's on its way to the disk
+    fn write_aligned(&mut self, buf: &[u8]) -> io::Result<()> {
+        #[cfg(feature = "io-uring")]
+        if let Some(ring) = self.ring.as_mut() {
+            write_all_uring(ring, &self.file, buf, self.offset, self.chunk_size)?;
+            self.offset += buf.len() as u64;
+            return Ok(());
+        }
+        self.file.write_all_at(buf, self.offset)?;
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+
+    fn flush_staging(&mut self) -> io::Result<()> {
+        let staging = std::mem::replace(&mut self.staging, AlignedBuf::new(0));
+        let result = self.write_aligned(&staging[..self.staged]);
+        self.staging = staging;
+        self.staged = 0;
+        result
+    }
+
+    /// Write data, in place if it's aligned and through the staging buffer otherwise.
+    ///
+    /// Returns once everything that can be written (all but the unaligned end) has been.
+    pub fn write_all(&mut self, mut data: &[u8]) -> PsrdadaResult<()> {
+        self.bytes_written += data.len() as u64;
+        while !data.is_empty() {
+            let aligned = data.as_ptr() as usize % DIRECT_ALIGN == 0 && data.len() >= DIRECT_ALIGN;
+            if aligned && self.staged % DIRECT_ALIGN == 0 {
+                // Whatever's staged lines up with the disk, so it can go first and the data straight after it
+                if self.staged > 0 {
+                    self.flush_staging()?;
+                }
+                let n = data.len() - data.len() % DIRECT_ALIGN;
+                self.write_aligned(&data[..n])?;
+                data = &data[n..];
+            } else {
+                let n = (self.staging.len() - self.staged).min(data.len());
+                self.staging[self.staged..self.staged + n].copy_from_slice(&data[..n]);
+                self.staged += n;
+                data = &data[n..];
+                if self.staged == self.staging.len() {
+                    self.flush_staging()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a whole block, only dropping it (and so clearing a ring block) once the write has completed
+    pub fn write_block<B: ReadableBlock>(&mut self, mut block: B) -> PsrdadaResult<()> {
+        self.write_all(block.block())
+    }
+
+    /// Write every block of the current transfer of a data ring, until the end of data, returning the number of bytes written
+    pub fn write_transfer(&mut self, data_client: &mut DataClient) -> PsrdadaResult<u64> {
+        let start = self.bytes_written;
+        let mut reader = data_client.reader()?;
+        while let Some(block) = reader.next() {
+            self.write_block(block)?;
+        }
+        Ok(self.bytes_written - start)
+    }
+
+    /// Write out what's left in the staging buffer and get back the file.
+    ///
+    /// The last write is padded out to [`DIRECT_ALIGN`], then the padding is cut off again.
+    pub fn finish(mut self) -> PsrdadaResult<File> {
+        if self.staged > 0 {
+            let padded = align_up(self.staged);
+            self.staging[self.staged..padded].fill(0);
+            self.staged = padded;
+            self.flush_staging()?;
+        }
+        self.file.set_len(self.hdr_size + self.bytes_written)?;
+        Ok(self.file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use test_log::test;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, file::DadaFileReader, tests::next_key};
+
+    fn header() -> DadaHeader {
+        DadaHeader::parse(b"UTC_START 2023-05-01-12:34:56\nOBS_OFFSET 0\n").unwrap()
+    }
+
+    fn read_data(path: &Path) -> Vec<u8> {
+        let mut reader = DadaFileReader::open(path).unwrap();
+        assert_eq!(reader.hdr_size(), 4096);
+        let mut data = vec![];
+        io::Read::read_to_end(&mut reader, &mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_aligned_buf() {
+        for len in [0, 1, 4096, 10000] {
+            let buf = AlignedBuf::new(len);
+            assert_eq!(buf.len(), len);
+            assert_eq!(buf.as_ptr() as usize % DIRECT_ALIGN, 0);
+            assert!(buf.iter().all(|b| *b == 0));
+        }
+    }
+
+    #[test]
+    fn test_aligned_and_unaligned() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("direct.dada");
+        let mut writer = DirectFileWriter::create(&path, header())
+            .unwrap()
+            .chunk_size(8192);
+        let mut aligned = AlignedBuf::new(3 * DIRECT_ALIGN);
+        aligned
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, b)| *b = i as u8);
+        let unaligned: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+
+        writer.write_all(&aligned).unwrap();
+        writer.write_all(&unaligned).unwrap();
+        // Once the staging buffer is out of step with the alignment, aligned data has to go through it too
+        writer.write_all(&aligned).unwrap();
+        assert_eq!(
+            writer.bytes_written() as usize,
+            2 * aligned.len() + unaligned.len()
+        );
+        let file = writer.finish().unwrap();
+        assert_eq!(
+            file.metadata().unwrap().len() as usize,
+            4096 + 2 * aligned.len() + unaligned.len()
+        );
+
+        let expected = [&aligned[..], &unaligned, &aligned].concat();
+        assert_eq!(read_data(&path), expected);
+    }
+
+    #[test]
+    fn test_shrink_chunk_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shrink.dada");
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let mut writer = DirectFileWriter::create(&path, header()).unwrap();
+        writer.write_all(&data).unwrap();
+        // More is staged than the new chunk size, and it doesn't end on the alignment
+        let file = writer.chunk_size(DIRECT_ALIGN).finish().unwrap();
+        assert_eq!(file.metadata().unwrap().len() as usize, 4096 + data.len());
+        assert_eq!(read_data(&path), data);
+    }
+
+    #[test]
+    fn test_write_transfer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ring.dada");
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_bufs(4)
+            .buf_size(2 * DIRECT_ALIGN as u64)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+        {
+            let mut writer = dc.writer().unwrap();
+            for i in 0..3u8 {
+                let mut block = writer.next().unwrap();
+                block.write_all(&[i; 2 * DIRECT_ALIGN]).unwrap();
+                if i == 2 {
+                    block.mark_eod();
+                }
+            }
+        }
+        let mut writer = DirectFileWriter::create(&path, header()).unwrap();
+        assert_eq!(
+            writer.write_transfer(&mut dc).unwrap(),
+            6 * DIRECT_ALIGN as u64
+        );
+        writer.finish().unwrap();
+
+        let data = read_data(&path);
+        assert_eq!(data.len(), 6 * DIRECT_ALIGN);
+        assert!(data
+            .chunks(2 * DIRECT_ALIGN)
+            .enumerate()
+            .all(|(i, c)| c.iter().all(|b| *b == i as u8)));
+    }
+
+    #[cfg(feature = "io-uring")]
+    #[test]
+    fn test_io_uring() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("uring.dada");
+        let writer = DirectFileWriter::create(&path, header())
+            .unwrap()
+            .chunk_size(DIRECT_ALIGN)
+            .io_uring(4);
+        // io_uring is often turned off in containers
+        let Ok(mut writer) = writer else {
+            warn!("io_uring isn't available, skipping");
+            return;
+        };
+        let mut block = AlignedBuf::new(10 * DIRECT_ALIGN);
+        block
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, b)| *b = (i / DIRECT_ALIGN) as u8);
+        writer.write_all(&block).unwrap();
+        writer.write_all(&[42; 100]).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(read_data(&path), [&block[..], &[42; 100]].concat());
+    }
+}
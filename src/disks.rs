@@ -0,0 +1,178 @@
+//! Sets of disks to record to, like `disk_array` of the C library.
+//!
+//! A [`DiskSet`] is a list of directories, usually each on a volume of its own. Before a recorder opens a file it asks the set
+//! for a disk with room for the whole file, picked by a [`DiskPolicy`]. Disks that can't be queried (not mounted, say) are
+//! skipped rather than failing the recording.
+
+use std::{
+    cell::Cell,
+    ffi::CString,
+    io,
+    mem::MaybeUninit,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+
+/// How a [`DiskSet`] picks the next disk
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DiskPolicy {
+    /// The disk with the most free space
+    MostFree,
+    /// Each disk in turn, skipping those without room
+    RoundRobin,
+}
+
+/// A set of directories to spread recordings over
+#[derive(Debug, Clone)]
+pub struct DiskSet {
+    disks: Vec<PathBuf>,
+    policy: DiskPolicy,
+    reserve: u64,
+    next: Cell<usize>,
+    free_space: fn(&Path) -> io::Result<u64>,
+}
+
+/// The space available to unprivileged users on the filesystem holding `path`
+fn available_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // Safety: `path` is NUL terminated and `stat` is big enough for statvfs to fill in
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safety: statvfs succeeded, so it filled in `stat`
+    let stat = unsafe { stat.assume_init() };
+    // The widths of these fields depend on the platform
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+impl DiskSet {
+    /// Create a set of these directories, picking the one with the most free space by default
+    pub fn new<P: Into<PathBuf>>(disks: impl IntoIterator<Item = P>) -> Self {
+        Self {
+            disks: disks.into_iter().map(Into::into).collect(),
+            policy: DiskPolicy::MostFree,
+            reserve: 0,
+            next: Cell::new(0),
+            free_space: available_space,
+        }
+    }
+
+    /// Set how the next disk is picked
+    pub fn policy(mut self, policy: DiskPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Leave at least this many bytes free on every disk
+    pub fn reserve(mut self, bytes: u64) -> Self {
+        self.reserve = bytes;
+        self
+    }
+
+    /// Use something other than `statvfs` to find the free space of a disk
+    #[cfg(test)]
+    pub(crate) fn free_space_with(mut self, free_space: fn(&Path) -> io::Result<u64>) -> Self {
+        self.free_space = free_space;
+        self
+    }
+
+    /// The directories in the set
+    pub fn disks(&self) -> &[PathBuf] {
+        &self.disks
+    }
+
+    /// The free space on a disk, less the reserve, or `None` if it couldn't be found
+    pub fn free_space(&self, disk: &Path) -> Option<u64> {
+        match (self.free_space)(disk) {
+            Ok(free) => Some(free.saturating_sub(self.reserve)),
+            Err(e) => {
+                warn!(?disk, %e, "Couldn't get the free space of a disk, skipping it");
+                None
+            }
+        }
+    }
+
+    /// Pick a disk with room for `needed` bytes, ignoring those in `exclude`.
+    ///
+    /// Returns `None` if every disk is full (or excluded).
+    pub fn select(&self, needed: u64, exclude: &[PathBuf]) -> Option<PathBuf> {
+        let n = self.disks.len();
+        let mut candidates = (0..n)
+            .map(|i| match self.policy {
+                DiskPolicy::MostFree => i,
+                DiskPolicy::RoundRobin => (self.next.get() + i) % n,
+            })
+            .filter(|&i| !exclude.contains(&self.disks[i]))
+            .filter_map(|i| Some((i, self.free_space(&self.disks[i])?)))
+            .filter(|(_, free)| *free >= needed);
+        let picked = match self.policy {
+            // Ties go to the disk listed first
+            DiskPolicy::MostFree => {
+                candidates.fold(None, |best: Option<(usize, u64)>, disk| match best {
+                    Some(best) if best.1 >= disk.1 => Some(best),
+                    _ => Some(disk),
+                })
+            }
+            DiskPolicy::RoundRobin => candidates.next(),
+        };
+        let (i, _) = picked?;
+        self.next.set((i + 1) % n);
+        Some(self.disks[i].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+
+    /// Pretend the disk named `n` has `n` kilobytes free
+    fn fake_free(disk: &Path) -> io::Result<u64> {
+        let name = disk.file_name().unwrap().to_str().unwrap();
+        name.parse::<u64>()
+            .map(|kb| kb * 1024)
+            .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "no such disk"))
+    }
+
+    #[test]
+    fn test_most_free() {
+        let disks = DiskSet::new(["/1", "/8", "/4", "/8"]).free_space_with(fake_free);
+        assert_eq!(disks.select(0, &[]), Some(PathBuf::from("/8")));
+        assert_eq!(
+            disks.select(0, &[PathBuf::from("/8")]),
+            Some(PathBuf::from("/4"))
+        );
+        assert_eq!(disks.select(5 * 1024, &[]), Some(PathBuf::from("/8")));
+        assert_eq!(disks.select(9 * 1024, &[]), None);
+        // The reserve comes off every disk
+        let disks = disks.reserve(4 * 1024);
+        assert_eq!(disks.free_space(Path::new("/4")), Some(0));
+        assert_eq!(disks.select(5 * 1024, &[]), None);
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let disks = DiskSet::new(["/1", "/missing", "/8", "/4"])
+            .policy(DiskPolicy::RoundRobin)
+            .free_space_with(fake_free);
+        let picks: Vec<_> = (0..4).map(|_| disks.select(0, &[]).unwrap()).collect();
+        assert_eq!(picks, ["/1", "/8", "/4", "/1"].map(PathBuf::from));
+        // Disks without room are skipped, and the rotation carries on after the one picked
+        assert_eq!(disks.select(2 * 1024, &[]), Some(PathBuf::from("/8")));
+        assert_eq!(disks.select(2 * 1024, &[]), Some(PathBuf::from("/4")));
+        assert_eq!(disks.select(2 * 1024, &[]), Some(PathBuf::from("/8")));
+    }
+
+    #[test]
+    fn test_statvfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let disks = DiskSet::new([dir.path()]);
+        assert!(disks.free_space(dir.path()).unwrap() > 0);
+        assert_eq!(disks.free_space(&dir.path().join("missing")), None);
+    }
+}
@@ -1,5 +1,7 @@
 //! Error types for this crate
 
+use crate::headers::ParseDiagnostic;
+
 #[derive(Debug, PartialEq, Eq)]
 /// All the errors we can return
 pub enum PsrdadaError {
@@ -15,10 +17,52 @@ pub enum PsrdadaError {
     DadaWriteError,
     DadaShmemLockError,
     UTF8Error,
-    HeaderOverflow,
-    HeaderParseError,
+    HeaderOverflow {
+        header_size: usize,
+        buffer_size: usize,
+    },
+    HeaderParseError(ParseDiagnostic),
+    HdrSizeError {
+        hdr_size: usize,
+        header_size: usize,
+        buffer_size: usize,
+    },
     HeaderEodError,
+    HeaderDuplicateKey(String),
+    HeaderInvalidEntry {
+        key: String,
+        value: String,
+    },
+    HeaderValueError {
+        key: String,
+        value: String,
+    },
+    HeaderMissingKey(String),
+    TimeParseError,
+    AngleParseError,
+    HeaderSerdeError(String),
+    TemplateUndefinedVariable(String),
+    TemplateIncludeCycle(String),
+    SigprocMalformedHeader,
+    SigprocUnknownKey(String),
+    GuppiMalformedHeader,
+    VdifMalformedFrame,
+    UdpPacketSize {
+        payload_size: usize,
+        block_size: usize,
+    },
+    BridgeMalformedFrame,
+    BridgeChecksumMismatch,
+    BridgeDisconnected,
+    PwcCommandError(String),
+    IoError(std::io::ErrorKind),
     GpuError,
 }
 
 pub type PsrdadaResult<T> = Result<T, PsrdadaError>;
+
+impl From<std::io::Error> for PsrdadaError {
+    fn from(e: std::io::Error) -> Self {
+        PsrdadaError::IoError(e.kind())
+    }
+}
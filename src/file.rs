@@ -0,0 +1,462 @@
+//! Reading and writing `.dada` files.
+//!
+//! A `.dada` file is what `dada_dbdisk` writes to disk: the header, padded with zeros to `HDR_SIZE` bytes, followed by the raw data
+//! of (part of) a transfer. Long observations are split over several files, each with a copy of the header where `FILE_NUMBER`
+//! counts the files and `OBS_OFFSET` is the offset of the file's first data byte from the start of the observation.
+//!
+//! [`DadaFileReader`] lends out the data in blocks through [`DadaIterator`], just like a [`Reader`](crate::io::Reader) of a ring,
+//! and both kinds of block implement [`ReadableBlock`], so processing can be written once and run from either.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Chain, Cursor, Read, Write},
+    path::Path,
+};
+
+use tracing::error;
+
+use crate::{
+    errors::{PsrdadaError, PsrdadaResult},
+    headers::{bytes_to_header_lenient, DadaHeader},
+    io::{read::ReadableBlock, BlockPosition},
+    iter::{DadaIterator, DadaIteratorItem},
+};
+
+/// The header size used by the C library when a header doesn't say otherwise
+pub const DEFAULT_HDR_SIZE: usize = 4096;
+
+/// The size of the blocks [`DadaFileReader`] lends out, unless set with [`DadaFileReader::block_size`]
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 20;
+
+/// Read until `buf` is full or we hit the end of the file, returning how many bytes were read
+pub(crate) fn read_up_to(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Append exactly `len` bytes from `reader` to `buf`, failing with [`io::ErrorKind::UnexpectedEof`] if there aren't that many.
+///
+/// Unlike resizing `buf` and using [`Read::read_exact`], this only grows `buf` as the bytes arrive, so a garbage length can't
+/// allocate any more than what's actually there.
+pub(crate) fn read_exact_len(
+    reader: &mut impl Read,
+    buf: &mut Vec<u8>,
+    len: usize,
+) -> io::Result<()> {
+    if reader.take(len as u64).read_to_end(buf)? != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Parse an optional unsigned keyword out of a header
+pub(crate) fn get_u64(header: &DadaHeader, key: &str) -> PsrdadaResult<Option<u64>> {
+    header
+        .get(key)
+        .map(|value| {
+            value.parse().map_err(|_| PsrdadaError::HeaderValueError {
+                key: key.to_owned(),
+                value: value.to_owned(),
+            })
+        })
+        .transpose()
+}
+
+/// Reads a `.dada` file as its header and then blocks of data
+pub struct DadaFileReader<R> {
+    inner: Chain<Cursor<Vec<u8>>, R>,
+    header: DadaHeader,
+    hdr_size: usize,
+    block_size: usize,
+    buf: Vec<u8>,
+    position: BlockPosition,
+    error: Option<PsrdadaError>,
+}
+
+impl DadaFileReader<BufReader<File>> {
+    /// Open a `.dada` file and read its header
+    pub fn open(path: impl AsRef<Path>) -> PsrdadaResult<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> DadaFileReader<R> {
+    /// Read the header from the start of `inner`, leaving it at the start of the data.
+    ///
+    /// Like the C library, this reads the first [`DEFAULT_HDR_SIZE`] bytes to find `HDR_SIZE`, which has to be set.
+    pub fn new(mut inner: R) -> PsrdadaResult<Self> {
+        let mut bytes = vec![0; DEFAULT_HDR_SIZE];
+        let n = read_up_to(&mut inner, &mut bytes)?;
+        bytes.truncate(n);
+        // This could have cut a line in half, so skip over anything that doesn't parse for now
+        let (first, _) = bytes_to_header_lenient(&bytes);
+        let value = first
+            .get("HDR_SIZE")
+            .ok_or_else(|| PsrdadaError::HeaderMissingKey("HDR_SIZE".to_owned()))?;
+        let hdr_size: usize = value.parse().map_err(|_| PsrdadaError::HeaderValueError {
+            key: "HDR_SIZE".to_owned(),
+            value: value.to_owned(),
+        })?;
+        // Either read the rest of the header, or hang on to the data we read past it
+        let data = if hdr_size > n {
+            read_exact_len(&mut inner, &mut bytes, hdr_size - n)?;
+            vec![]
+        } else {
+            bytes.split_off(hdr_size)
+        };
+        Ok(Self {
+            header: DadaHeader::parse(&bytes)?,
+            inner: Cursor::new(data).chain(inner),
+            hdr_size,
+            block_size: DEFAULT_BLOCK_SIZE,
+            buf: vec![],
+            position: BlockPosition {
+                index: 0,
+                byte_offset: 0,
+                transfer: 0,
+            },
+            error: None,
+        })
+    }
+
+    /// Set the size of the blocks to read the data in. The last block of the file may be shorter.
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    /// The header of this file
+    pub fn header(&self) -> &DadaHeader {
+        &self.header
+    }
+
+    /// The size of the header on disk, from `HDR_SIZE`
+    pub fn hdr_size(&self) -> usize {
+        self.hdr_size
+    }
+
+    /// Take the error that stopped iteration early, if there was one (rather than reaching the end of the file)
+    pub fn take_error(&mut self) -> Option<PsrdadaError> {
+        self.error.take()
+    }
+
+    /// Get back the underlying reader, positioned after the last block
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner().1
+    }
+}
+
+/// Reads the data straight out of the file, for when it doesn't need to be split into blocks.
+///
+/// Anything read this way won't be lent out as a block, but block positions will still count it.
+impl<R: Read> Read for DadaFileReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.position.byte_offset += n as u64;
+        Ok(n)
+    }
+}
+
+/// A block of data from a [`DadaFileReader`]
+pub struct FileBlock<'a> {
+    bytes: &'a [u8],
+    bytes_read: usize,
+    position: BlockPosition,
+}
+
+impl FileBlock<'_> {
+    /// Consumes the block. This only exists to mirror [`ReadBlock::done`](crate::io::read::ReadBlock::done).
+    pub fn done(self) {}
+
+    /// Get the underlying block of bytes for this block.
+    pub fn block(&mut self) -> &[u8] {
+        self.bytes
+    }
+
+    /// Get where this block sits in the data of the file. The transfer is always zero.
+    pub fn position(&self) -> BlockPosition {
+        self.position
+    }
+}
+
+impl Read for FileBlock<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = (&self.bytes[self.bytes_read..]).read(buf)?;
+        self.bytes_read += n;
+        Ok(n)
+    }
+}
+
+impl ReadableBlock for FileBlock<'_> {
+    fn block(&mut self) -> &[u8] {
+        self.bytes
+    }
+
+    fn position(&self) -> BlockPosition {
+        self.position
+    }
+}
+
+impl<'next, R: Read> DadaIteratorItem<'next> for DadaFileReader<R> {
+    type Item = FileBlock<'next>;
+}
+
+impl<R: Read> DadaIterator for DadaFileReader<R> {
+    fn next(&mut self) -> Option<FileBlock<'_>> {
+        self.buf.resize(self.block_size, 0);
+        let n = match read_up_to(&mut self.inner, &mut self.buf) {
+            Ok(0) => return None,
+            Ok(n) => n,
+            Err(e) => {
+                error!("Error reading the next block from file - {e}");
+                self.error = Some(e.into());
+                return None;
+            }
+        };
+        let position = self.position;
+        self.position.index += 1;
+        self.position.byte_offset += n as u64;
+        Some(FileBlock {
+            bytes: &self.buf[..n],
+            bytes_read: 0,
+            position,
+        })
+    }
+}
+
+/// Writes a `.dada` file: the header padded to `HDR_SIZE`, then whatever is written to it
+pub struct DadaFileWriter<W: Write> {
+    inner: W,
+    header: DadaHeader,
+    file_number: u64,
+    obs_offset: u64,
+    bytes_written: u64,
+}
+
+impl DadaFileWriter<BufWriter<File>> {
+    /// Create (or truncate) a file at `path` and write the header to it
+    pub fn create(path: impl AsRef<Path>, header: DadaHeader) -> PsrdadaResult<Self> {
+        Self::new(BufWriter::new(File::create(path)?), header)
+    }
+}
+
+impl<W: Write> DadaFileWriter<W> {
+    /// Write the header to `inner`, padded with zeros to `HDR_SIZE`.
+    ///
+    /// If the header doesn't set `HDR_SIZE`, it's set to [`DEFAULT_HDR_SIZE`]. Fails with [`PsrdadaError::HdrSizeError`] if
+    /// the header (and the `\0` after it) doesn't fit, or [`PsrdadaError::HeaderValueError`] if `HDR_SIZE`, `FILE_NUMBER` or
+    /// `OBS_OFFSET` aren't numbers.
+    pub fn new(mut inner: W, mut header: DadaHeader) -> PsrdadaResult<Self> {
+        let hdr_size = match get_u64(&header, "HDR_SIZE")? {
+            Some(hdr_size) => hdr_size as usize,
+            None => {
+                header.insert("HDR_SIZE", DEFAULT_HDR_SIZE.to_string());
+                DEFAULT_HDR_SIZE
+            }
+        };
+        header.validate()?;
+        let mut bytes = header.to_bytes();
+        if bytes.len() >= hdr_size {
+            return Err(PsrdadaError::HdrSizeError {
+                hdr_size,
+                header_size: bytes.len(),
+                buffer_size: hdr_size,
+            });
+        }
+        bytes.resize(hdr_size, 0);
+        inner.write_all(&bytes)?;
+        Ok(Self {
+            file_number: get_u64(&header, "FILE_NUMBER")?.unwrap_or_default(),
+            obs_offset: get_u64(&header, "OBS_OFFSET")?.unwrap_or_default(),
+            inner,
+            header,
+            bytes_written: 0,
+        })
+    }
+
+    /// The header of this file, as written
+    pub fn header(&self) -> &DadaHeader {
+        &self.header
+    }
+
+    /// The number of data bytes written so far, not counting the header
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// The header for the file that continues this one, with `FILE_NUMBER` incremented and `OBS_OFFSET` moved past the data
+    /// written so far
+    pub fn next_header(&self) -> DadaHeader {
+        let mut header = self.header.clone();
+        header.insert("FILE_NUMBER", (self.file_number + 1).to_string());
+        header.insert(
+            "OBS_OFFSET",
+            (self.obs_offset + self.bytes_written).to_string(),
+        );
+        header
+    }
+
+    /// Flush the file and get back the underlying writer
+    pub fn finish(mut self) -> PsrdadaResult<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for DadaFileWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes_written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, io::DadaClient, iter::Item, tests::next_key};
+
+    fn header() -> DadaHeader {
+        DadaHeader::parse(b"# A test observation\nSOURCE J0437-4715\nOBS_OFFSET 0\n").unwrap()
+    }
+
+    // Something that doesn't care where its blocks come from
+    fn collect<I>(mut iter: I) -> Vec<(u64, Vec<u8>)>
+    where
+        I: DadaIterator,
+        for<'a> Item<'a, I>: ReadableBlock,
+    {
+        let mut blocks = vec![];
+        while let Some(mut block) = iter.next() {
+            blocks.push((block.position().byte_offset, block.block().to_vec()));
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_roundtrip_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("obs.dada");
+        let mut writer = DadaFileWriter::create(&path, header()).unwrap();
+        writer.write_all(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+        assert_eq!(writer.bytes_written(), 10);
+        writer.finish().unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            DEFAULT_HDR_SIZE as u64 + 10
+        );
+
+        let reader = DadaFileReader::open(&path).unwrap().block_size(4);
+        assert_eq!(reader.hdr_size(), DEFAULT_HDR_SIZE);
+        assert_eq!(
+            reader.header().to_bytes(),
+            b"# A test observation\nSOURCE J0437-4715\nOBS_OFFSET 0\nHDR_SIZE 4096\n"
+        );
+        assert_eq!(collect(reader), [
+            (0, vec![0, 1, 2, 3]),
+            (4, vec![4, 5, 6, 7]),
+            (8, vec![8, 9])
+        ]);
+    }
+
+    #[test]
+    fn test_hdr_sizes() {
+        for hdr_size in [128, 8192] {
+            let mut header = header();
+            header.insert("HDR_SIZE", hdr_size.to_string());
+            let mut writer = DadaFileWriter::new(vec![], header).unwrap();
+            writer.write_all(&[42; 16]).unwrap();
+            let bytes = writer.finish().unwrap();
+            assert_eq!(bytes.len(), hdr_size + 16);
+
+            let mut reader = DadaFileReader::new(bytes.as_slice()).unwrap();
+            assert_eq!(reader.hdr_size(), hdr_size);
+            assert_eq!(reader.header().get("SOURCE"), Some("J0437-4715"));
+            assert_eq!(reader.next().unwrap().block(), &[42; 16]);
+            assert!(reader.next().is_none());
+            assert_eq!(reader.take_error(), None);
+        }
+    }
+
+    #[test]
+    fn test_bad_headers() {
+        let mut header = header();
+        header.insert("HDR_SIZE", "32");
+        assert!(matches!(
+            DadaFileWriter::new(vec![], header),
+            Err(PsrdadaError::HdrSizeError { hdr_size: 32, .. })
+        ));
+        assert!(matches!(
+            DadaFileReader::new(&b"SOURCE J0437-4715\n\0\0\0\0"[..]),
+            Err(PsrdadaError::HeaderMissingKey(key)) if key == "HDR_SIZE"
+        ));
+        // A file cut off in the middle of its header
+        assert!(matches!(
+            DadaFileReader::new(&b"HDR_SIZE 8192\n\0\0\0\0"[..]),
+            Err(PsrdadaError::IoError(io::ErrorKind::UnexpectedEof))
+        ));
+        // Or with a garbage HDR_SIZE, which shouldn't be allocated up front
+        assert!(matches!(
+            DadaFileReader::new(&b"HDR_SIZE 18446744073709551615\n\0\0\0\0"[..]),
+            Err(PsrdadaError::IoError(io::ErrorKind::UnexpectedEof))
+        ));
+    }
+
+    #[test]
+    fn test_next_header() {
+        let mut header = header();
+        header.insert("FILE_NUMBER", "3");
+        header.insert("OBS_OFFSET", "1000");
+        let mut writer = DadaFileWriter::new(vec![], header).unwrap();
+        writer.write_all(&[0; 24]).unwrap();
+        let next = writer.next_header();
+        assert_eq!(next.get("FILE_NUMBER"), Some("4"));
+        assert_eq!(next.get("OBS_OFFSET"), Some("1024"));
+        assert_eq!(next.get("SOURCE"), Some("J0437-4715"));
+    }
+
+    #[test]
+    fn test_ring_to_file_and_back() {
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_bufs(4)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+        let mut writer = dc.writer().unwrap();
+        for i in 0..3u8 {
+            let mut block = writer.next().unwrap();
+            block.write_all(&[i; 4]).unwrap();
+            if i == 2 {
+                block.mark_eod();
+            }
+        }
+        drop(writer);
+
+        // The same processing sees the same blocks from the ring and from a recording of it
+        let expected = [(0, vec![0; 4]), (4, vec![1; 4]), (8, vec![2; 4])];
+        let blocks = collect(dc.reader().unwrap());
+        assert_eq!(blocks, expected);
+        let mut file = DadaFileWriter::new(vec![], header()).unwrap();
+        for (_, block) in blocks {
+            file.write_all(&block).unwrap();
+        }
+        let bytes = file.finish().unwrap();
+        let file = DadaFileReader::new(bytes.as_slice()).unwrap().block_size(4);
+        assert_eq!(collect(file), expected);
+    }
+}
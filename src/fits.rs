@@ -0,0 +1,175 @@
+//! FITS-style header cards, as used by both PSRFITS and GUPPI RAW.
+//!
+//! A card is 80 ASCII characters: an 8 character keyword, `= `, then the value and an optional `/ comment`. Strings are quoted
+//! with `'` (doubled inside the string) and padded to at least 8 characters, while numbers and logicals are right-justified to
+//! end in column 30. A header ends with an `END` card.
+
+/// The size of a FITS record, which every HDU is padded to
+pub(crate) const RECORD: usize = 2880;
+pub(crate) const CARD: usize = 80;
+
+/// The value of a card to write
+pub(crate) enum Value<'a> {
+    Logical(bool),
+    Int(i64),
+    Float(f64),
+    Str(&'a str),
+    /// Anything else that goes unquoted, as it's given
+    Raw(&'a str),
+}
+
+/// The value of a card that was read: a string without its quotes, or anything else as it was written
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum CardValue {
+    Str(String),
+    Raw(String),
+}
+
+impl CardValue {
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            Self::Str(s) | Self::Raw(s) => s,
+        }
+    }
+
+    pub(crate) fn as_value(&self) -> Value<'_> {
+        match self {
+            Self::Str(s) => Value::Str(s),
+            Self::Raw(s) => Value::Raw(s),
+        }
+    }
+}
+
+/// The cards of a header
+#[derive(Default)]
+pub(crate) struct FitsHeader {
+    pub(crate) cards: Vec<u8>,
+}
+
+impl FitsHeader {
+    pub(crate) fn card(&mut self, key: &str, value: Value, comment: &str) -> &mut Self {
+        let value = match value {
+            Value::Logical(b) => format!("{:>20}", if b { "T" } else { "F" }),
+            Value::Int(i) => format!("{i:>20}"),
+            // FITS wants an uppercase exponent, and a decimal point to tell it apart from an integer
+            Value::Float(f) => format!("{:>20}", format!("{f:?}").to_uppercase()),
+            Value::Str(s) => format!("'{:<8}'", s.replace('\'', "''")),
+            Value::Raw(s) => format!("{s:>20}"),
+        };
+        let mut card = format!("{key:<8}= {value}");
+        if !comment.is_empty() {
+            card = format!("{card} / {comment}");
+        }
+        let mut bytes = card.into_bytes();
+        bytes.resize(CARD, b' ');
+        self.cards.extend_from_slice(&bytes[..CARD]);
+        self
+    }
+
+    /// The cards followed by `END`
+    pub(crate) fn end(&self) -> Vec<u8> {
+        let mut bytes = self.cards.clone();
+        bytes.extend_from_slice(&END);
+        bytes
+    }
+
+    /// The header, ended and padded to a whole record
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.end();
+        bytes.resize(align(bytes.len(), RECORD), b' ');
+        bytes
+    }
+}
+
+const END: [u8; CARD] = {
+    let mut end = [b' '; CARD];
+    end[0] = b'E';
+    end[1] = b'N';
+    end[2] = b'D';
+    end
+};
+
+/// Whether a card is the `END` of a header
+pub(crate) fn is_end(card: &[u8]) -> bool {
+    card.starts_with(b"END") && card[3..].iter().all(|b| *b == b' ')
+}
+
+/// The keyword and value of a card, or `None` for one without a value, like `COMMENT` or a blank
+pub(crate) fn parse_card(card: &[u8]) -> Option<(String, CardValue)> {
+    let card = String::from_utf8_lossy(card);
+    let (key, value) = (card.get(..8)?, card.get(8..)?.strip_prefix("= ")?);
+    let key = key.trim_end().to_owned();
+    let value = value.trim_start();
+    if let Some(quoted) = value.strip_prefix('\'') {
+        // Find the closing quote, skipping over doubled ones
+        let mut s = String::new();
+        let mut chars = quoted.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '\'' {
+                if chars.peek() != Some(&'\'') {
+                    break;
+                }
+                chars.next();
+            }
+            s.push(c);
+        }
+        Some((key, CardValue::Str(s.trim_end().to_owned())))
+    } else {
+        let raw = value.split('/').next().unwrap_or_default().trim();
+        Some((key, CardValue::Raw(raw.to_owned())))
+    }
+}
+
+/// Round up to a multiple of `to`
+pub(crate) fn align(n: usize, to: usize) -> usize {
+    (n + to - 1) / to * to
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+
+    #[test]
+    fn test_card_format() {
+        let mut header = FitsHeader::default();
+        header
+            .card("SIMPLE", Value::Logical(true), "")
+            .card("TBIN", Value::Float(6.4e-5), "")
+            .card("OBSFREQ", Value::Float(1400.0), "")
+            .card("SRC_NAME", Value::Str("O'Hare"), "comment");
+        let cards: Vec<_> = header
+            .to_bytes()
+            .chunks(CARD)
+            .map(|c| std::str::from_utf8(c).unwrap().trim_end().to_owned())
+            .take(5)
+            .collect();
+        assert_eq!(cards, [
+            "SIMPLE  =                    T",
+            "TBIN    =               6.4E-5",
+            "OBSFREQ =               1400.0",
+            "SRC_NAME= 'O''Hare ' / comment",
+            "END",
+        ]);
+        assert_eq!(header.to_bytes().len(), RECORD);
+    }
+
+    #[test]
+    fn test_parse_card() {
+        let mut header = FitsHeader::default();
+        header
+            .card("SRC_NAME", Value::Str("O'Hare"), "a / comment")
+            .card("TBIN", Value::Float(6.4e-5), "s")
+            .card("NOTE", Value::Str(""), "");
+        let cards: Vec<_> = header.end().chunks(CARD).map(parse_card).collect();
+        assert_eq!(cards, [
+            Some(("SRC_NAME".to_owned(), CardValue::Str("O'Hare".to_owned()))),
+            Some(("TBIN".to_owned(), CardValue::Raw("6.4E-5".to_owned()))),
+            Some(("NOTE".to_owned(), CardValue::Str(String::new()))),
+            None,
+        ]);
+        assert!(is_end(&header.end()[3 * CARD..]));
+        assert_eq!(parse_card(b"COMMENT something"), None);
+    }
+}
@@ -0,0 +1,847 @@
+//! Reading and writing GUPPI RAW files, and streaming them into and out of rings.
+//!
+//! A GUPPI RAW file is a series of blocks, each a header of FITS-style 80 character cards ending with `END`, followed by
+//! `BLOCSIZE` bytes of data. If `DIRECTIO` is set (and isn't 0), the header and the data are each padded to a multiple of 512
+//! bytes so the file can be read with `O_DIRECT`. The data is copied as is, so it stays in GUPPI's order: complex voltages, with
+//! all of a block's samples of the first channel, then all of the second, and so on.
+//!
+//! The cards map to and from [`StandardHeader`] as:
+//!
+//! | DADA              | GUPPI RAW                              |
+//! |-------------------|----------------------------------------|
+//! | `NCHAN`           | `OBSNCHAN`                             |
+//! | `NBIT`            | `NBITS`                                |
+//! | `NPOL`            | `NPOL` (4 for two polarizations)       |
+//! | `NDIM`            | Always 2                               |
+//! | `TSAMP` (µs)      | `TBIN` (s)                             |
+//! | `FREQ`, `BW`      | `OBSFREQ`, `OBSBW`                     |
+//! | `UTC_START`       | `STT_IMJD`, `STT_SMJD`, `STT_OFFS`     |
+//! | `OBS_OFFSET`      | `PKTIDX` × `BLOCSIZE` / `PIPERBLK`     |
+//! | `SOURCE`          | `SRC_NAME`                             |
+//! | `RA`, `DEC`       | `RA_STR`, `DEC_STR`                    |
+//! | `TELESCOPE`       | `TELESCOP`                             |
+//!
+//! Every other card (`BLOCSIZE`, `DIRECTIO`, `PIPERBLK` included) is carried over as a keyword of the same name and back, so
+//! recording what was played from a GUPPI file gives the same layout. The exceptions are GUPPI's `RA` and `DEC`, which are in
+//! degrees and get written from the DADA ones. As with [`sigproc`](crate::sigproc), `UTC_START` only has whole seconds, so
+//! the exact start is kept in `MJD_START` as well.
+//!
+//! `PKTIDX` goes up by `PIPERBLK` every block. Without `PIPERBLK` there's no telling how much data that is, so `OBS_OFFSET` is
+//! left at 0, the first `PKTIDX` is kept as a keyword, and written blocks count up by one. When it is known, gaps in `PKTIDX`
+//! are filled with zeros on the way into a ring, up to [`max_gap`](GuppiReader::max_gap) bytes of them.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, Read, Write},
+    str::FromStr,
+};
+
+use tracing::warn;
+
+use crate::{
+    client::{DataClient, HeaderClient},
+    errors::{PsrdadaError, PsrdadaResult},
+    file::{read_exact_len, read_up_to},
+    fits::{align, is_end, parse_card, CardValue, FitsHeader, CARD},
+    headers::{
+        standard::{required, value_error},
+        Declination, Mjd, RightAscension, StandardHeader, UtcTime,
+    },
+    io::DadaClient,
+    iter::DadaIterator,
+};
+
+/// What headers and data are padded to when `DIRECTIO` is set
+pub const DIRECTIO_ALIGN: usize = 512;
+
+/// The most bytes of zeros a reader fills a gap in `PKTIDX` with by default, see [`GuppiReader::max_gap`]
+pub const DEFAULT_MAX_GAP: u64 = 1 << 30;
+
+/// The most cards we accept in a header, to catch files that aren't GUPPI RAW at all
+const MAX_CARDS: usize = 8192;
+
+/// Cards that are set from the [`StandardHeader`] fields, so aren't carried over as keywords
+const MAPPED: [&str; 16] = [
+    "OBSNCHAN", "NBITS", "NPOL", "TBIN", "OBSFREQ", "OBSBW", "CHAN_BW", "STT_IMJD", "STT_SMJD",
+    "STT_OFFS", "SRC_NAME", "RA_STR", "DEC_STR", "RA", "DEC", "TELESCOP",
+];
+
+/// The header of a block of a GUPPI RAW file: its cards, in order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GuppiHeader {
+    cards: Vec<(String, CardValue)>,
+}
+
+impl GuppiHeader {
+    /// The value of a card, without quotes if it's a string
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.cards
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of a card parsed as a `T`, failing with [`PsrdadaError::HeaderValueError`] if it doesn't parse
+    pub fn parse<T: FromStr>(&self, key: &str) -> PsrdadaResult<Option<T>> {
+        self.get(key)
+            .map(|value| value.parse().map_err(|_| value_error(key, value)))
+            .transpose()
+    }
+
+    fn put(&mut self, key: &str, value: CardValue) {
+        match self.cards.iter_mut().find(|(k, _)| k == key) {
+            Some((_, old)) => *old = value,
+            None => self.cards.push((key.to_owned(), value)),
+        }
+    }
+
+    /// Set a card, replacing it if it's already there. Numbers and logicals (`T` or `F`) are written as they are, anything
+    /// else as a string.
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        let value = value.to_string();
+        let numeric = value.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c))
+            && value.parse::<f64>().is_ok();
+        if numeric || value == "T" || value == "F" {
+            self.put(key, CardValue::Raw(value));
+        } else {
+            self.put(key, CardValue::Str(value));
+        }
+    }
+
+    /// Set a card to a string, replacing it if it's already there
+    pub fn set_str(&mut self, key: &str, value: &str) {
+        self.put(key, CardValue::Str(value.to_owned()));
+    }
+
+    /// Every card as `(key, value)`, in order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cards.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// The size of the data that follows this header, from `BLOCSIZE`
+    pub fn block_size(&self) -> PsrdadaResult<usize> {
+        required(self.parse("BLOCSIZE")?, "BLOCSIZE")
+    }
+
+    /// Whether the header and data are padded for `O_DIRECT`, from `DIRECTIO`
+    pub fn direct_io(&self) -> bool {
+        matches!(self.parse::<i64>("DIRECTIO"), Ok(Some(d)) if d != 0)
+    }
+
+    /// Read a header, leaving `reader` at the start of the data. Returns `None` at the end of the file.
+    ///
+    /// Fails with [`PsrdadaError::GuppiMalformedHeader`] if the file ends part way through, or this doesn't look like a header.
+    pub fn read(reader: &mut impl Read) -> PsrdadaResult<Option<Self>> {
+        let mut header = Self::default();
+        let mut card = [0; CARD];
+        for i in 0..MAX_CARDS {
+            match read_up_to(reader, &mut card)? {
+                0 if i == 0 => return Ok(None),
+                CARD => (),
+                _ => return Err(PsrdadaError::GuppiMalformedHeader),
+            }
+            if !card.iter().all(|b| (b' '..=b'~').contains(b)) {
+                return Err(PsrdadaError::GuppiMalformedHeader);
+            }
+            if is_end(&card) {
+                if header.direct_io() {
+                    let len = (i + 1) * CARD;
+                    let padding = align(len, DIRECTIO_ALIGN) - len;
+                    io::copy(&mut reader.take(padding as u64), &mut io::sink())?;
+                }
+                return Ok(Some(header));
+            }
+            if let Some((key, value)) = parse_card(&card) {
+                header.put(&key, value);
+            }
+        }
+        Err(PsrdadaError::GuppiMalformedHeader)
+    }
+
+    /// The header as it's written to a file, padded if `DIRECTIO` is set
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut fits = FitsHeader::default();
+        for (key, value) in &self.cards {
+            fits.card(key, value.as_value(), "");
+        }
+        let mut bytes = fits.end();
+        if self.direct_io() {
+            bytes.resize(align(bytes.len(), DIRECTIO_ALIGN), b' ');
+        }
+        bytes
+    }
+}
+
+/// Needs `OBSNCHAN`, `NBITS`, `TBIN`, `OBSFREQ` and `OBSBW`, failing with [`PsrdadaError::HeaderMissingKey`] (naming the
+/// GUPPI card) otherwise, and with [`PsrdadaError::HeaderValueError`] if `OBSNCHAN`, `NBITS` or `NPOL` is 0
+impl TryFrom<&GuppiHeader> for StandardHeader {
+    type Error = PsrdadaError;
+
+    fn try_from(guppi: &GuppiHeader) -> Result<Self, Self::Error> {
+        let nchan: u32 = required(guppi.parse("OBSNCHAN")?, "OBSNCHAN")?;
+        let nbit: u32 = required(guppi.parse("NBITS")?, "NBITS")?;
+        let tbin: f64 = required(guppi.parse("TBIN")?, "TBIN")?;
+        let freq = required(guppi.parse("OBSFREQ")?, "OBSFREQ")?;
+        let bw = required(guppi.parse("OBSBW")?, "OBSBW")?;
+        let npol = match guppi.parse("NPOL")?.unwrap_or(1) {
+            4 => 2,
+            npol => npol,
+        };
+        for (key, value) in [("OBSNCHAN", nchan), ("NBITS", nbit), ("NPOL", npol)] {
+            if value == 0 {
+                return Err(value_error(key, value));
+            }
+        }
+        let bits = [nchan, npol, 2, nbit]
+            .iter()
+            .try_fold(1u64, |bits, n| bits.checked_mul((*n).into()))
+            .ok_or_else(|| value_error("OBSNCHAN", nchan))?;
+
+        let mut extras = HashMap::new();
+        let mut utc_start = None;
+        if let Some(day) = guppi.parse("STT_IMJD")? {
+            let seconds: u64 = guppi.parse("STT_SMJD")?.unwrap_or(0);
+            let offset: f64 = guppi.parse("STT_OFFS")?.unwrap_or(0.0);
+            let nanos = seconds
+                .checked_mul(1_000_000_000)
+                .and_then(|nanos| nanos.checked_add((offset * 1e9).round() as u64))
+                .ok_or_else(|| value_error("STT_SMJD", seconds))?;
+            let mjd = Mjd { day, nanos };
+            let start = mjd.to_utc();
+            utc_start = Some(UtcTime::from_unix(start.unix_seconds(), 0));
+            if start.subsec_nanos() != 0 {
+                extras.insert("MJD_START".to_owned(), mjd.to_string());
+            }
+        }
+        let pktidx: Option<u64> = guppi.parse("PKTIDX")?;
+        let piperblk: Option<u64> = guppi.parse("PIPERBLK")?;
+        let obs_offset = match (pktidx, piperblk) {
+            (Some(pktidx), Some(piperblk)) if piperblk > 0 => {
+                pktidx
+                    .checked_mul(guppi.block_size()? as u64)
+                    .ok_or_else(|| value_error("PKTIDX", pktidx))?
+                    / piperblk
+            }
+            _ => 0,
+        };
+        for (key, value) in guppi.iter() {
+            let mapped = key == "PKTIDX" && piperblk.is_some();
+            if !mapped && !MAPPED.contains(&key) {
+                extras.insert(key.to_owned(), value.to_owned());
+            }
+        }
+        let ra = guppi
+            .get("RA_STR")
+            .map(|s| {
+                s.parse::<RightAscension>()
+                    .map_err(|_| value_error("RA_STR", s))
+            })
+            .transpose()?;
+        let dec = guppi
+            .get("DEC_STR")
+            .map(|s| {
+                s.parse::<Declination>()
+                    .map_err(|_| value_error("DEC_STR", s))
+            })
+            .transpose()?;
+        Ok(Self {
+            utc_start,
+            obs_offset: Some(obs_offset),
+            freq: Some(freq),
+            bw: Some(bw),
+            nchan: Some(nchan),
+            npol: Some(npol),
+            nbit: Some(nbit),
+            ndim: Some(2),
+            tsamp: Some(tbin * 1e6),
+            bytes_per_second: Some((bits as f64 / 8.0 / tbin).round() as u64),
+            source: guppi.get("SRC_NAME").map(str::to_owned),
+            ra,
+            dec,
+            telescope: guppi.get("TELESCOP").map(str::to_owned),
+            extras,
+            ..Default::default()
+        })
+    }
+}
+
+/// Needs `NCHAN`, `NBIT`, `TSAMP`, `FREQ` and `BW`, failing with [`PsrdadaError::HeaderMissingKey`] otherwise, and with
+/// [`PsrdadaError::HeaderValueError`] for real data (`NDIM` of 1).
+///
+/// Keywords too long to be a card are left out. `PKTIDX` is only set if `BLOCSIZE` and `PIPERBLK` are there to work it out
+/// from `OBS_OFFSET` (or it's there itself), so [`GuppiWriter`] starts from 0 otherwise.
+impl TryFrom<&StandardHeader> for GuppiHeader {
+    type Error = PsrdadaError;
+
+    fn try_from(dada: &StandardHeader) -> Result<Self, Self::Error> {
+        let nchan = required(dada.nchan, "NCHAN")?;
+        let nbit = required(dada.nbit, "NBIT")?;
+        let tsamp = required(dada.tsamp, "TSAMP")?;
+        let freq = required(dada.freq, "FREQ")?;
+        let bw = required(dada.bw, "BW")?;
+        if let Some(ndim) = dada.ndim.filter(|ndim| *ndim != 2) {
+            return Err(value_error("NDIM", ndim));
+        }
+        let npol = match dada.npol.unwrap_or(1) {
+            2 => 4,
+            npol => npol,
+        };
+
+        let mut guppi = Self::default();
+        if let Some(telescope) = &dada.telescope {
+            guppi.set_str("TELESCOP", telescope);
+        }
+        guppi.set("OBSFREQ", freq);
+        guppi.set("OBSBW", bw);
+        guppi.set("CHAN_BW", bw / nchan as f64);
+        guppi.set("OBSNCHAN", nchan);
+        guppi.set("NPOL", npol);
+        guppi.set("NBITS", nbit);
+        guppi.set("TBIN", tsamp / 1e6);
+        if let Some(source) = &dada.source {
+            guppi.set_str("SRC_NAME", source);
+        }
+        if let Some(ra) = dada.ra {
+            guppi.set_str("RA_STR", &ra.to_string());
+            guppi.set("RA", ra.degrees());
+        }
+        if let Some(dec) = dada.dec {
+            guppi.set_str("DEC_STR", &dec.to_string());
+            guppi.set("DEC", dec.degrees());
+        }
+        let mjd = match dada.extras.get("MJD_START") {
+            Some(mjd) => Some(mjd.parse::<Mjd>()?),
+            None => dada.utc_start.map(|utc| utc.to_mjd()),
+        };
+        if let Some(mjd) = mjd {
+            guppi.set("STT_IMJD", mjd.day);
+            guppi.set("STT_SMJD", mjd.nanos / 1_000_000_000);
+            guppi.set("STT_OFFS", (mjd.nanos % 1_000_000_000) as f64 / 1e9);
+        }
+
+        let mut extras: Vec<_> = dada.extras.iter().collect();
+        extras.sort();
+        for (key, value) in extras {
+            if key == "MJD_START" || guppi.get(key).is_some() {
+                continue;
+            }
+            if key.len() > 8 {
+                warn!(key, "Leaving out a keyword too long for a GUPPI card");
+                continue;
+            }
+            guppi.set(key, value);
+        }
+        let piperblk: Option<u64> = guppi.parse("PIPERBLK")?;
+        let block_size: Option<u64> = guppi.parse("BLOCSIZE")?;
+        if let (Some(piperblk), Some(block_size), Some(obs_offset)) =
+            (piperblk, block_size, dada.obs_offset)
+        {
+            let pktidx = obs_offset
+                .checked_mul(piperblk)
+                .ok_or_else(|| value_error("OBS_OFFSET", obs_offset))?
+                .checked_div(block_size);
+            if let Some(pktidx) = pktidx {
+                guppi.set("PKTIDX", pktidx);
+            }
+        }
+        Ok(guppi)
+    }
+}
+
+/// Reads a GUPPI RAW file (or a stream of one) a block at a time, or as one stream of data with [`Read`]
+pub struct GuppiReader<R> {
+    inner: R,
+    header: GuppiHeader,
+    /// The header of the block being read
+    current: GuppiHeader,
+    data: Vec<u8>,
+    /// How much of `data` has been read
+    read: usize,
+    /// Whether the current block has been handed out by [`GuppiReader::next_block`] yet
+    fresh: bool,
+    /// Zeros still to be read, to fill a gap in `PKTIDX`
+    gap: u64,
+    max_gap: u64,
+}
+
+impl<R: Read> GuppiReader<R> {
+    /// Read the first block, failing with [`PsrdadaError::GuppiMalformedHeader`] if there isn't one
+    pub fn new(mut inner: R) -> PsrdadaResult<Self> {
+        let header = GuppiHeader::read(&mut inner)?.ok_or(PsrdadaError::GuppiMalformedHeader)?;
+        let mut reader = Self {
+            inner,
+            current: header.clone(),
+            header,
+            data: vec![],
+            read: 0,
+            fresh: true,
+            gap: 0,
+            max_gap: DEFAULT_MAX_GAP,
+        };
+        reader.read_data()?;
+        Ok(reader)
+    }
+
+    /// The most bytes of zeros to fill a gap in `PKTIDX` with, [`DEFAULT_MAX_GAP`] by default. Reading fails with
+    /// [`PsrdadaError::HeaderValueError`] on a bigger one, as the `PKTIDX` is most likely garbage.
+    pub fn max_gap(mut self, bytes: u64) -> Self {
+        self.max_gap = bytes;
+        self
+    }
+
+    /// The header of the first block
+    pub fn header(&self) -> &GuppiHeader {
+        &self.header
+    }
+
+    fn read_data(&mut self) -> PsrdadaResult<()> {
+        let block_size = self.current.block_size()?;
+        self.data.clear();
+        read_exact_len(&mut self.inner, &mut self.data, block_size)?;
+        if self.current.direct_io() {
+            let padding = align(block_size, DIRECTIO_ALIGN) - block_size;
+            io::copy(&mut (&mut self.inner).take(padding as u64), &mut io::sink())?;
+        }
+        self.read = 0;
+        Ok(())
+    }
+
+    /// Move on to the next block, returning false at the end of the file
+    fn advance(&mut self) -> PsrdadaResult<bool> {
+        let header = match GuppiHeader::read(&mut self.inner)? {
+            Some(header) => header,
+            None => return Ok(false),
+        };
+        let previous: Option<u64> = self.current.parse("PKTIDX")?;
+        let next: Option<u64> = header.parse("PKTIDX")?;
+        let piperblk: Option<u64> = self.current.parse("PIPERBLK")?;
+        if let (Some(previous), Some(next), Some(piperblk)) = (previous, next, piperblk) {
+            let expected = previous.saturating_add(piperblk);
+            if next > expected && piperblk > 0 {
+                let missing = next - expected;
+                let gap = missing
+                    .checked_mul(self.current.block_size()? as u64 / piperblk)
+                    .filter(|gap| *gap <= self.max_gap)
+                    .ok_or_else(|| value_error("PKTIDX", next))?;
+                warn!(missing, "Filling a gap in PKTIDX with zeros");
+                self.gap += gap;
+            } else if next < expected {
+                warn!(previous, next, "PKTIDX went backwards");
+            }
+        }
+        self.current = header;
+        self.read_data()?;
+        self.fresh = true;
+        Ok(true)
+    }
+
+    /// The header and data of the next block, or `None` at the end of the file.
+    ///
+    /// Gaps in `PKTIDX` aren't filled here, so this shouldn't be mixed with reading the data as a stream.
+    pub fn next_block(&mut self) -> PsrdadaResult<Option<(&GuppiHeader, &[u8])>> {
+        if !self.fresh && !self.advance()? {
+            return Ok(None);
+        }
+        self.fresh = false;
+        self.gap = 0;
+        Ok(Some((&self.current, &self.data)))
+    }
+}
+
+/// Reads the data of every block one after the other, with any gaps in `PKTIDX` filled with zeros
+impl<R: Read> Read for GuppiReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.gap > 0 {
+                let n = (self.gap.min(buf.len() as u64)) as usize;
+                buf[..n].fill(0);
+                self.gap -= n as u64;
+                return Ok(n);
+            }
+            if self.read < self.data.len() {
+                let n = (&self.data[self.read..]).read(buf)?;
+                self.read += n;
+                return Ok(n);
+            }
+            match self.advance() {
+                Ok(true) => continue,
+                Ok(false) => return Ok(0),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}"))),
+            }
+        }
+    }
+}
+
+/// Writes a GUPPI RAW file, splitting the data written to it into blocks of `BLOCSIZE`
+pub struct GuppiWriter<W: Write> {
+    inner: W,
+    header: GuppiHeader,
+    block_size: usize,
+    pktidx: u64,
+    piperblk: u64,
+    /// Data waiting for a whole block
+    data: Vec<u8>,
+    blocks: u64,
+}
+
+impl<W: Write> GuppiWriter<W> {
+    /// Write blocks with the cards of `header`, which has to have `BLOCSIZE`. `PKTIDX` starts from the one in the header (or 0)
+    /// and goes up by `PIPERBLK` (or 1) every block.
+    pub fn new(inner: W, header: GuppiHeader) -> PsrdadaResult<Self> {
+        let block_size = header.block_size()?;
+        if block_size == 0 {
+            return Err(value_error("BLOCSIZE", 0));
+        }
+        Ok(Self {
+            inner,
+            pktidx: header.parse("PKTIDX")?.unwrap_or(0),
+            piperblk: header.parse("PIPERBLK")?.unwrap_or(1),
+            header,
+            block_size,
+            data: Vec::with_capacity(block_size),
+            blocks: 0,
+        })
+    }
+
+    /// The number of blocks written so far
+    pub fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    /// Write a block of any size straight away, with `BLOCSIZE` set to match
+    pub fn write_block(&mut self, data: &[u8]) -> PsrdadaResult<()> {
+        self.header.set("BLOCSIZE", data.len());
+        self.header.set("PKTIDX", self.pktidx);
+        self.inner.write_all(&self.header.to_bytes())?;
+        self.inner.write_all(data)?;
+        if self.header.direct_io() {
+            let padding = align(data.len(), DIRECTIO_ALIGN) - data.len();
+            self.inner.write_all(&vec![0; padding])?;
+        }
+        self.pktidx += self.piperblk;
+        self.blocks += 1;
+        Ok(())
+    }
+
+    /// Add data, writing out every block that fills up
+    pub fn write_data(&mut self, mut data: &[u8]) -> PsrdadaResult<()> {
+        while !data.is_empty() {
+            if self.data.is_empty() && data.len() >= self.block_size {
+                let (block, rest) = data.split_at(self.block_size);
+                self.write_block(block)?;
+                data = rest;
+                continue;
+            }
+            let n = (self.block_size - self.data.len()).min(data.len());
+            self.data.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.data.len() == self.block_size {
+                let block = std::mem::take(&mut self.data);
+                self.write_block(&block)?;
+                self.data = block;
+                self.data.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out what's left as a last, shorter block and get back the underlying writer
+    pub fn finish(mut self) -> PsrdadaResult<W> {
+        if !self.data.is_empty() {
+            let block = std::mem::take(&mut self.data);
+            self.write_block(&block)?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for GuppiWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_data(buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{e:?}")))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Read the next transfer from a ring and write it to `out` as GUPPI RAW, returning the number of data bytes written.
+///
+/// Blocks are `BLOCSIZE` if the header has it, or the size of the ring's blocks otherwise.
+pub fn record_guppi(
+    header_client: &mut HeaderClient,
+    data_client: &mut DataClient,
+    out: &mut impl Write,
+) -> PsrdadaResult<u64> {
+    let dada = StandardHeader::try_from(header_client.read_header()?)?;
+    let mut guppi = GuppiHeader::try_from(&dada)?;
+    let mut reader = data_client.reader()?;
+    let mut first = match reader.next() {
+        Some(block) => block,
+        None => return Ok(0),
+    };
+    if guppi.get("BLOCSIZE").is_none() {
+        guppi.set("BLOCSIZE", first.block().len());
+    }
+    let mut writer = GuppiWriter::new(out, guppi)?;
+    let mut bytes = first.block().len() as u64;
+    writer.write_data(first.block())?;
+    drop(first);
+    while let Some(mut block) = reader.next() {
+        let data = block.block();
+        writer.write_data(data)?;
+        bytes += data.len() as u64;
+    }
+    writer.finish()?;
+    Ok(bytes)
+}
+
+/// Write a GUPPI RAW file into a ring as a single transfer, returning the number of data bytes written
+pub fn play_guppi(
+    input: impl Read,
+    header_client: &mut HeaderClient,
+    data_client: &mut DataClient,
+) -> PsrdadaResult<u64> {
+    let reader = GuppiReader::new(input)?;
+    header_client.write_header(&StandardHeader::try_from(reader.header())?.into())?;
+    data_client
+        .writer()?
+        .write_from(&mut BufReader::new(reader), |_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use test_log::test;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, headers::bytes_to_header, tests::next_key};
+
+    const DADA: &[u8] = b"UTC_START 2023-05-01-12:00:00
+OBS_OFFSET 2048
+FREQ 1400
+BW -200
+NCHAN 4
+NPOL 2
+NBIT 8
+NDIM 2
+TSAMP 20
+SOURCE J0437-4715
+RA 04:37:15.8961
+DEC -47:15:09.1100
+TELESCOPE GBT
+BYTES_PER_SECOND 800000
+BLOCSIZE 1024
+PIPERBLK 16
+DIRECTIO 1
+BACKEND GUPPI
+";
+
+    fn standard() -> StandardHeader {
+        StandardHeader::try_from(bytes_to_header(DADA).unwrap()).unwrap()
+    }
+
+    fn guppi() -> GuppiHeader {
+        GuppiHeader::try_from(&standard()).unwrap()
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = guppi();
+        assert_eq!(header.get("OBSNCHAN"), Some("4"));
+        assert_eq!(header.get("NPOL"), Some("4"));
+        assert_eq!(header.get("TBIN"), Some("0.00002"));
+        assert_eq!(header.get("SRC_NAME"), Some("J0437-4715"));
+        assert_eq!(header.get("STT_IMJD"), Some("60065"));
+        assert_eq!(header.get("STT_SMJD"), Some("43200"));
+        assert_eq!(header.get("PKTIDX"), Some("32"));
+        assert_eq!(header.get("BACKEND"), Some("GUPPI"));
+
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len() % DIRECTIO_ALIGN, 0);
+        let mut cursor = Cursor::new(bytes.clone());
+        assert_eq!(
+            GuppiHeader::read(&mut cursor).unwrap(),
+            Some(header.clone())
+        );
+        assert_eq!(cursor.position() as usize, bytes.len());
+        assert_eq!(GuppiHeader::read(&mut cursor).unwrap(), None);
+
+        let mut back = StandardHeader::try_from(&header).unwrap();
+        assert!((back.tsamp.unwrap() - 20.0).abs() < 1e-9);
+        back.tsamp = Some(20.0);
+        assert_eq!(back, standard());
+    }
+
+    #[test]
+    fn test_bad_headers() {
+        let mut header = standard();
+        header.ndim = Some(1);
+        assert_eq!(GuppiHeader::try_from(&header), Err(value_error("NDIM", 1)));
+        let mut cursor = Cursor::new(b"BLOCSIZE=                   10".to_vec());
+        assert_eq!(
+            GuppiHeader::read(&mut cursor),
+            Err(PsrdadaError::GuppiMalformedHeader)
+        );
+        let mut header = guppi();
+        header.set("TBIN", "soon");
+        assert_eq!(
+            StandardHeader::try_from(&header),
+            Err(value_error("TBIN", "soon"))
+        );
+        let mut header = guppi();
+        header.set("NBITS", 0);
+        assert_eq!(
+            StandardHeader::try_from(&header),
+            Err(value_error("NBITS", 0))
+        );
+        let mut header = guppi();
+        header.set("OBSNCHAN", u32::MAX);
+        header.set("NBITS", u32::MAX);
+        assert_eq!(
+            StandardHeader::try_from(&header),
+            Err(value_error("OBSNCHAN", u32::MAX))
+        );
+        // A garbage BLOCSIZE runs into the end of the file, rather than being allocated up front
+        let mut header = guppi();
+        header.set("BLOCSIZE", usize::MAX);
+        let mut bytes = header.to_bytes();
+        bytes.extend_from_slice(&[0; 100]);
+        assert!(matches!(
+            GuppiReader::new(Cursor::new(bytes)),
+            Err(PsrdadaError::IoError(io::ErrorKind::UnexpectedEof))
+        ));
+    }
+
+    #[test]
+    fn test_write_read() {
+        let data: Vec<u8> = (0..2600).map(|i| i as u8).collect();
+        let mut writer = GuppiWriter::new(vec![], guppi()).unwrap();
+        writer.write_all(&data[..100]).unwrap();
+        writer.write_all(&data[100..]).unwrap();
+        assert_eq!(writer.blocks(), 2);
+        let bytes = writer.finish().unwrap();
+        assert_eq!(bytes.len() % DIRECTIO_ALIGN, 0);
+
+        let mut reader = GuppiReader::new(Cursor::new(&bytes)).unwrap();
+        let mut blocks = vec![];
+        while let Some((header, block)) = reader.next_block().unwrap() {
+            blocks.push((header.get("PKTIDX").unwrap().to_owned(), block.to_vec()));
+        }
+        assert_eq!(blocks, [
+            ("32".to_owned(), data[..1024].to_vec()),
+            ("48".to_owned(), data[1024..2048].to_vec()),
+            ("64".to_owned(), data[2048..].to_vec()),
+        ]);
+
+        let mut read = vec![];
+        GuppiReader::new(Cursor::new(&bytes))
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, data);
+    }
+
+    #[test]
+    fn test_gap_fill() {
+        let mut header = guppi();
+        header.set("DIRECTIO", 0);
+        let mut writer = GuppiWriter::new(vec![], header).unwrap();
+        writer.write_block(&[1; 1024]).unwrap();
+        // Skip half a block's worth of packets
+        writer.pktidx += 8;
+        writer.write_block(&[2; 1024]).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let mut read = vec![];
+        GuppiReader::new(Cursor::new(bytes))
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        let expected: Vec<u8> = [vec![1; 1024], vec![0; 512], vec![2; 1024]].concat();
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn test_max_gap() {
+        let mut header = guppi();
+        header.set("DIRECTIO", 0);
+        let mut writer = GuppiWriter::new(vec![], header).unwrap();
+        writer.write_block(&[1; 1024]).unwrap();
+        writer.pktidx += 8;
+        writer.write_block(&[2; 1024]).unwrap();
+        // Without the limit, this would be more zeros than fit in a u64
+        writer.pktidx = u64::MAX - 16;
+        writer.write_block(&[3; 1024]).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        // Half a block of zeros is too many
+        let mut reader = GuppiReader::new(Cursor::new(&bytes)).unwrap().max_gap(511);
+        let mut read = vec![0; 1024];
+        reader.read_exact(&mut read).unwrap();
+        assert_eq!(
+            reader.read(&mut read).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut reader = GuppiReader::new(Cursor::new(&bytes)).unwrap().max_gap(512);
+        let mut read = vec![0; 2560];
+        reader.read_exact(&mut read).unwrap();
+        assert_eq!(
+            reader.read(&mut read).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut header = guppi();
+        header.set("PKTIDX", u64::MAX);
+        assert_eq!(
+            StandardHeader::try_from(&header),
+            Err(value_error("PKTIDX", u64::MAX))
+        );
+        header.set("PKTIDX", 0);
+        header.set("STT_SMJD", u64::MAX);
+        assert_eq!(
+            StandardHeader::try_from(&header),
+            Err(value_error("STT_SMJD", u64::MAX))
+        );
+        let mut dada = standard();
+        dada.obs_offset = Some(u64::MAX);
+        assert_eq!(
+            GuppiHeader::try_from(&dada),
+            Err(value_error("OBS_OFFSET", u64::MAX))
+        );
+    }
+
+    #[test]
+    fn test_ring_round_trip() {
+        let data: Vec<u8> = (0..3000).map(|i| (i * 7) as u8).collect();
+        let mut writer = GuppiWriter::new(vec![], guppi()).unwrap();
+        writer.write_all(&data).unwrap();
+        let file = writer.finish().unwrap();
+
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_bufs(8)
+            .buf_size(512)
+            .build()
+            .unwrap();
+        let (mut hc, mut dc) = client.split();
+        assert_eq!(
+            play_guppi(Cursor::new(&file), &mut hc, &mut dc).unwrap(),
+            3000
+        );
+        let mut out = vec![];
+        assert_eq!(record_guppi(&mut hc, &mut dc, &mut out).unwrap(), 3000);
+        // BLOCSIZE, DIRECTIO and PIPERBLK came along, so the layout is the same
+        assert_eq!(out.len(), file.len());
+        let mut read = vec![];
+        GuppiReader::new(Cursor::new(out))
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, data);
+    }
+}
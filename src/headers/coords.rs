@@ -0,0 +1,303 @@
+//! Sky coordinates as they appear in DADA headers.
+//!
+//! `RA` is written in hours (`hh:mm:ss.s`) and `DEC` in degrees (`±dd:mm:ss.s`). Both are stored here as an integer number of
+//! nanoseconds (of time or arc, respectively) alongside the number of decimal places they were written with, so that parsing and
+//! formatting a coordinate gives back exactly the same string. Up to nine decimal places are kept, the rest are truncated.
+
+use std::{f64::consts::PI, fmt, str::FromStr};
+
+use crate::errors::PsrdadaError;
+
+const NANOS_PER_MINUTE: u64 = 60_000_000_000;
+const NANOS_PER_UNIT: u64 = 60 * NANOS_PER_MINUTE;
+
+/// The magnitude of a sexagesimal value, in nanoseconds of the last field
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+struct Sexagesimal {
+    negative: bool,
+    nanos: u64,
+    /// Decimal places of the seconds field
+    precision: u8,
+}
+
+impl Sexagesimal {
+    fn parse(s: &str, signed: bool) -> Result<Self, PsrdadaError> {
+        let err = || PsrdadaError::AngleParseError;
+        let s = s.trim();
+        let (negative, s) = match (signed, s.strip_prefix('-'), s.strip_prefix('+')) {
+            (true, Some(rest), _) => (true, rest),
+            (true, _, Some(rest)) => (false, rest),
+            _ => (false, s),
+        };
+        let fields: Vec<_> = s.split(':').collect();
+        let &[units, minutes, seconds] = &fields[..] else {
+            return Err(err());
+        };
+        let (seconds, frac) = match seconds.split_once('.') {
+            Some((_, "")) => return Err(err()),
+            Some(split) => split,
+            None => (seconds, ""),
+        };
+        let digits = |f: &str| -> Result<u64, PsrdadaError> {
+            if f.is_empty() || !f.bytes().all(|c| c.is_ascii_digit()) {
+                return Err(err());
+            }
+            f.parse().map_err(|_| err())
+        };
+        let (units, minutes, seconds) = (digits(units)?, digits(minutes)?, digits(seconds)?);
+        if minutes >= 60 || seconds >= 60 {
+            return Err(err());
+        }
+        let (frac_nanos, precision) = if frac.is_empty() {
+            (0, 0)
+        } else {
+            let frac = &frac[..frac.len().min(9)];
+            (
+                digits(frac)? * 10u64.pow(9 - frac.len() as u32),
+                frac.len() as u8,
+            )
+        };
+        let nanos = units
+            .checked_mul(NANOS_PER_UNIT)
+            .and_then(|nanos| nanos.checked_add(minutes * NANOS_PER_MINUTE))
+            .and_then(|nanos| nanos.checked_add(seconds * 1_000_000_000))
+            .and_then(|nanos| nanos.checked_add(frac_nanos))
+            .ok_or_else(err)?;
+        Ok(Self {
+            negative,
+            nanos,
+            precision,
+        })
+    }
+
+    fn from_units(units: f64, precision: u8) -> Self {
+        let precision = precision.min(9);
+        // Round to the precision we'll print with
+        let step = 10f64.powi(9 - precision as i32);
+        let nanos = ((units.abs() * NANOS_PER_UNIT as f64) / step).round() * step;
+        Self {
+            negative: units < 0.0,
+            nanos: nanos as u64,
+            precision,
+        }
+    }
+
+    fn units(&self) -> f64 {
+        let magnitude = self.nanos as f64 / NANOS_PER_UNIT as f64;
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>, sign: bool) -> fmt::Result {
+        if sign {
+            f.write_str(if self.negative { "-" } else { "+" })?;
+        }
+        let units = self.nanos / NANOS_PER_UNIT;
+        let minutes = self.nanos % NANOS_PER_UNIT / NANOS_PER_MINUTE;
+        let seconds = self.nanos % NANOS_PER_MINUTE / 1_000_000_000;
+        write!(f, "{units:02}:{minutes:02}:{seconds:02}")?;
+        if self.precision > 0 {
+            let frac = self.nanos % 1_000_000_000 / 10u64.pow(9 - self.precision as u32);
+            write!(f, ".{:0width$}", frac, width = self.precision as usize)?;
+        }
+        Ok(())
+    }
+}
+
+/// A right ascension, written as `hh:mm:ss.s`
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub struct RightAscension(Sexagesimal);
+
+impl RightAscension {
+    /// Create from an angle in hours, to be formatted with `precision` decimal places on the seconds
+    pub fn from_hours(hours: f64, precision: u8) -> Self {
+        let mut ra = Sexagesimal::from_units(hours.rem_euclid(24.0), precision);
+        // Rounding could have taken us to 24h
+        ra.nanos %= 24 * NANOS_PER_UNIT;
+        Self(ra)
+    }
+
+    /// Create from an angle in degrees, to be formatted with `precision` decimal places on the seconds
+    pub fn from_degrees(degrees: f64, precision: u8) -> Self {
+        Self::from_hours(degrees / 15.0, precision)
+    }
+
+    /// Create from an angle in radians, to be formatted with `precision` decimal places on the seconds
+    pub fn from_radians(radians: f64, precision: u8) -> Self {
+        Self::from_degrees(radians.to_degrees(), precision)
+    }
+
+    /// The angle in hours
+    pub fn hours(&self) -> f64 {
+        self.0.units()
+    }
+
+    /// The angle in degrees
+    pub fn degrees(&self) -> f64 {
+        self.hours() * 15.0
+    }
+
+    /// The angle in radians
+    pub fn radians(&self) -> f64 {
+        self.hours() * PI / 12.0
+    }
+}
+
+impl FromStr for RightAscension {
+    type Err = PsrdadaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ra = Sexagesimal::parse(s, false)?;
+        if ra.nanos >= 24 * NANOS_PER_UNIT {
+            return Err(PsrdadaError::AngleParseError);
+        }
+        Ok(Self(ra))
+    }
+}
+
+impl fmt::Display for RightAscension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f, false)
+    }
+}
+
+/// A declination, written as `±dd:mm:ss.s`
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub struct Declination(Sexagesimal);
+
+impl Declination {
+    /// Create from an angle in degrees, to be formatted with `precision` decimal places on the seconds.
+    ///
+    /// Returns `None` if the angle is outside of ±90 degrees.
+    pub fn from_degrees(degrees: f64, precision: u8) -> Option<Self> {
+        if degrees.abs() > 90.0 {
+            return None;
+        }
+        Some(Self(Sexagesimal::from_units(degrees, precision)))
+    }
+
+    /// Create from an angle in radians, to be formatted with `precision` decimal places on the seconds
+    ///
+    /// Returns `None` if the angle is outside of ±π/2.
+    pub fn from_radians(radians: f64, precision: u8) -> Option<Self> {
+        Self::from_degrees(radians.to_degrees(), precision)
+    }
+
+    /// The angle in degrees
+    pub fn degrees(&self) -> f64 {
+        self.0.units()
+    }
+
+    /// The angle in radians
+    pub fn radians(&self) -> f64 {
+        self.degrees().to_radians()
+    }
+}
+
+impl FromStr for Declination {
+    type Err = PsrdadaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let dec = Sexagesimal::parse(s, true)?;
+        if dec.nanos > 90 * NANOS_PER_UNIT {
+            return Err(PsrdadaError::AngleParseError);
+        }
+        Ok(Self(dec))
+    }
+}
+
+/// Always includes the sign
+impl fmt::Display for Declination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        for s in [
+            "19:39:38.56",
+            "04:37:15.8961737",
+            "00:00:00",
+            "23:59:59.999999999",
+        ] {
+            assert_eq!(s.parse::<RightAscension>().unwrap().to_string(), s);
+        }
+        for s in [
+            "+21:34:59.1",
+            "-47:15:09.110714",
+            "-00:30:00.0",
+            "+90:00:00",
+        ] {
+            assert_eq!(s.parse::<Declination>().unwrap().to_string(), s);
+        }
+        // Unsigned declinations are positive
+        assert_eq!(
+            "21:34:59.1".parse::<Declination>().unwrap().to_string(),
+            "+21:34:59.1"
+        );
+    }
+
+    #[test]
+    fn test_conversions() {
+        let ra: RightAscension = "19:39:38.56".parse().unwrap();
+        assert!((ra.degrees() - 294.910_666_666_666_7).abs() < 1e-9);
+        assert!((ra.radians() - 294.910_666_666_666_7_f64.to_radians()).abs() < 1e-12);
+        assert_eq!(
+            RightAscension::from_degrees(ra.degrees(), 2).to_string(),
+            "19:39:38.56"
+        );
+        assert_eq!(RightAscension::from_hours(-1.0, 0).to_string(), "23:00:00");
+
+        let dec: Declination = "-47:15:09.110714".parse().unwrap();
+        assert!((dec.degrees() + 47.252_530_753_888_9).abs() < 1e-9);
+        assert_eq!(
+            Declination::from_radians(dec.radians(), 6)
+                .unwrap()
+                .to_string(),
+            "-47:15:09.110714"
+        );
+        assert_eq!(Declination::from_degrees(91.0, 0), None);
+    }
+
+    #[test]
+    fn test_bad_angles() {
+        for s in [
+            "",
+            "19:39",
+            "24:00:00",
+            "19:60:00",
+            "19:39:38.",
+            "+19:39:38",
+            "1a:00:00",
+            // The hours fit in a u64 of nanoseconds, but not with the minutes and seconds on top
+            "5124095:59:59.9",
+        ] {
+            assert_eq!(
+                s.parse::<RightAscension>(),
+                Err(PsrdadaError::AngleParseError),
+                "{s}"
+            );
+        }
+        for s in [
+            "+90:00:00.1",
+            "+21:34",
+            "--21:34:59",
+            "+21:34:60",
+            "+5124095:59:59",
+        ] {
+            assert_eq!(
+                s.parse::<Declination>(),
+                Err(PsrdadaError::AngleParseError),
+                "{s}"
+            );
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Where and why the bytes of a header failed to parse.
+//!
+//! Headers are parsed a line at a time, so a bad line can be pinned down to a line number and column, and in lenient mode
+//! (see [`bytes_to_header_lenient`](super::bytes_to_header_lenient)) skipped without losing the rest of the header.
+
+use std::{fmt, str};
+
+use nom::{
+    character::complete::space0,
+    combinator::{eof, opt},
+    sequence::{preceded, tuple},
+};
+
+use super::{comment, line_content, token};
+
+/// The ways a line of a header can be malformed
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum ParseErrorKind {
+    /// The line isn't valid UTF-8
+    InvalidUtf8,
+    /// A key without a value
+    MissingValue,
+    /// Something other than padding after a `\0`, which would truncate the header for the C library
+    EmbeddedNul,
+    /// A character that can't appear in a header line, like a lone carriage return
+    UnexpectedCharacter,
+}
+
+/// A line of a header that couldn't be parsed
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct ParseDiagnostic {
+    /// The line number, starting from 1
+    pub line: usize,
+    /// The column (in bytes) where things went wrong, starting from 1
+    pub column: usize,
+    /// The offending line, without its line ending. For [`ParseErrorKind::EmbeddedNul`], this is the rest of the line after the `\0`.
+    pub bytes: Vec<u8>,
+    /// What went wrong
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            ParseErrorKind::InvalidUtf8 => "invalid UTF-8",
+            ParseErrorKind::MissingValue => "missing value",
+            ParseErrorKind::EmbeddedNul => "content after an embedded NUL",
+            ParseErrorKind::UnexpectedCharacter => "unexpected character",
+        };
+        write!(
+            f,
+            "{what} at line {}, column {}: {:?}",
+            self.line,
+            self.column,
+            String::from_utf8_lossy(&self.bytes)
+        )
+    }
+}
+
+type Pair<'a> = (&'a str, &'a str, Option<&'a str>);
+
+/// A line of a header that parsed successfully
+pub(crate) struct HeaderLine<'a> {
+    /// The line, without its line ending
+    pub(crate) raw: &'a str,
+    pub(crate) ending: &'static str,
+    /// The key, value and comment if this line has a pair
+    pub(crate) pair: Option<Pair<'a>>,
+}
+
+/// Parse a single line (without its line ending), or get the column and kind of the problem
+fn parse_line(raw: &[u8]) -> Result<(&str, Option<Pair<'_>>), (usize, ParseErrorKind)> {
+    let text =
+        str::from_utf8(raw).map_err(|e| (e.valid_up_to() + 1, ParseErrorKind::InvalidUtf8))?;
+    // Safety of the unwraps: we're slicing a valid str on ASCII boundaries
+    let to_str = |b| str::from_utf8(b).unwrap();
+    if let Ok((b"", content)) = line_content(raw) {
+        return Ok((
+            text,
+            content.map(|(k, v, c)| (to_str(k), to_str(v), c.map(to_str))),
+        ));
+    }
+    // A lone key, perhaps followed by a comment
+    if let Ok((rest, _)) = preceded(space0, token)(raw) {
+        if tuple((space0, opt(comment), eof))(rest).is_ok() {
+            return Err((raw.len() - rest.len() + 1, ParseErrorKind::MissingValue));
+        }
+    }
+    // Tokens and values run up to line endings, so the only thing left to trip over is a stray carriage return
+    let column = raw.iter().position(|c| *c == b'\r').unwrap_or_default() + 1;
+    Err((column, ParseErrorKind::UnexpectedCharacter))
+}
+
+/// Parse every line of a header, collecting the ones that parsed and diagnostics for the ones that didn't
+pub(crate) fn parse_lines(bytes: &[u8]) -> (Vec<HeaderLine<'_>>, Vec<ParseDiagnostic>) {
+    let end = bytes.iter().position(|c| *c == 0).unwrap_or(bytes.len());
+    let (text, tail) = bytes.split_at(end);
+    let mut lines = vec![];
+    let mut diagnostics = vec![];
+    for (idx, raw) in text.split_inclusive(|c| *c == b'\n').enumerate() {
+        let (raw, ending) = if let Some(raw) = raw.strip_suffix(b"\r\n") {
+            (raw, "\r\n")
+        } else if let Some(raw) = raw.strip_suffix(b"\n") {
+            (raw, "\n")
+        } else {
+            (raw, "")
+        };
+        match parse_line(raw) {
+            Ok((raw, pair)) => lines.push(HeaderLine { raw, ending, pair }),
+            Err((column, kind)) => diagnostics.push(ParseDiagnostic {
+                line: idx + 1,
+                column,
+                bytes: raw.to_vec(),
+                kind,
+            }),
+        }
+    }
+    // Trailing nulls are padding, but anything else after one would be lost
+    if let Some(offset) = tail.iter().position(|c| *c != 0) {
+        let stray = &tail[offset..];
+        let stray = &stray[..stray
+            .iter()
+            .position(|c| matches!(c, b'\0' | b'\r' | b'\n'))
+            .unwrap_or(stray.len())];
+        let line_start = text.iter().rposition(|c| *c == b'\n').map_or(0, |i| i + 1);
+        diagnostics.push(ParseDiagnostic {
+            line: text.iter().filter(|c| **c == b'\n').count() + 1,
+            column: end - line_start + 1,
+            bytes: stray.to_vec(),
+            kind: ParseErrorKind::EmbeddedNul,
+        });
+    }
+    (lines, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnose(bytes: &[u8]) -> Vec<ParseDiagnostic> {
+        parse_lines(bytes).1
+    }
+
+    #[test]
+    fn test_missing_value() {
+        assert_eq!(diagnose(b"FOO BAR\r\n  KEY   # no value\nBAZ QUUZ"), [
+            ParseDiagnostic {
+                line: 2,
+                column: 6,
+                bytes: b"  KEY   # no value".to_vec(),
+                kind: ParseErrorKind::MissingValue,
+            }
+        ]);
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        assert_eq!(diagnose(b"FOO BAR\nSOURCE J\xff1939\n"), [
+            ParseDiagnostic {
+                line: 2,
+                column: 9,
+                bytes: b"SOURCE J\xff1939".to_vec(),
+                kind: ParseErrorKind::InvalidUtf8,
+            }
+        ]);
+    }
+
+    #[test]
+    fn test_embedded_nul() {
+        // Padding is fine
+        assert!(diagnose(b"FOO BAR\n\0\0\0").is_empty());
+        assert_eq!(diagnose(b"FOO BAR\nBAZ\0 QUUZ\nA B\0\0"), [
+            ParseDiagnostic {
+                line: 2,
+                column: 4,
+                bytes: b"BAZ".to_vec(),
+                kind: ParseErrorKind::MissingValue,
+            },
+            ParseDiagnostic {
+                line: 2,
+                column: 4,
+                bytes: b" QUUZ".to_vec(),
+                kind: ParseErrorKind::EmbeddedNul,
+            }
+        ]);
+    }
+
+    #[test]
+    fn test_carriage_return() {
+        let diagnostic = &diagnose(b"FOO BAR\rBAZ QUUZ\n")[0];
+        assert_eq!(diagnostic.kind, ParseErrorKind::UnexpectedCharacter);
+        assert_eq!((diagnostic.line, diagnostic.column), (1, 8));
+        assert_eq!(
+            diagnostic.to_string(),
+            "unexpected character at line 1, column 8: \"FOO BAR\\rBAZ QUUZ\""
+        );
+    }
+}
@@ -0,0 +1,669 @@
+//! A [serde](https://serde.rs) data format for DADA headers, enabled with the `serde` feature.
+//!
+//! This lets instrument-specific headers be described with plain structs
+//!
+//! ```rust
+//! use std::collections::HashMap;
+//!
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, PartialEq, Serialize, Deserialize)]
+//! struct MyHeader {
+//!     #[serde(rename = "NCHAN")]
+//!     nchan: u32,
+//!     #[serde(rename = "CAL_ON")]
+//!     cal_on: bool,
+//!     #[serde(rename = "SOURCE")]
+//!     source: Option<String>,
+//!     #[serde(flatten)]
+//!     extras: HashMap<String, String>,
+//! }
+//!
+//! let header: MyHeader =
+//!     psrdada::headers::format::from_bytes(b"NCHAN 1024\nCAL_ON true\nMODE PSR\n").unwrap();
+//! assert_eq!(header.nchan, 1024);
+//! assert_eq!(header.source, None);
+//! assert_eq!(header.extras["MODE"], "PSR");
+//! ```
+//!
+//! The top level must be a struct or map, and every value must be a "scalar": a number, boolean, string, character,
+//! unit enum variant, or an option/newtype of one of those. A `None` is serialized by leaving out the key entirely.
+//! Booleans are written as `true` and `false`, but `1` and `0` are accepted as well.
+//!
+//! As with [`bytes_to_header`](super::bytes_to_header), anything after the first `\0` is ignored. If a key is repeated,
+//! the first occurrence wins (matching `ascii_header_get` from the C library).
+
+use std::{collections::HashSet, fmt, str};
+
+use serde::{
+    de::{self, value::BorrowedStrDeserializer, DeserializeOwned, IntoDeserializer, Visitor},
+    forward_to_deserialize_any,
+    ser::{self, Impossible},
+    Deserialize, Serialize,
+};
+
+use super::{header, validate_entry};
+use crate::{
+    client::HeaderClient,
+    errors::{PsrdadaError, PsrdadaResult},
+};
+
+/// Errors from serializing or deserializing a header
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+impl From<Error> for PsrdadaError {
+    fn from(e: Error) -> Self {
+        PsrdadaError::HeaderSerdeError(e.0)
+    }
+}
+
+/// Deserialize a `T` from the bytes of a header
+pub fn from_bytes<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T, Error> {
+    let pairs = header(bytes).map_err(|d| Error(d.to_string()))?;
+    let mut seen = HashSet::new();
+    let mut strs = vec![];
+    for (k, v) in pairs {
+        if seen.insert(k) {
+            strs.push((k, v));
+        }
+    }
+    T::deserialize(Deserializer {
+        pairs: strs.into_iter(),
+        value: None,
+    })
+}
+
+/// Serialize a `T` into the bytes of a header
+///
+/// Every serialized entry is checked with [`validate_entry`], failing if it couldn't be parsed back.
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    let pairs = to_pairs(value)?;
+    for (k, v) in &pairs {
+        validate_entry(k, v).map_err(|_| Error(format!("Invalid header entry {k:?} {v:?}")))?;
+    }
+    Ok(pairs_to_bytes(&pairs))
+}
+
+fn to_pairs<T: Serialize + ?Sized>(value: &T) -> Result<Vec<(String, String)>, Error> {
+    let mut serializer = Serializer {
+        pairs: vec![],
+        key: None,
+    };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.pairs)
+}
+
+fn pairs_to_bytes(pairs: &[(String, String)]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (k, v) in pairs {
+        bytes.extend(k.as_bytes());
+        bytes.extend(b" ");
+        bytes.extend(v.as_bytes());
+        bytes.extend(b"\n");
+    }
+    bytes
+}
+
+/// Deserializer over the key/value pairs of a header
+struct Deserializer<'de> {
+    pairs: std::vec::IntoIter<(&'de str, &'de str)>,
+    value: Option<(&'de str, &'de str)>,
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.pairs.next() {
+            Some((k, v)) => {
+                self.value = Some((k, v));
+                seed.deserialize(BorrowedStrDeserializer::new(k)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let (key, value) = self
+            .value
+            .take()
+            .ok_or_else(|| Error("Value requested before key".to_owned()))?;
+        seed.deserialize(ValueDeserializer { key, value })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.pairs.len())
+    }
+}
+
+/// Deserializer for a single value, which knows its key for the sake of error messages
+struct ValueDeserializer<'de> {
+    key: &'de str,
+    value: &'de str,
+}
+
+impl ValueDeserializer<'_> {
+    fn parse<T: str::FromStr>(&self) -> Result<T, Error> {
+        self.value.parse().map_err(|_| {
+            Error(format!(
+                "Invalid value {:?} for key {}, expected {}",
+                self.value,
+                self.key,
+                std::any::type_name::<T>()
+            ))
+        })
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.value)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            "true" | "1" => visitor.visit_bool(true),
+            "false" | "0" => visitor.visit_bool(false),
+            _ => Err(Error(format!(
+                "Invalid value {:?} for key {}, expected a boolean",
+                self.value, self.key
+            ))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        // Missing keys are None, so if we got here, it's Some
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self.value.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Serializer for a whole header, collecting key/value pairs in order
+struct Serializer {
+    pairs: Vec<(String, String)>,
+    /// The key of a map entry whose value we're waiting for
+    key: Option<String>,
+}
+
+fn top_level_error<T>() -> Result<T, Error> {
+    Err(Error("A header must be a struct or a map".to_owned()))
+}
+
+macro_rules! not_top_level {
+    ($($method:ident($($arg:ty),*) -> $ret:ty,)*) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<$ret, Error> {
+                top_level_error()
+            }
+        )*
+    };
+}
+
+impl<'a> ser::Serializer for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = &'a mut Serializer;
+    type SerializeStruct = &'a mut Serializer;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    not_top_level! {
+        serialize_bool(bool) -> (),
+        serialize_i8(i8) -> (),
+        serialize_i16(i16) -> (),
+        serialize_i32(i32) -> (),
+        serialize_i64(i64) -> (),
+        serialize_u8(u8) -> (),
+        serialize_u16(u16) -> (),
+        serialize_u32(u32) -> (),
+        serialize_u64(u64) -> (),
+        serialize_f32(f32) -> (),
+        serialize_f64(f64) -> (),
+        serialize_char(char) -> (),
+        serialize_str(&str) -> (),
+        serialize_bytes(&[u8]) -> (),
+        serialize_none() -> (),
+        serialize_unit() -> (),
+        serialize_unit_struct(&'static str) -> (),
+        serialize_unit_variant(&'static str, u32, &'static str) -> (),
+        serialize_seq(Option<usize>) -> Self::SerializeSeq,
+        serialize_tuple(usize) -> Self::SerializeTuple,
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct,
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant,
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant,
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), Error> {
+        top_level_error()
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Ok(self)
+    }
+}
+
+impl Serializer {
+    fn push<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<(), Error> {
+        if let Some(value) = value.serialize(ValueSerializer { key: &key })? {
+            self.pairs.push((key, value));
+        }
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.push(key.to_owned(), value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(
+            key.serialize(ValueSerializer { key: "" })?
+                .ok_or_else(|| Error("Map keys can't be None".to_owned()))?,
+        );
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error("Value serialized before key".to_owned()))?;
+        self.push(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Serializer for a single value, returning `None` if the key should be left out
+struct ValueSerializer<'a> {
+    key: &'a str,
+}
+
+impl ValueSerializer<'_> {
+    fn unsupported<T>(&self, what: &str) -> Result<T, Error> {
+        Err(Error(format!(
+            "Can't serialize {what} as the value for key {}",
+            self.key
+        )))
+    }
+}
+
+macro_rules! serialize_display {
+    ($($method:ident($ty:ty),)*) => {
+        $(
+            fn $method(self, v: $ty) -> Result<Self::Ok, Error> {
+                Ok(Some(v.to_string()))
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for ValueSerializer<'_> {
+    type Ok = Option<String>;
+    type Error = Error;
+    type SerializeSeq = Impossible<Self::Ok, Error>;
+    type SerializeTuple = Impossible<Self::Ok, Error>;
+    type SerializeTupleStruct = Impossible<Self::Ok, Error>;
+    type SerializeTupleVariant = Impossible<Self::Ok, Error>;
+    type SerializeMap = Impossible<Self::Ok, Error>;
+    type SerializeStruct = Impossible<Self::Ok, Error>;
+    type SerializeStructVariant = Impossible<Self::Ok, Error>;
+
+    serialize_display! {
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_u128(u128),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Error> {
+        self.unsupported("bytes")
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Error> {
+        self.unsupported("unit")
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Error> {
+        self.unsupported("a unit struct")
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Error> {
+        Ok(Some(variant.to_owned()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Error> {
+        self.unsupported("a newtype variant")
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        self.unsupported("a sequence")
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        self.unsupported("a tuple")
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        self.unsupported("a tuple struct")
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        self.unsupported("a tuple variant")
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        self.unsupported("a map")
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        self.unsupported("a struct")
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        self.unsupported("a struct variant")
+    }
+}
+
+impl HeaderClient<'_> {
+    /// Read a block of header data from the header ringbuffer into any deserializable type
+    pub fn read_typed_header<T: DeserializeOwned>(&mut self) -> PsrdadaResult<T> {
+        Ok(from_bytes(&self.read_header_bytes(false)?)?)
+    }
+
+    /// Write any serializable type into the header ringbuffer
+    ///
+    /// This is checked in the same way as [`HeaderClient::write_header`].
+    pub fn write_typed_header<T: Serialize + ?Sized>(&mut self, header: &T) -> PsrdadaResult<()> {
+        let pairs = to_pairs(header)?;
+        for (k, v) in &pairs {
+            validate_entry(k, v)?;
+        }
+        self.write_header_bytes(&pairs_to_bytes(&pairs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, tests::next_key};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Mode {
+        Psr,
+        Cal,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    struct Instrument {
+        nchan: u32,
+        freq: f64,
+        bw: f32,
+        cal_on: bool,
+        source: Option<String>,
+        obs_offset: Option<u64>,
+        mode: Mode,
+        #[serde(rename = "MY_FLAG")]
+        flag: char,
+        #[serde(flatten)]
+        extras: HashMap<String, String>,
+    }
+
+    fn instrument() -> Instrument {
+        Instrument {
+            nchan: 1024,
+            freq: 1382.5,
+            bw: -400.0,
+            cal_on: false,
+            source: Some("J1939 +2134".to_owned()),
+            obs_offset: None,
+            mode: Mode::Psr,
+            flag: 'x',
+            extras: HashMap::from([("RECEIVER".to_owned(), "Multibeam".to_owned())]),
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        assert_eq!(
+            to_bytes(&instrument()).unwrap(),
+            b"NCHAN 1024\nFREQ 1382.5\nBW -400\nCAL_ON false\nSOURCE J1939 +2134\nMODE psr\nMY_FLAG x\nRECEIVER Multibeam\n"
+        );
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let hdr = b"# Comment\nNCHAN 1024\nFREQ   1382.5 # MHz\nBW -400\nCAL_ON 0\nSOURCE J1939 +2134\nSOURCE ignored\nMODE psr\nMY_FLAG x\nRECEIVER Multibeam\n\0\0\0";
+        assert_eq!(from_bytes::<Instrument>(hdr).unwrap(), instrument());
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = from_bytes::<Instrument>(b"NCHAN many\n").unwrap_err();
+        assert!(err.to_string().contains("NCHAN"), "{err}");
+        let err = from_bytes::<Instrument>(b"NCHAN 1\nFREQ 1\nBW 1\nCAL_ON maybe\n").unwrap_err();
+        assert!(err.to_string().contains("CAL_ON"), "{err}");
+        let err = from_bytes::<Instrument>(b"NCHAN 1\n").unwrap_err();
+        assert!(err.to_string().contains("FREQ"), "{err}");
+        assert!(to_bytes(&[1, 2, 3]).is_err());
+        assert!(to_bytes(&HashMap::from([("A", vec![1])])).is_err());
+        let err = to_bytes(&HashMap::from([("A", "1 # 2")])).unwrap_err();
+        assert!(err.to_string().contains("1 # 2"), "{err}");
+    }
+
+    #[test]
+    fn test_roundtrip_typed_header() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key).build().unwrap();
+        let (mut hc, _) = client.split();
+        hc.write_typed_header(&instrument()).unwrap();
+        assert_eq!(hc.read_typed_header::<Instrument>().unwrap(), instrument());
+    }
+}
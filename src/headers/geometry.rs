@@ -0,0 +1,249 @@
+//! Consistency checks between a header and the ringbuffers it describes.
+//!
+//! By convention, the first `HDR_SIZE` bytes of a header block are the header (this is what ends up at the start of a `.dada` file),
+//! and the rest of the keywords describe how to interpret the bytes in the data ring. None of this is checked by the C library, so a
+//! typo in `NCHAN` would otherwise only show up as garbage somewhere downstream. [`validate_geometry`] checks what it can, and
+//! reports missing keywords (that prevented a check) as warnings.
+
+use super::standard::StandardHeader;
+use crate::client::HduClient;
+
+/// Anything [`validate_geometry`] found wrong with a header
+#[derive(Debug, PartialEq, Clone)]
+pub enum GeometryIssue {
+    /// A keyword needed for a check is missing
+    MissingKey(&'static str),
+    /// A keyword that needs to be non-zero is zero
+    ZeroValue(&'static str),
+    /// `HDR_SIZE` is larger than a header block
+    HdrSizeTooLarge { hdr_size: u64, block_size: u64 },
+    /// `NBIT * NCHAN * NPOL * NDIM / TSAMP` doesn't agree with `BYTES_PER_SECOND`
+    DataRateMismatch {
+        /// The rate implied by the sample geometry, in bytes per second
+        expected: f64,
+        bytes_per_second: u64,
+    },
+    /// A data block doesn't hold a whole number of samples
+    PartialSample {
+        block_size: u64,
+        /// `NBIT * NCHAN * NPOL * NDIM`
+        bits_per_sample: u64,
+    },
+    /// `RESOLUTION` doesn't divide the data block size
+    ResolutionMismatch { resolution: u64, block_size: u64 },
+}
+
+/// The result of [`validate_geometry`]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct GeometryReport {
+    /// Things that prevented a check from happening
+    pub warnings: Vec<GeometryIssue>,
+    /// Inconsistencies between the header and the rings
+    pub errors: Vec<GeometryIssue>,
+}
+
+impl GeometryReport {
+    /// Whether there were no errors (there may still be warnings)
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    fn warn_missing(&mut self, key: &'static str) {
+        let issue = GeometryIssue::MissingKey(key);
+        if !self.warnings.contains(&issue) {
+            self.warnings.push(issue);
+        }
+    }
+
+    // Get the value of a keyword that has to be present and non-zero for a check
+    fn require<T: Copy + Default + PartialEq>(
+        &mut self,
+        key: &'static str,
+        value: Option<T>,
+    ) -> Option<T> {
+        match value {
+            None => {
+                self.warn_missing(key);
+                None
+            }
+            Some(v) if v == T::default() => {
+                let issue = GeometryIssue::ZeroValue(key);
+                if !self.errors.contains(&issue) {
+                    self.errors.push(issue);
+                }
+                None
+            }
+            Some(v) => Some(v),
+        }
+    }
+}
+
+/// Check a header against the sizes of the header and data blocks it will be written to (or was read from).
+///
+/// The data rate is considered consistent if it agrees to within a part per million (or one byte per second),
+/// to allow for `TSAMP` being written with limited precision.
+pub fn validate_geometry(
+    header: &StandardHeader,
+    header_block_size: u64,
+    data_block_size: u64,
+) -> GeometryReport {
+    let mut report = GeometryReport::default();
+
+    if let Some(hdr_size) = report.require("HDR_SIZE", header.hdr_size) {
+        if hdr_size > header_block_size {
+            report.errors.push(GeometryIssue::HdrSizeTooLarge {
+                hdr_size,
+                block_size: header_block_size,
+            });
+        }
+    }
+
+    let nbit = report.require("NBIT", header.nbit);
+    let nchan = report.require("NCHAN", header.nchan);
+    let npol = report.require("NPOL", header.npol);
+    let ndim = report.require("NDIM", header.ndim);
+    let bits_per_sample = match (nbit, nchan, npol, ndim) {
+        (Some(nbit), Some(nchan), Some(npol), Some(ndim)) => {
+            Some(nbit as u64 * nchan as u64 * npol as u64 * ndim as u64)
+        }
+        _ => None,
+    };
+
+    if let Some(bits_per_sample) = bits_per_sample {
+        if (data_block_size * 8) % bits_per_sample != 0 {
+            report.errors.push(GeometryIssue::PartialSample {
+                block_size: data_block_size,
+                bits_per_sample,
+            });
+        }
+        let tsamp = report.require("TSAMP", header.tsamp);
+        let bytes_per_second = report.require("BYTES_PER_SECOND", header.bytes_per_second);
+        if let (Some(tsamp), Some(bytes_per_second)) = (tsamp, bytes_per_second) {
+            // TSAMP is in microseconds
+            let expected = bits_per_sample as f64 / 8.0 / (tsamp * 1e-6);
+            let tolerance = (expected * 1e-6).max(1.0);
+            if (expected - bytes_per_second as f64).abs() > tolerance {
+                report.errors.push(GeometryIssue::DataRateMismatch {
+                    expected,
+                    bytes_per_second,
+                });
+            }
+        }
+    }
+
+    if let Some(resolution) = report.require("RESOLUTION", header.resolution) {
+        if data_block_size % resolution != 0 {
+            report.errors.push(GeometryIssue::ResolutionMismatch {
+                resolution,
+                block_size: data_block_size,
+            });
+        }
+    }
+
+    report
+}
+
+impl HduClient {
+    /// Check a header against the block sizes of this client's rings with [`validate_geometry`]
+    pub fn validate_geometry(&self, header: &StandardHeader) -> GeometryReport {
+        validate_geometry(
+            header,
+            self.header_buf_size() as u64,
+            self.data_buf_size() as u64,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::DadaClientBuilder, tests::next_key};
+
+    fn header() -> StandardHeader {
+        StandardHeader {
+            hdr_size: Some(4096),
+            nbit: Some(8),
+            nchan: Some(1),
+            npol: Some(2),
+            ndim: Some(1),
+            tsamp: Some(0.0025),
+            bytes_per_second: Some(800_000_000),
+            resolution: Some(2),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_consistent() {
+        let report = validate_geometry(&header(), 4096, 1 << 16);
+        assert_eq!(report, GeometryReport::default());
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn test_inconsistent() {
+        let mut header = header();
+        header.hdr_size = Some(8192);
+        header.nchan = Some(3);
+        header.resolution = Some(1000);
+        let report = validate_geometry(&header, 4096, 1 << 16);
+        assert!(!report.is_ok());
+        assert_eq!(report.errors.len(), 4);
+        assert_eq!(report.errors[0], GeometryIssue::HdrSizeTooLarge {
+            hdr_size: 8192,
+            block_size: 4096
+        });
+        assert_eq!(report.errors[1], GeometryIssue::PartialSample {
+            block_size: 1 << 16,
+            bits_per_sample: 48
+        });
+        assert!(matches!(
+            report.errors[2],
+            GeometryIssue::DataRateMismatch {
+                expected,
+                bytes_per_second: 800_000_000
+            } if (expected - 2.4e9).abs() < 1.0
+        ));
+        assert_eq!(report.errors[3], GeometryIssue::ResolutionMismatch {
+            resolution: 1000,
+            block_size: 1 << 16
+        });
+    }
+
+    #[test]
+    fn test_missing_and_zero() {
+        let header = StandardHeader {
+            nbit: Some(0),
+            nchan: Some(1),
+            ..Default::default()
+        };
+        let report = validate_geometry(&header, 4096, 4096);
+        assert_eq!(report.errors, [GeometryIssue::ZeroValue("NBIT")]);
+        assert_eq!(report.warnings, [
+            GeometryIssue::MissingKey("HDR_SIZE"),
+            GeometryIssue::MissingKey("NPOL"),
+            GeometryIssue::MissingKey("NDIM"),
+            GeometryIssue::MissingKey("RESOLUTION"),
+        ]);
+    }
+
+    #[test]
+    fn test_client_geometry() {
+        let client = DadaClientBuilder::new(next_key())
+            .buf_size(999)
+            .header_size(4096)
+            .build()
+            .unwrap();
+        let report = client.validate_geometry(&header());
+        assert_eq!(report.errors, [
+            GeometryIssue::PartialSample {
+                block_size: 999,
+                bits_per_sample: 16
+            },
+            GeometryIssue::ResolutionMismatch {
+                resolution: 2,
+                block_size: 999
+            }
+        ]);
+    }
+}
@@ -0,0 +1,693 @@
+//! Implementation of header serialization and deserialization
+//!
+//! While the header ringbuffer is just like the data ringbuffer, there is "convention" that
+//! it contains key/value pairs of ASCII plaintext. Nothing about the C abstraction guarantees that,
+//! so we still have a public API for dealing with the raw bytes. However, this module will introduce some
+//! methods that implement the serde to and from these bytes and automatically read and write. These methods
+//! are of course falliable, because who knows what people put on the header buffer.
+//!
+//! ## Parsing
+//!
+//! From the [specification](http://psrdada.sourceforge.net/manuals/Specification.pdf):
+//!
+//! > Data attributes are stored as keyword-value pairs, each separated by a new line.
+//!
+//! The specification does not mention how these key value pairs are separated, and we will interpret new line
+//! as `\n` or `\r\n`. Missing from the specification is how the key/value pairs are separated. Following what
+//! `ascii_header_set` from the C library writes, keys are a single token separated from their value by any amount of
+//! whitespace (tabs or spaces, not newlines or carriage returns). Values run to the end of the line (so may contain spaces,
+//! like `SOURCE  J1939 +2134`), with surrounding whitespace trimmed.
+//! Also, in some examples, we see `#` denoting comments in headers, either after a pair or on a line of their own,
+//! in which case we will ignore when parsing. We will also ignore empty lines.
+//!
+//! Formally, we can write this grammar in "EBNF" as:
+//! ```ebnf
+//! Header       := (Line <Newline>)* Line? EOF?
+//! Line         := <Whitespace?> (Pair | <Comment>)?
+//! Pair         := Key <Whitespace> Value <Whitespace?> <Comment?>
+//!
+//! Key          := #"[^\s#\0]+"
+//! Value        := #"[^\s#\0]([^\r\n#\0]*[^\s#\0])?"
+//!
+//! <Comment>    := "#" #"[^\n\r\0]*"
+//! <Whitespace> := #"[^\S\r\n\0]+"
+//! <Newline>    := "\n" | "\r\n"
+//! <EOF>        := "\0"
+//! ```
+//!
+//! Where #"..." are PCRE regular expressions.
+//! This grammar will work as is with the [instaparse](https://github.com/Engelberg/instaparse) library from Clojure.
+//! Anything after the first `\0` is ignored, as the C library treats headers as null-terminated strings. As that would silently
+//! drop the rest of a header, only more `\0`s (padding) are allowed after it.
+//!
+//! When a line doesn't match, parsing fails with a [`ParseDiagnostic`] giving its line number, column and contents.
+//! [`bytes_to_header_lenient`] instead skips bad lines and returns their diagnostics as warnings.
+//!
+//! ## Serializing
+//!
+//! Going from a `HashMap<String,String>`, we will print keys as is, separated by a single space with newlines separating pairs.
+//! As values are trimmed when parsing, values with leading or trailing whitespace won't round trip. Everything that writes to the
+//! header ringbuffer checks every entry with [`validate_entry`] first, so we never write a header we couldn't parse back.
+//! To keep the order, comments and formatting of a header intact, use [`DadaHeader`] instead.
+//! For the standard keywords parsed into numbers and times, see [`StandardHeader`], which can be checked against the rings with
+//! [`validate_geometry`]. For any other struct, there's a serde
+//! data format in `format` behind the `serde` feature. Headers can also be assembled from layered templates with [`HeaderTemplate`].
+//! Monitoring tools can look at headers without consuming them with [`HeaderClient::peek_header`] and
+//! [`HeaderClient::queued_headers`].
+
+pub mod coords;
+pub mod diagnostics;
+#[cfg(feature = "serde")]
+pub mod format;
+pub mod geometry;
+pub mod ordered;
+pub mod peek;
+pub mod standard;
+pub mod template;
+pub mod time;
+
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    str,
+};
+
+pub use coords::{Declination, RightAscension};
+use diagnostics::parse_lines;
+pub use diagnostics::{ParseDiagnostic, ParseErrorKind};
+pub use geometry::{validate_geometry, GeometryIssue, GeometryReport};
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag, take_till},
+    character::complete::{space0, space1},
+    combinator::{map, opt, verify},
+    sequence::{pair as both, preceded, tuple},
+    IResult,
+};
+pub use ordered::{DadaHeader, DuplicatePolicy};
+use psrdada_sys::ipcbuf_get_bufsz;
+pub use standard::StandardHeader;
+pub use template::HeaderTemplate;
+pub use time::{Mjd, UtcTime};
+
+use crate::{
+    client::HeaderClient,
+    errors::{PsrdadaError, PsrdadaResult},
+    io::DadaClient,
+    iter::DadaIterator,
+};
+
+type CommentedPair<'a> = (&'a [u8], &'a [u8], Option<&'a [u8]>);
+
+fn is_line_end(c: u8) -> bool {
+    c == b'\n' || c == b'\r' || c == b'\0'
+}
+
+fn token(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    is_not(" \t\n\r#\0")(input)
+}
+
+fn value(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    // Take everything up to a comment or the end of the line, then trim the whitespace off the end
+    map(
+        verify(is_not("\n\r#\0"), |v: &[u8]| {
+            !v.iter().all(u8::is_ascii_whitespace)
+        }),
+        |v: &[u8]| {
+            let end = v.iter().rposition(|c| !c.is_ascii_whitespace()).unwrap() + 1;
+            &v[..end]
+        },
+    )(input)
+}
+
+fn comment(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    preceded(tag("#"), take_till(is_line_end))(input)
+}
+
+fn commented_pair(input: &[u8]) -> IResult<&[u8], CommentedPair<'_>> {
+    map(
+        preceded(space0, tuple((token, space1, value, space0, opt(comment)))),
+        |(k, _, v, _, c)| (k, v, c),
+    )(input)
+}
+
+/// The contents of a line (without the line ending), which is either a pair or only whitespace and comments
+fn line_content(input: &[u8]) -> IResult<&[u8], Option<CommentedPair<'_>>> {
+    alt((
+        map(commented_pair, Some),
+        map(both(space0, opt(comment)), |_| None),
+    ))(input)
+}
+
+/// Parse a header into its pairs, failing on the first line that couldn't be parsed
+fn header(input: &[u8]) -> Result<Vec<(&str, &str)>, ParseDiagnostic> {
+    let (lines, mut diagnostics) = parse_lines(input);
+    if !diagnostics.is_empty() {
+        return Err(diagnostics.swap_remove(0));
+    }
+    Ok(lines
+        .into_iter()
+        .filter_map(|line| line.pair.map(|(k, v, _)| (k, v)))
+        .collect())
+}
+
+/// Check that a key/value pair can be written to a header and parsed back unchanged.
+///
+/// Keys must be non-empty and can't contain whitespace, `#` or `\0`. Values must be non-empty, can't contain newlines,
+/// carriage returns, `#` or `\0`, and can't start or end with whitespace (as it would be trimmed when parsing).
+/// On failure, this returns [`PsrdadaError::HeaderInvalidEntry`] naming the offending pair.
+pub fn validate_entry(key: &str, value: &str) -> PsrdadaResult<()> {
+    let key_ok = !key.is_empty()
+        && !key
+            .bytes()
+            .any(|c| c.is_ascii_whitespace() || c == b'#' || c == b'\0');
+    let value_ok = !value.is_empty()
+        && !value.starts_with(|c: char| c.is_ascii_whitespace())
+        && !value.ends_with(|c: char| c.is_ascii_whitespace())
+        && !value
+            .bytes()
+            .any(|c| matches!(c, b'\n' | b'\r' | b'#' | b'\0'));
+    if key_ok && value_ok {
+        Ok(())
+    } else {
+        Err(PsrdadaError::HeaderInvalidEntry {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+}
+
+/// Convert a `HashMap<String,String>` into a psrdada-compatible vector of bytes
+///
+/// # Safety
+///
+/// There are limitations on what can be a key and a value. For example, neither
+/// can contain newlines, #, or \0, keys can't contain spaces or tabs, and values can't start or end with them.
+/// We are not validating that here so you could end up with bad bytes in the end. Use [`try_header_to_bytes`] instead.
+pub unsafe fn header_to_bytes(header: &HashMap<String, String>) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (k, v) in header {
+        bytes.extend(k.as_bytes());
+        bytes.extend(b" ");
+        bytes.extend(v.as_bytes());
+        bytes.extend(b"\n");
+    }
+    bytes
+}
+
+/// Convert a `HashMap<String,String>` into a psrdada-compatible vector of bytes, checking every entry with [`validate_entry`]
+pub fn try_header_to_bytes(header: &HashMap<String, String>) -> PsrdadaResult<Vec<u8>> {
+    for (k, v) in header {
+        validate_entry(k, v)?;
+    }
+    // Safety: We just validated every pair
+    Ok(unsafe { header_to_bytes(header) })
+}
+
+/// Collect key/value pairs into a map, keeping the first occurrence of any duplicate key like `ascii_header_get` does
+fn first_wins<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    for (k, v) in pairs {
+        map.entry(k.to_owned()).or_insert_with(|| v.to_owned());
+    }
+    map
+}
+
+/// Parse the bytes of a header into a `HashMap<String,String>`. If a key is repeated, the first occurrence wins, matching
+/// `ascii_header_get` from the C library (and [`DadaHeader`]).
+///
+/// Fails with [`PsrdadaError::HeaderParseError`] describing the first line that couldn't be parsed.
+pub fn bytes_to_header(bytes: &[u8]) -> PsrdadaResult<HashMap<String, String>> {
+    let pairs = header(bytes).map_err(PsrdadaError::HeaderParseError)?;
+    Ok(first_wins(pairs))
+}
+
+/// Parse the bytes of a header like [`bytes_to_header`], but skip any lines that can't be parsed, returning them as warnings
+pub fn bytes_to_header_lenient(bytes: &[u8]) -> (HashMap<String, String>, Vec<ParseDiagnostic>) {
+    let (lines, diagnostics) = parse_lines(bytes);
+    let header = first_wins(
+        lines
+            .into_iter()
+            .filter_map(|line| line.pair)
+            .map(|(k, v, _)| (k, v)),
+    );
+    (header, diagnostics)
+}
+
+/// Get `HDR_SIZE` out of the bytes of a header, if it's there.
+///
+/// If `lenient`, lines that can't be parsed are skipped, so only the `HDR_SIZE` line itself has to make sense.
+fn hdr_size(bytes: &[u8], lenient: bool) -> PsrdadaResult<Option<usize>> {
+    let mut header = if lenient {
+        bytes_to_header_lenient(bytes).0
+    } else {
+        bytes_to_header(bytes)?
+    };
+    match header.remove("HDR_SIZE") {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| PsrdadaError::HeaderValueError {
+                key: "HDR_SIZE".to_owned(),
+                value,
+            }),
+        None => Ok(None),
+    }
+}
+
+/// Cut the bytes of a header block down to `HDR_SIZE`, if it's set, checking the header fits in it and it fits in the block
+fn truncate_to_hdr_size(mut bytes: Vec<u8>, bufsz: usize, lenient: bool) -> PsrdadaResult<Vec<u8>> {
+    if let Some(hdr_size) = hdr_size(&bytes, lenient)? {
+        let header_size = bytes.iter().position(|c| *c == 0).unwrap_or(bytes.len());
+        if hdr_size > bufsz || header_size > hdr_size {
+            return Err(PsrdadaError::HdrSizeError {
+                hdr_size,
+                header_size,
+                buffer_size: bufsz,
+            });
+        }
+        bytes.truncate(hdr_size);
+    }
+    Ok(bytes)
+}
+
+impl HeaderClient<'_> {
+    /// Write a block of header bytes, padded with zeros to the size of the header buffer.
+    ///
+    /// The header has to leave room for at least one `\0`, as readers treat it as a null-terminated string.
+    /// If it sets `HDR_SIZE`, it has to fit (with that `\0`) in `HDR_SIZE` bytes, which in turn have to fit in the block.
+    fn write_header_bytes(&mut self, bytes: &[u8]) -> PsrdadaResult<()> {
+        // Safety: We're connected, so the buffer is valid
+        let bufsz = unsafe { ipcbuf_get_bufsz(self.buf as *mut _) };
+        if bytes.len() >= bufsz as usize {
+            return Err(PsrdadaError::HeaderOverflow {
+                header_size: bytes.len(),
+                buffer_size: bufsz as usize,
+            });
+        }
+        if let Some(hdr_size) = hdr_size(bytes, false)? {
+            if hdr_size > bufsz as usize || bytes.len() >= hdr_size {
+                return Err(PsrdadaError::HdrSizeError {
+                    hdr_size,
+                    header_size: bytes.len(),
+                    buffer_size: bufsz as usize,
+                });
+            }
+        }
+        let mut writer = self.writer()?;
+        // Create a buffer of zeros, then copy over our header
+        let mut whole_buffer = vec![0u8; bufsz as usize];
+        (whole_buffer[0..bytes.len()]).copy_from_slice(bytes);
+        // Write it out
+        let mut next_block = writer.next().ok_or(PsrdadaError::DadaWriteError)?;
+        next_block
+            .write_all(&whole_buffer)
+            .map_err(|_| PsrdadaError::DadaWriteError)?;
+        Ok(())
+    }
+
+    /// Read the next block of header bytes.
+    ///
+    /// If the header sets `HDR_SIZE`, only the first `HDR_SIZE` bytes are returned, and it's an error for the header to
+    /// extend past them (or for `HDR_SIZE` to be larger than the block). If `lenient`, other lines that can't be parsed don't
+    /// get in the way of finding `HDR_SIZE`.
+    fn read_header_bytes(&mut self, lenient: bool) -> PsrdadaResult<Vec<u8>> {
+        // Safety: We're connected, so the buffer is valid
+        let bufsz = unsafe { ipcbuf_get_bufsz(self.buf as *mut _) } as usize;
+        let mut reader = self.reader()?;
+        // Get the next header block
+        let mut next_block = reader.next().ok_or(PsrdadaError::DadaReadError)?;
+        let mut bytes = vec![];
+        next_block
+            .read_to_end(&mut bytes)
+            .map_err(|_| PsrdadaError::DadaReadError)?;
+        truncate_to_hdr_size(bytes, bufsz, lenient)
+    }
+
+    /// Write a `HashMap<String,String>` into into the header ringbuffer
+    ///
+    /// Every entry is checked with [`validate_entry`] and the header has to fit in a header block
+    /// (otherwise this returns [`PsrdadaError::HeaderOverflow`]). Nothing is written if either check fails.
+    pub fn write_header(&mut self, header: &HashMap<String, String>) -> PsrdadaResult<()> {
+        self.write_header_bytes(&try_header_to_bytes(header)?)
+    }
+
+    /// Read a block of header data from the header ringbuffer into a HashMap<String,String>
+    pub fn read_header(&mut self) -> PsrdadaResult<HashMap<String, String>> {
+        bytes_to_header(&self.read_header_bytes(false)?)
+    }
+
+    /// Read a block of header data, skipping any lines that can't be parsed (see [`bytes_to_header_lenient`])
+    pub fn read_header_lenient(
+        &mut self,
+    ) -> PsrdadaResult<(HashMap<String, String>, Vec<ParseDiagnostic>)> {
+        Ok(bytes_to_header_lenient(&self.read_header_bytes(true)?))
+    }
+
+    /// Write a [`DadaHeader`] into the header ringbuffer, preserving its order and comments
+    ///
+    /// This is checked in the same way as [`HeaderClient::write_header`].
+    pub fn write_dada_header(&mut self, header: &DadaHeader) -> PsrdadaResult<()> {
+        header.validate()?;
+        self.write_header_bytes(&header.to_bytes())
+    }
+
+    /// Read a block of header data from the header ringbuffer into a [`DadaHeader`]
+    pub fn read_dada_header(&mut self) -> PsrdadaResult<DadaHeader> {
+        DadaHeader::parse(&self.read_header_bytes(false)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use psrdada_sys::ipcbuf_get_nfull;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, tests::next_key};
+
+    #[test]
+    fn test_to_and_from_header() {
+        let mut header = HashMap::new();
+        header.insert("KEY".to_string(), "VALUE".to_string());
+        header.insert("KEY2".to_string(), "VALUE2".to_string());
+        let bytes = unsafe { header_to_bytes(&header) };
+        let new_header = bytes_to_header(&bytes).unwrap();
+        assert_eq!(header, new_header)
+    }
+
+    #[test]
+    fn test_token_parser() {
+        let (_, token) = token(b"A_TOKEN").unwrap();
+        assert_eq!(b"A_TOKEN", token);
+    }
+
+    #[test]
+    fn test_pair_parser() {
+        let (_, pair) = commented_pair(b"FOO_BAR 123.456").unwrap();
+        assert_eq!(b"FOO_BAR", pair.0);
+        assert_eq!(b"123.456", pair.1);
+    }
+
+    #[test]
+    fn test_commented_pair_parser() {
+        let (_, pair) = commented_pair(b"FOO_BAR 123.456    # random nonsense foo barbaz").unwrap();
+        assert_eq!(b"FOO_BAR", pair.0);
+        assert_eq!(b"123.456", pair.1);
+        assert_eq!(Some(&b" random nonsense foo barbaz"[..]), pair.2);
+    }
+
+    #[test]
+    fn test_spaced_value_parser() {
+        let (_, pair) = commented_pair(b"SOURCE  J1939 +2134   # the source").unwrap();
+        assert_eq!(b"SOURCE", pair.0);
+        assert_eq!(b"J1939 +2134", pair.1);
+    }
+
+    #[test]
+    fn test_missing_value() {
+        assert!(commented_pair(b"KEY   # no value").is_err());
+        assert!(commented_pair(b"KEY   ").is_err());
+        assert!(bytes_to_header(b"FOO BAR\nKEY\nBAZ QUUZ").is_err());
+    }
+
+    #[test]
+    fn test_header_parser() {
+        let hdr = b"FOO\tBAR # A comment\nBAZ   \tquuz123#morecomment__\n\nbEanS __RICE__";
+        let pairs = header(hdr).unwrap();
+
+        let p1 = pairs.first().unwrap();
+        assert_eq!("FOO", p1.0);
+        assert_eq!("BAR", p1.1);
+
+        let p2 = pairs.get(1).unwrap();
+        assert_eq!("BAZ", p2.0);
+        assert_eq!("quuz123", p2.1);
+
+        let p3 = pairs.get(2).unwrap();
+        assert_eq!("bEanS", p3.0);
+        assert_eq!("__RICE__", p3.1);
+    }
+
+    #[test]
+    fn test_from_c_string() {
+        let hdr = b"foo bar\nbaz buzz#foob\n\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        let pairs = header(hdr).unwrap();
+
+        let p1 = pairs.first().unwrap();
+        assert_eq!("foo", p1.0);
+        assert_eq!("bar", p1.1);
+
+        let p2 = pairs.get(1).unwrap();
+        assert_eq!("baz", p2.0);
+        assert_eq!("buzz", p2.1);
+    }
+
+    #[test]
+    fn test_comment_lines_and_whitespace() {
+        let hdr = b"# DADA ASCII header\r\n\r\n  INSTRUMENT   CASPSR   # comment\r\n\t# indented comment\r\nMODE PSR";
+        let pairs = header(hdr).unwrap();
+        assert_eq!(pairs, [("INSTRUMENT", "CASPSR"), ("MODE", "PSR")]);
+    }
+
+    #[test]
+    fn test_bytes_to_header() {
+        let hdr = b"foo bar\nbaz buzz";
+        let hdr_parsed = bytes_to_header(hdr).unwrap();
+        assert_eq!(
+            HashMap::from([
+                ("foo".to_owned(), "bar".to_owned()),
+                ("baz".to_owned(), "buzz".to_owned()),
+            ]),
+            hdr_parsed
+        )
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let bytes = b"HDR_SIZE 32\nNCHAN 1\nHDR_SIZE 16\nNCHAN 2\n";
+        assert_eq!(bytes_to_header(bytes).unwrap()["NCHAN"], "1");
+        assert_eq!(bytes_to_header_lenient(bytes).0["NCHAN"], "1");
+        assert_eq!(hdr_size(bytes, false), Ok(Some(32)));
+        assert_eq!(hdr_size(bytes, true), Ok(Some(32)));
+        assert_eq!(DadaHeader::parse(bytes).unwrap().get("NCHAN"), Some("1"));
+    }
+
+    #[test]
+    fn test_parse_diagnostics() {
+        assert_eq!(
+            bytes_to_header(b"FOO BAR\nKEY\nBAZ QUUZ"),
+            Err(PsrdadaError::HeaderParseError(ParseDiagnostic {
+                line: 2,
+                column: 4,
+                bytes: b"KEY".to_vec(),
+                kind: ParseErrorKind::MissingValue,
+            }))
+        );
+        // Used to panic
+        assert!(matches!(
+            bytes_to_header(b"SOURCE \xe9toile\n"),
+            Err(PsrdadaError::HeaderParseError(ParseDiagnostic {
+                kind: ParseErrorKind::InvalidUtf8,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_bytes_to_header_lenient() {
+        let (header, warnings) =
+            bytes_to_header_lenient(b"FOO BAR\nKEY\nSOURCE \xe9toile\nBAZ QUUZ\0junk\0");
+        assert_eq!(
+            header,
+            HashMap::from([
+                ("FOO".to_owned(), "BAR".to_owned()),
+                ("BAZ".to_owned(), "QUUZ".to_owned()),
+            ])
+        );
+        let kinds: Vec<_> = warnings.iter().map(|w| (w.line, w.kind)).collect();
+        assert_eq!(kinds, [
+            (2, ParseErrorKind::MissingValue),
+            (3, ParseErrorKind::InvalidUtf8),
+            (4, ParseErrorKind::EmbeddedNul)
+        ]);
+    }
+
+    #[test]
+    fn test_read_header_lenient() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key).header_size(64).build().unwrap();
+        let (mut hc, _) = client.split();
+
+        // A writer that doesn't check its lines, with HDR_SIZE after a bad one
+        let mut writer = hc.writer().unwrap();
+        let mut block = writer.next().unwrap();
+        block
+            .write_all(b"SOURCE J0437-4715\nKEY\nHDR_SIZE 48\n")
+            .unwrap();
+        drop(block);
+        drop(writer);
+        let (header, warnings) = hc.read_header_lenient().unwrap();
+        assert_eq!(
+            header,
+            HashMap::from([
+                ("SOURCE".to_owned(), "J0437-4715".to_owned()),
+                ("HDR_SIZE".to_owned(), "48".to_owned()),
+            ])
+        );
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            (warnings[0].line, warnings[0].kind),
+            (2, ParseErrorKind::MissingValue)
+        );
+    }
+
+    #[test]
+    fn test_roundtrip_header() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key).build().unwrap();
+        let (mut hc, _) = client.split();
+
+        let header = HashMap::from([
+            ("foo".to_owned(), "bar".to_owned()),
+            ("baz".to_owned(), "buzz".to_owned()),
+            ("SOURCE".to_owned(), "J1939 +2134".to_owned()),
+        ]);
+
+        // Write
+        hc.write_header(&header).unwrap();
+
+        // Read
+        assert_eq!(header, hc.read_header().unwrap());
+    }
+
+    #[test]
+    fn test_roundtrip_dada_header() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key).build().unwrap();
+        let (mut hc, _) = client.split();
+
+        let mut header =
+            DadaHeader::parse(b"# Observation\nSOURCE J1939 +2134 # Target\n").unwrap();
+        header.insert("NBIT", "8");
+
+        hc.write_dada_header(&header).unwrap();
+        assert_eq!(
+            hc.read_dada_header().unwrap().to_bytes(),
+            b"# Observation\nSOURCE J1939 +2134 # Target\nNBIT 8\n"
+        );
+    }
+
+    #[test]
+    fn test_validate_entry() {
+        assert_eq!(validate_entry("SOURCE", "J1939 +2134"), Ok(()));
+        for (k, v) in [
+            ("", "VALUE"),
+            ("MY KEY", "VALUE"),
+            ("KEY#", "VALUE"),
+            ("KEY", ""),
+            ("KEY", " VALUE"),
+            ("KEY", "VALUE\t"),
+            ("KEY", "VALUE # comment"),
+            ("KEY", "VAL\nUE"),
+            ("KEY", "VAL\0UE"),
+        ] {
+            assert_eq!(
+                validate_entry(k, v),
+                Err(PsrdadaError::HeaderInvalidEntry {
+                    key: k.to_owned(),
+                    value: v.to_owned()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_invalid_header() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key).build().unwrap();
+        let (mut hc, _) = client.split();
+
+        let header = HashMap::from([
+            ("GOOD".to_owned(), "1".to_owned()),
+            ("BAD".to_owned(), "1\nINJECTED 2".to_owned()),
+        ]);
+        assert_eq!(
+            hc.write_header(&header),
+            Err(PsrdadaError::HeaderInvalidEntry {
+                key: "BAD".to_owned(),
+                value: "1\nINJECTED 2".to_owned()
+            })
+        );
+        // Nothing was written
+        assert_eq!(unsafe { ipcbuf_get_nfull(hc.buf as *mut _) }, 0);
+    }
+
+    #[test]
+    fn test_header_overflow() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key).header_size(16).build().unwrap();
+        let (mut hc, _) = client.split();
+
+        let header = HashMap::from([("LONG_KEY".to_owned(), "LONG_VALUE".to_owned())]);
+        assert_eq!(
+            hc.write_header(&header),
+            Err(PsrdadaError::HeaderOverflow {
+                header_size: 20,
+                buffer_size: 16
+            })
+        );
+        // But a header that fits is fine
+        let header = HashMap::from([("KEY".to_owned(), "VALUE".to_owned())]);
+        hc.write_header(&header).unwrap();
+        assert_eq!(hc.read_header().unwrap(), header);
+    }
+
+    #[test]
+    fn test_hdr_size() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key).header_size(64).build().unwrap();
+        let (mut hc, _) = client.split();
+
+        // HDR_SIZE larger than the block
+        let mut header = HashMap::from([("HDR_SIZE".to_owned(), "128".to_owned())]);
+        assert_eq!(
+            hc.write_header(&header),
+            Err(PsrdadaError::HdrSizeError {
+                hdr_size: 128,
+                header_size: 13,
+                buffer_size: 64
+            })
+        );
+        // Header larger than HDR_SIZE
+        header.insert("HDR_SIZE".to_owned(), "12".to_owned());
+        assert_eq!(
+            hc.write_header(&header),
+            Err(PsrdadaError::HdrSizeError {
+                hdr_size: 12,
+                header_size: 12,
+                buffer_size: 64
+            })
+        );
+        // Just right
+        header.insert("HDR_SIZE".to_owned(), "32".to_owned());
+        hc.write_header(&header).unwrap();
+        assert_eq!(hc.read_header().unwrap(), header);
+
+        // A writer that ignored the convention
+        let mut writer = hc.writer().unwrap();
+        let mut block = writer.next().unwrap();
+        block
+            .write_all(b"HDR_SIZE 16\nSOURCE J0437-4715\n")
+            .unwrap();
+        drop(block);
+        drop(writer);
+        assert_eq!(
+            hc.read_header(),
+            Err(PsrdadaError::HdrSizeError {
+                hdr_size: 16,
+                header_size: 30,
+                buffer_size: 64
+            })
+        );
+    }
+}
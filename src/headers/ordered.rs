@@ -0,0 +1,401 @@
+//! A header type that keeps everything a human would care about when diffing headers.
+//!
+//! [`bytes_to_header`](super::bytes_to_header) gives back a `HashMap`, which throws away the order of the keys, any comments and
+//! any duplicate keys. [`DadaHeader`] instead keeps every line, so a header that is parsed and serialized again comes back
+//! byte-for-byte (minus any trailing `\0` padding and dropped duplicates). Lines that get modified are rewritten as `KEY VALUE`,
+//! keeping their comment.
+
+use std::{collections::HashMap, str};
+
+use super::{diagnostics::parse_lines, validate_entry};
+use crate::errors::{PsrdadaError, PsrdadaResult};
+
+/// What to do when a key shows up more than once in a header
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum DuplicatePolicy {
+    /// Fail with [`PsrdadaError::HeaderDuplicateKey`]
+    Error,
+    /// Keep the first occurrence, dropping the rest. This matches `ascii_header_get` from the C library.
+    #[default]
+    KeepFirst,
+    /// Keep the last occurrence (in its position), dropping the rest.
+    KeepLast,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Content {
+    /// A key/value pair, with the comment that followed it
+    Pair {
+        key: String,
+        value: String,
+        comment: Option<String>,
+    },
+    /// Empty or comment-only line
+    Other,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Line {
+    content: Content,
+    /// The text of this line as it was parsed, cleared when the line is modified
+    raw: Option<String>,
+    /// The line ending, empty for the last line if it had none
+    ending: &'static str,
+}
+
+impl Line {
+    fn pair(&self) -> Option<(&str, &str)> {
+        match &self.content {
+            Content::Pair { key, value, .. } => Some((key, value)),
+            Content::Other => None,
+        }
+    }
+
+    fn render(&self, bytes: &mut Vec<u8>) {
+        match (&self.raw, &self.content) {
+            (Some(raw), _) => bytes.extend(raw.as_bytes()),
+            (
+                None,
+                Content::Pair {
+                    key,
+                    value,
+                    comment,
+                },
+            ) => {
+                bytes.extend(key.as_bytes());
+                bytes.extend(b" ");
+                bytes.extend(value.as_bytes());
+                if let Some(comment) = comment {
+                    bytes.extend(b" #");
+                    bytes.extend(comment.as_bytes());
+                }
+            }
+            (None, Content::Other) => {}
+        }
+        bytes.extend(self.ending.as_bytes());
+    }
+}
+
+/// An ordered header that preserves comments and formatting
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct DadaHeader {
+    lines: Vec<Line>,
+}
+
+impl DadaHeader {
+    /// Create an empty header
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a header, keeping the first occurrence of any duplicate keys
+    pub fn parse(bytes: &[u8]) -> PsrdadaResult<Self> {
+        Self::parse_with(bytes, DuplicatePolicy::default())
+    }
+
+    /// Parse a header, handling duplicate keys according to `policy`
+    pub fn parse_with(bytes: &[u8], policy: DuplicatePolicy) -> PsrdadaResult<Self> {
+        let (lines, mut diagnostics) = parse_lines(bytes);
+        if !diagnostics.is_empty() {
+            return Err(PsrdadaError::HeaderParseError(diagnostics.swap_remove(0)));
+        }
+        let mut header = Self::new();
+        for parsed in lines {
+            let content = match parsed.pair {
+                Some((k, v, c)) => Content::Pair {
+                    key: k.to_owned(),
+                    value: v.to_owned(),
+                    comment: c.map(str::to_owned),
+                },
+                None => Content::Other,
+            };
+            let line = Line {
+                content,
+                raw: Some(parsed.raw.to_owned()),
+                ending: parsed.ending,
+            };
+            if let Some((key, _)) = line.pair() {
+                if let Some(idx) = header.position(key) {
+                    match policy {
+                        DuplicatePolicy::Error => {
+                            return Err(PsrdadaError::HeaderDuplicateKey(key.to_owned()))
+                        }
+                        DuplicatePolicy::KeepFirst => continue,
+                        DuplicatePolicy::KeepLast => {
+                            header.lines.remove(idx);
+                        }
+                    }
+                }
+            }
+            header.lines.push(line);
+        }
+        Ok(header)
+    }
+
+    /// Check every pair with [`validate_entry`], and that comment lines don't contain line breaks or `\0`.
+    ///
+    /// Headers straight from [`DadaHeader::parse`] are always valid, this catches bad values given to [`DadaHeader::insert`]
+    /// or [`DadaHeader::push_comment`]. A bad comment line is reported as an entry with the key `#`.
+    pub fn validate(&self) -> PsrdadaResult<()> {
+        for line in &self.lines {
+            match (&line.content, &line.raw) {
+                (Content::Pair { key, value, .. }, _) => validate_entry(key, value)?,
+                (Content::Other, Some(raw))
+                    if raw.bytes().any(|c| matches!(c, b'\n' | b'\r' | b'\0')) =>
+                {
+                    return Err(PsrdadaError::HeaderInvalidEntry {
+                        key: "#".to_owned(),
+                        value: raw.to_owned(),
+                    })
+                }
+                (Content::Other, _) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Serialize this header, reproducing the original formatting for every line that wasn't modified
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for line in &self.lines {
+            line.render(&mut bytes);
+        }
+        bytes
+    }
+
+    fn position(&self, key: &str) -> Option<usize> {
+        self.lines
+            .iter()
+            .position(|l| matches!(l.pair(), Some((k, _)) if k == key))
+    }
+
+    /// Add a line to the end of the header, making sure the previous line is terminated
+    fn push_line(&mut self, content: Content) {
+        let ending = match self.lines.last_mut() {
+            Some(last) => {
+                if last.ending.is_empty() {
+                    last.ending = "\n";
+                }
+                last.ending
+            }
+            None => "\n",
+        };
+        self.lines.push(Line {
+            content,
+            raw: None,
+            ending,
+        });
+    }
+
+    /// Get the value associated with a key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// Whether the header contains a given key
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.position(key).is_some()
+    }
+
+    /// Set the value of a key, returning the old value if there was one.
+    ///
+    /// Existing keys are updated in place (keeping their comment), new keys are appended to the end.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        let key = key.into();
+        let value = value.into();
+        match self.position(&key) {
+            Some(idx) => {
+                let line = &mut self.lines[idx];
+                line.raw = None;
+                match &mut line.content {
+                    Content::Pair { value: old, .. } => Some(std::mem::replace(old, value)),
+                    Content::Other => unreachable!(),
+                }
+            }
+            None => {
+                self.push_line(Content::Pair {
+                    key,
+                    value,
+                    comment: None,
+                });
+                None
+            }
+        }
+    }
+
+    /// Set every pair from `other` on this header, so that `other` takes precedence.
+    ///
+    /// Keys already present keep their position and comment, new keys are appended in the order they appear in `other`.
+    /// Comment lines from `other` are not copied.
+    pub fn merge(&mut self, other: &DadaHeader) {
+        for (k, v) in other.iter() {
+            if self.get(k) != Some(v) {
+                self.insert(k, v);
+            }
+        }
+    }
+
+    /// Remove a key (and its line), returning its value if it was present
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let idx = self.position(key)?;
+        match self.lines.remove(idx).content {
+            Content::Pair { value, .. } => Some(value),
+            Content::Other => unreachable!(),
+        }
+    }
+
+    /// Append a comment line to the end of the header
+    pub fn push_comment(&mut self, comment: &str) {
+        self.push_line(Content::Other);
+        let line = self.lines.last_mut().unwrap();
+        line.raw = Some(format!("# {comment}"));
+    }
+
+    /// Iterate over the key/value pairs in order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.iter().filter_map(Line::pair)
+    }
+
+    /// Iterate over the keys in order
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    /// The number of key/value pairs
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Whether there are no key/value pairs
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for DadaHeader {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut header = Self::new();
+        for (k, v) in iter {
+            header.insert(k, v);
+        }
+        header
+    }
+}
+
+impl From<&DadaHeader> for HashMap<String, String> {
+    fn from(header: &DadaHeader) -> Self {
+        header
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::{ParseDiagnostic, ParseErrorKind};
+
+    const HDR: &[u8] =
+        b"# DADA ASCII header\nHDR_VERSION  1.0   # Version\r\n\nSOURCE   J1939 +2134\nNBIT 8";
+
+    #[test]
+    fn test_roundtrip_bytes() {
+        let header = DadaHeader::parse(HDR).unwrap();
+        assert_eq!(header.to_bytes(), HDR);
+        assert_eq!(header.iter().collect::<Vec<_>>(), [
+            ("HDR_VERSION", "1.0"),
+            ("SOURCE", "J1939 +2134"),
+            ("NBIT", "8")
+        ]);
+    }
+
+    #[test]
+    fn test_ignores_nulls() {
+        let mut bytes = HDR.to_vec();
+        bytes.extend([0u8; 16]);
+        assert_eq!(DadaHeader::parse(&bytes).unwrap().to_bytes(), HDR);
+    }
+
+    #[test]
+    fn test_modify() {
+        let mut header = DadaHeader::parse(HDR).unwrap();
+        assert_eq!(header.insert("HDR_VERSION", "2.0"), Some("1.0".to_owned()));
+        assert_eq!(header.insert("NPOL", "2"), None);
+        assert_eq!(header.remove("SOURCE"), Some("J1939 +2134".to_owned()));
+        header.push_comment("the end");
+        assert_eq!(
+            header.to_bytes(),
+            b"# DADA ASCII header\nHDR_VERSION 2.0 # Version\r\n\nNBIT 8\nNPOL 2\n# the end\n"
+        );
+        // And what we wrote parses back to the same thing
+        assert_eq!(
+            DadaHeader::parse(&header.to_bytes())
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            header.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut header = DadaHeader::parse(HDR).unwrap();
+        header
+            .merge(&DadaHeader::parse(b"# Overrides\nNBIT 2\nNPOL 2\nHDR_VERSION 1.0\n").unwrap());
+        assert_eq!(
+            header.to_bytes(),
+            b"# DADA ASCII header\nHDR_VERSION  1.0   # Version\r\n\nSOURCE   J1939 +2134\nNBIT 2\nNPOL 2\n"
+        );
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let hdr = b"A 1\nB 2\nA 3\n";
+        assert_eq!(
+            DadaHeader::parse_with(hdr, DuplicatePolicy::Error),
+            Err(PsrdadaError::HeaderDuplicateKey("A".to_owned()))
+        );
+        let first = DadaHeader::parse_with(hdr, DuplicatePolicy::KeepFirst).unwrap();
+        assert_eq!(first.to_bytes(), b"A 1\nB 2\n");
+        let last = DadaHeader::parse_with(hdr, DuplicatePolicy::KeepLast).unwrap();
+        assert_eq!(last.to_bytes(), b"B 2\nA 3\n");
+        assert_eq!(last.get("A"), Some("3"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut header = DadaHeader::parse(HDR).unwrap();
+        assert_eq!(header.validate(), Ok(()));
+        header.insert("NPOL", "2\nNBIT 4");
+        assert_eq!(
+            header.validate(),
+            Err(PsrdadaError::HeaderInvalidEntry {
+                key: "NPOL".to_owned(),
+                value: "2\nNBIT 4".to_owned()
+            })
+        );
+        header.remove("NPOL");
+        header.push_comment("two\nlines");
+        assert_eq!(
+            header.validate(),
+            Err(PsrdadaError::HeaderInvalidEntry {
+                key: "#".to_owned(),
+                value: "# two\nlines".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn test_bad_line() {
+        assert_eq!(
+            DadaHeader::parse(b"A 1\nB\n"),
+            Err(PsrdadaError::HeaderParseError(ParseDiagnostic {
+                line: 2,
+                column: 2,
+                bytes: b"B".to_vec(),
+                kind: ParseErrorKind::MissingValue,
+            }))
+        );
+    }
+}
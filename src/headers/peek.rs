@@ -0,0 +1,165 @@
+//! Looking at headers without consuming them.
+//!
+//! [`HeaderClient::read_header`] takes the read lock and clears the block it read, so a monitoring tool using it would steal
+//! headers from the real consumer. The methods here copy blocks straight out of the header ring instead, without locking or
+//! clearing anything, in the same way viewers of the C library do.
+//!
+//! As nothing is locked, a block could be reused by the writer while we copy it. The writer can only reuse a block once the
+//! reader has cleared it and the rest of the ring has filled up, so we check the read and write counts after copying and throw
+//! away (or retry) anything that could have been written over.
+
+use std::{collections::HashMap, slice};
+
+use psrdada_sys::{
+    ipcbuf_get_bufsz, ipcbuf_get_nbufs, ipcbuf_get_read_count, ipcbuf_get_write_count, ipcbuf_t,
+};
+
+use super::{bytes_to_header, truncate_to_hdr_size};
+use crate::{client::HeaderClient, errors::PsrdadaResult};
+
+impl HeaderClient<'_> {
+    fn write_count(&self) -> u64 {
+        // Safety: We're connected, so the buffer is valid
+        unsafe { ipcbuf_get_write_count(self.buf as *mut _) }
+    }
+
+    fn read_count(&self) -> u64 {
+        // Safety: We're connected, so the buffer is valid
+        unsafe { ipcbuf_get_read_count(self.buf as *mut _) }
+    }
+
+    /// Copy the `seq`th block written to the ring
+    fn copy_block(&self, seq: u64) -> Vec<u8> {
+        // Safety: We're connected, so the buffer and its block pointers are valid, and every block is `bufsz` long
+        unsafe {
+            let buf = self.buf as *mut ipcbuf_t;
+            let idx = (seq % ipcbuf_get_nbufs(buf)) as usize;
+            let ptr = *(*buf).buffer.add(idx) as *const u8;
+            slice::from_raw_parts(ptr, ipcbuf_get_bufsz(buf) as usize).to_vec()
+        }
+    }
+
+    /// Whether the `seq`th block could have been written over since it was filled
+    fn maybe_reused(&self, seq: u64) -> bool {
+        // Safety: We're connected, so the buffer is valid
+        let nbufs = unsafe { ipcbuf_get_nbufs(self.buf as *mut _) };
+        self.read_count() > seq && self.write_count() >= seq + nbufs
+    }
+
+    /// Parse the header in a block we copied, like [`read_header`](HeaderClient::read_header)
+    fn parse_block(&self, bytes: Vec<u8>) -> PsrdadaResult<HashMap<String, String>> {
+        // Safety: We're connected, so the buffer is valid
+        let bufsz = unsafe { ipcbuf_get_bufsz(self.buf as *mut _) } as usize;
+        bytes_to_header(&truncate_to_hdr_size(bytes, bufsz, false)?)
+    }
+
+    /// Get the most recently written header, whether or not it has been read, without consuming it.
+    ///
+    /// Returns `None` if no header has been written yet, or if its block could be written over already, which only happens once
+    /// it has been read from a ring with a single header block.
+    pub fn peek_header(&self) -> PsrdadaResult<Option<HashMap<String, String>>> {
+        loop {
+            let count = self.write_count();
+            let Some(seq) = count.checked_sub(1) else {
+                return Ok(None);
+            };
+            let bytes = self.copy_block(seq);
+            // If another header landed while we were copying, that's the one we want anyway
+            if self.write_count() == count {
+                // The writer could be partway through writing over it
+                if self.maybe_reused(seq) {
+                    return Ok(None);
+                }
+                return self.parse_block(bytes).map(Some);
+            }
+        }
+    }
+
+    /// Get every header that has been written but not yet read, oldest first, without consuming any of them.
+    ///
+    /// Headers that get read (and written over) while we're looking are left out.
+    pub fn queued_headers(&self) -> PsrdadaResult<Vec<HashMap<String, String>>> {
+        let (first, last) = (self.read_count(), self.write_count());
+        let blocks: Vec<_> = (first..last)
+            .map(|seq| (seq, self.copy_block(seq)))
+            .collect();
+        blocks
+            .into_iter()
+            .filter(|(seq, _)| !self.maybe_reused(*seq))
+            .map(|(_, bytes)| self.parse_block(bytes))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, tests::next_key};
+
+    fn header(n: usize) -> HashMap<String, String> {
+        HashMap::from([("OBS_ID".to_owned(), format!("obs{n}"))])
+    }
+
+    #[test]
+    fn test_peek_header() {
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_headers(4)
+            .build()
+            .unwrap();
+        let (mut hc, _) = client.split();
+        assert_eq!(hc.peek_header().unwrap(), None);
+        assert!(hc.queued_headers().unwrap().is_empty());
+
+        for n in 0..3 {
+            hc.write_header(&header(n)).unwrap();
+        }
+        assert_eq!(hc.peek_header().unwrap(), Some(header(2)));
+        assert_eq!(hc.queued_headers().unwrap(), [
+            header(0),
+            header(1),
+            header(2)
+        ]);
+
+        // The real consumer still gets everything
+        assert_eq!(hc.read_header().unwrap(), header(0));
+        assert_eq!(hc.queued_headers().unwrap(), [header(1), header(2)]);
+        assert_eq!(hc.read_header().unwrap(), header(1));
+        assert_eq!(hc.read_header().unwrap(), header(2));
+
+        // And the last header is still there to look at once it's been read
+        assert_eq!(hc.peek_header().unwrap(), Some(header(2)));
+        assert!(hc.queued_headers().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_peek_after_wrapping() {
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_headers(2)
+            .build()
+            .unwrap();
+        let (mut hc, _) = client.split();
+        for n in 0..5 {
+            hc.write_header(&header(n)).unwrap();
+            assert_eq!(hc.peek_header().unwrap(), Some(header(n)));
+            assert_eq!(hc.read_header().unwrap(), header(n));
+        }
+        hc.write_header(&header(5)).unwrap();
+        assert_eq!(hc.queued_headers().unwrap(), [header(5)]);
+    }
+
+    #[test]
+    fn test_peek_single_block() {
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_headers(1)
+            .build()
+            .unwrap();
+        let (mut hc, _) = client.split();
+        hc.write_header(&header(0)).unwrap();
+        assert_eq!(hc.peek_header().unwrap(), Some(header(0)));
+        assert_eq!(hc.read_header().unwrap(), header(0));
+        // The writer is free to reuse the only block now
+        assert_eq!(hc.peek_header().unwrap(), None);
+    }
+}
@@ -0,0 +1,241 @@
+//! A typed view of the keywords every DADA header is expected to have.
+//!
+//! The meaning (and units) of each keyword come from the psrdada [specification](http://psrdada.sourceforge.net/manuals/Specification.pdf)
+//! and the `ascii_header_get` calls in the C library. Every keyword is optional, as plenty of headers in the wild only carry a few of them.
+//! Anything we don't know about ends up in [`StandardHeader::extras`], so converting to and from a `HashMap` is lossless
+//! (up to the formatting of numbers).
+
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use super::{
+    coords::{Declination, RightAscension},
+    time::UtcTime,
+};
+use crate::errors::{PsrdadaError, PsrdadaResult};
+
+/// The standard DADA header keywords, parsed into their natural types
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct StandardHeader {
+    /// `HDR_VERSION` - Version of the header format
+    pub hdr_version: Option<f64>,
+    /// `HDR_SIZE` - Size of the header in bytes
+    pub hdr_size: Option<u64>,
+    /// `OBS_ID` - Unique identifier of the observation
+    pub obs_id: Option<String>,
+    /// `UTC_START` - Time of the first sample of the observation
+    pub utc_start: Option<UtcTime>,
+    /// `OBS_OFFSET` - Offset of the first byte of this transfer from the start of the observation, in bytes
+    pub obs_offset: Option<u64>,
+    /// `FREQ` - Centre frequency in MHz
+    pub freq: Option<f64>,
+    /// `BW` - Bandwidth in MHz, negative if the channels are in decreasing frequency
+    pub bw: Option<f64>,
+    /// `NCHAN` - Number of frequency channels
+    pub nchan: Option<u32>,
+    /// `NPOL` - Number of polarizations
+    pub npol: Option<u32>,
+    /// `NBIT` - Number of bits per sample
+    pub nbit: Option<u32>,
+    /// `NDIM` - Number of dimensions per sample (1 for real, 2 for complex)
+    pub ndim: Option<u32>,
+    /// `TSAMP` - Sampling interval in microseconds
+    pub tsamp: Option<f64>,
+    /// `BYTES_PER_SECOND` - Data rate
+    pub bytes_per_second: Option<u64>,
+    /// `RESOLUTION` - Smallest number of bytes that can be processed on their own
+    pub resolution: Option<u64>,
+    /// `FILE_SIZE` - Size of each file written to disk, in bytes
+    pub file_size: Option<u64>,
+    /// `SOURCE` - Name of the observed source
+    pub source: Option<String>,
+    /// `RA` - Right ascension of the source
+    pub ra: Option<RightAscension>,
+    /// `DEC` - Declination of the source
+    pub dec: Option<Declination>,
+    /// `TELESCOPE` - Name of the telescope
+    pub telescope: Option<String>,
+    /// Every other key/value pair in the header
+    pub extras: HashMap<String, String>,
+}
+
+impl StandardHeader {
+    /// The time of the byte `byte_offset` bytes into this transfer, from `UTC_START`, `OBS_OFFSET` and `BYTES_PER_SECOND`.
+    ///
+    /// `OBS_OFFSET` is taken to be zero if it's missing. Returns `None` if either of the others are missing (or the rate is zero).
+    pub fn time_at_byte(&self, byte_offset: u64) -> Option<UtcTime> {
+        self.utc_start?.at_byte(
+            self.obs_offset.unwrap_or_default(),
+            self.bytes_per_second?,
+            byte_offset,
+        )
+    }
+}
+
+/// The value of a keyword a conversion can't do without, failing with [`PsrdadaError::HeaderMissingKey`] if it's missing
+pub(crate) fn required<T>(value: Option<T>, key: &str) -> PsrdadaResult<T> {
+    value.ok_or_else(|| PsrdadaError::HeaderMissingKey(key.to_owned()))
+}
+
+/// A [`PsrdadaError::HeaderValueError`] for a keyword with a value we can't use
+pub(crate) fn value_error(key: &str, value: impl ToString) -> PsrdadaError {
+    PsrdadaError::HeaderValueError {
+        key: key.to_owned(),
+        value: value.to_string(),
+    }
+}
+
+// Remove a key from the map and parse it, naming the key if it fails
+fn take<T: FromStr>(map: &mut HashMap<String, String>, key: &str) -> PsrdadaResult<Option<T>> {
+    match map.remove(key) {
+        Some(value) => match value.parse() {
+            Ok(v) => Ok(Some(v)),
+            Err(_) => Err(value_error(key, value)),
+        },
+        None => Ok(None),
+    }
+}
+
+fn put<T: Display>(map: &mut HashMap<String, String>, key: &str, value: &Option<T>) {
+    if let Some(v) = value {
+        map.insert(key.to_owned(), v.to_string());
+    }
+}
+
+impl TryFrom<HashMap<String, String>> for StandardHeader {
+    type Error = PsrdadaError;
+
+    fn try_from(mut map: HashMap<String, String>) -> Result<Self, Self::Error> {
+        let m = &mut map;
+        Ok(Self {
+            hdr_version: take(m, "HDR_VERSION")?,
+            hdr_size: take(m, "HDR_SIZE")?,
+            obs_id: take(m, "OBS_ID")?,
+            utc_start: take(m, "UTC_START")?,
+            obs_offset: take(m, "OBS_OFFSET")?,
+            freq: take(m, "FREQ")?,
+            bw: take(m, "BW")?,
+            nchan: take(m, "NCHAN")?,
+            npol: take(m, "NPOL")?,
+            nbit: take(m, "NBIT")?,
+            ndim: take(m, "NDIM")?,
+            tsamp: take(m, "TSAMP")?,
+            bytes_per_second: take(m, "BYTES_PER_SECOND")?,
+            resolution: take(m, "RESOLUTION")?,
+            file_size: take(m, "FILE_SIZE")?,
+            source: take(m, "SOURCE")?,
+            ra: take(m, "RA")?,
+            dec: take(m, "DEC")?,
+            telescope: take(m, "TELESCOPE")?,
+            extras: map,
+        })
+    }
+}
+
+impl TryFrom<&HashMap<String, String>> for StandardHeader {
+    type Error = PsrdadaError;
+
+    fn try_from(map: &HashMap<String, String>) -> Result<Self, Self::Error> {
+        map.clone().try_into()
+    }
+}
+
+impl From<&StandardHeader> for HashMap<String, String> {
+    fn from(header: &StandardHeader) -> Self {
+        let mut m = header.extras.clone();
+        put(&mut m, "HDR_VERSION", &header.hdr_version);
+        put(&mut m, "HDR_SIZE", &header.hdr_size);
+        put(&mut m, "OBS_ID", &header.obs_id);
+        put(&mut m, "UTC_START", &header.utc_start);
+        put(&mut m, "OBS_OFFSET", &header.obs_offset);
+        put(&mut m, "FREQ", &header.freq);
+        put(&mut m, "BW", &header.bw);
+        put(&mut m, "NCHAN", &header.nchan);
+        put(&mut m, "NPOL", &header.npol);
+        put(&mut m, "NBIT", &header.nbit);
+        put(&mut m, "NDIM", &header.ndim);
+        put(&mut m, "TSAMP", &header.tsamp);
+        put(&mut m, "BYTES_PER_SECOND", &header.bytes_per_second);
+        put(&mut m, "RESOLUTION", &header.resolution);
+        put(&mut m, "FILE_SIZE", &header.file_size);
+        put(&mut m, "SOURCE", &header.source);
+        put(&mut m, "RA", &header.ra);
+        put(&mut m, "DEC", &header.dec);
+        put(&mut m, "TELESCOPE", &header.telescope);
+        m
+    }
+}
+
+impl From<StandardHeader> for HashMap<String, String> {
+    fn from(header: StandardHeader) -> Self {
+        (&header).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::bytes_to_header;
+
+    #[test]
+    fn test_from_map() {
+        let map = bytes_to_header(
+            b"HDR_VERSION 1.0\nHDR_SIZE 4096\nUTC_START 2023-05-01-12:34:56\nFREQ 1382.0\nBW -400\nNBIT 8\nTSAMP 0.0025\nSOURCE J1939 +2134\nMODE PSR\n",
+        )
+        .unwrap();
+        let header = StandardHeader::try_from(&map).unwrap();
+        assert_eq!(header.hdr_version, Some(1.0));
+        assert_eq!(header.hdr_size, Some(4096));
+        assert_eq!(
+            header.utc_start,
+            Some(UtcTime::from_calendar(2023, 5, 1, 12, 34, 56, 0).unwrap())
+        );
+        assert_eq!(header.bw, Some(-400.0));
+        assert_eq!(header.nbit, Some(8));
+        assert_eq!(header.tsamp, Some(0.0025));
+        assert_eq!(header.source.as_deref(), Some("J1939 +2134"));
+        assert_eq!(header.npol, None);
+        assert_eq!(
+            header.extras,
+            HashMap::from([("MODE".to_owned(), "PSR".to_owned())])
+        );
+
+        // And back again
+        let mut back = HashMap::from(&header);
+        assert_eq!(back.remove("FREQ").unwrap(), "1382");
+        assert_eq!(back.remove("HDR_VERSION").unwrap(), "1");
+        let mut map = map;
+        map.remove("FREQ");
+        map.remove("HDR_VERSION");
+        assert_eq!(back, map);
+    }
+
+    #[test]
+    fn test_time_at_byte() {
+        let mut header = StandardHeader {
+            utc_start: Some("2023-05-01-12:34:56".parse().unwrap()),
+            obs_offset: Some(1600),
+            ..Default::default()
+        };
+        assert_eq!(header.time_at_byte(0), None);
+        header.bytes_per_second = Some(800);
+        assert_eq!(
+            header.time_at_byte(400).unwrap().to_string(),
+            "2023-05-01-12:34:58.5"
+        );
+    }
+
+    #[test]
+    fn test_bad_value() {
+        let map = HashMap::from([
+            ("NBIT".to_owned(), "8".to_owned()),
+            ("NCHAN".to_owned(), "-1".to_owned()),
+        ]);
+        assert_eq!(
+            StandardHeader::try_from(map),
+            Err(PsrdadaError::HeaderValueError {
+                key: "NCHAN".to_owned(),
+                value: "-1".to_owned()
+            })
+        );
+    }
+}
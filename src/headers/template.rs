@@ -0,0 +1,310 @@
+//! Header templates, like the `.hdr` configuration files given to `dada_junkdb`.
+//!
+//! A template is a stack of layers, each of which is an ordinary header. Rendering a template merges the layers in order
+//! (so later layers override earlier ones, see [`DadaHeader::merge`]) and then substitutes `${VAR}` in every value.
+//! A literal `$` can be written as `$$`.
+//!
+//! Template files can pull in other files with a comment line of the form
+//!
+//! ```text
+//! #include path/to/instrument.hdr
+//! ```
+//!
+//! (with nothing between the `#` and `include`), which inserts the layers of that file at that point. Relative paths are
+//! resolved against the directory of the including file (or the current directory, for templates parsed from strings). As this
+//! is just a comment, template files are still valid headers.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use super::ordered::DadaHeader;
+use crate::errors::{PsrdadaError, PsrdadaResult};
+
+/// A stack of header layers with `${VAR}` placeholders
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct HeaderTemplate {
+    layers: Vec<DadaHeader>,
+}
+
+// Get the path of an `#include` directive, if this line is one. There's no space after the `#`, so that free-text comments
+// like `# include cal scans here` stay comments.
+fn include_path(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("#include")?;
+    // Make sure this wasn't something like `#included`
+    if !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+    Some(rest.trim())
+}
+
+impl HeaderTemplate {
+    /// Create an empty template
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a template from a string, resolving includes relative to the current directory
+    pub fn parse(text: &str) -> PsrdadaResult<Self> {
+        let mut template = Self::new();
+        template.parse_into(text, Path::new("."), &mut vec![])?;
+        Ok(template)
+    }
+
+    /// Load a template from a file, resolving includes relative to its directory
+    pub fn from_file(path: impl AsRef<Path>) -> PsrdadaResult<Self> {
+        let mut template = Self::new();
+        template.load_into(path.as_ref(), &mut vec![])?;
+        Ok(template)
+    }
+
+    fn load_into(&mut self, path: &Path, stack: &mut Vec<PathBuf>) -> PsrdadaResult<()> {
+        let canonical = path.canonicalize()?;
+        if stack.contains(&canonical) {
+            return Err(PsrdadaError::TemplateIncludeCycle(
+                path.display().to_string(),
+            ));
+        }
+        let text = fs::read_to_string(&canonical)?;
+        stack.push(canonical);
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        self.parse_into(&text, dir, stack)?;
+        stack.pop();
+        Ok(())
+    }
+
+    fn parse_into(
+        &mut self,
+        text: &str,
+        dir: &Path,
+        stack: &mut Vec<PathBuf>,
+    ) -> PsrdadaResult<()> {
+        let mut chunk = String::new();
+        for line in text.split_inclusive('\n') {
+            match include_path(line) {
+                Some(include) => {
+                    if !chunk.is_empty() {
+                        self.layers.push(DadaHeader::parse(chunk.as_bytes())?);
+                        chunk.clear();
+                    }
+                    self.load_into(&dir.join(include), stack)?;
+                }
+                None => chunk.push_str(line),
+            }
+        }
+        if !chunk.is_empty() {
+            self.layers.push(DadaHeader::parse(chunk.as_bytes())?);
+        }
+        Ok(())
+    }
+
+    /// Add a layer on top of the existing ones, overriding any keys they share
+    pub fn push_layer(&mut self, layer: DadaHeader) {
+        self.layers.push(layer);
+    }
+
+    /// Add all the layers of another template on top of this one's
+    pub fn overlay(&mut self, other: HeaderTemplate) {
+        self.layers.extend(other.layers);
+    }
+
+    /// Set a single key on top of every layer
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let mut layer = DadaHeader::new();
+        layer.insert(key, value);
+        self.push_layer(layer);
+    }
+
+    /// The layers of this template, from lowest to highest precedence
+    pub fn layers(&self) -> &[DadaHeader] {
+        &self.layers
+    }
+
+    /// Merge all the layers into a single header, without substituting anything
+    pub fn merged(&self) -> DadaHeader {
+        let mut layers = self.layers.iter();
+        let mut header = layers.next().cloned().unwrap_or_default();
+        for layer in layers {
+            header.merge(layer);
+        }
+        header
+    }
+
+    /// Render the template, substituting variables with `lookup`.
+    ///
+    /// Fails with [`PsrdadaError::TemplateUndefinedVariable`] if `lookup` returns `None`, or with
+    /// [`PsrdadaError::HeaderInvalidEntry`] if a substituted value can't be written to a header.
+    pub fn render_with(
+        &self,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> PsrdadaResult<DadaHeader> {
+        let mut header = self.merged();
+        let pairs: Vec<_> = header
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        for (key, value) in pairs {
+            let substituted = substitute(&key, &value, &lookup)?;
+            if substituted != value {
+                header.insert(key, substituted);
+            }
+        }
+        header.validate()?;
+        Ok(header)
+    }
+
+    /// Render the template, substituting variables from a map
+    pub fn render(&self, vars: &HashMap<String, String>) -> PsrdadaResult<DadaHeader> {
+        self.render_with(|k| vars.get(k).cloned())
+    }
+
+    /// Render the template, substituting variables from the environment
+    pub fn render_env(&self) -> PsrdadaResult<DadaHeader> {
+        self.render_with(|k| env::var(k).ok())
+    }
+}
+
+/// Replace every `${VAR}` in `value`
+fn substitute(
+    key: &str,
+    value: &str,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> PsrdadaResult<String> {
+    let syntax_error = || PsrdadaError::HeaderValueError {
+        key: key.to_owned(),
+        value: value.to_owned(),
+    };
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(idx) = rest.find('$') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            let end = after.find('}').ok_or_else(syntax_error)?;
+            let name = &after[..end];
+            if name.is_empty() || !name.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_') {
+                return Err(syntax_error());
+            }
+            let var = lookup(name)
+                .ok_or_else(|| PsrdadaError::TemplateUndefinedVariable(name.to_owned()))?;
+            out.push_str(&var);
+            rest = &after[end + 1..];
+        } else {
+            out.push('$');
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute() {
+        let vars = HashMap::from([
+            ("SRC".to_owned(), "J0437-4715".to_owned()),
+            ("N".to_owned(), "1024".to_owned()),
+        ]);
+        let template = HeaderTemplate::parse(
+            "# Observation\nSOURCE ${SRC} # target\nNCHAN ${N}\nPRICE $$5 or $6\nFREQ 1400\n",
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&vars).unwrap().to_bytes(),
+            b"# Observation\nSOURCE J0437-4715 # target\nNCHAN 1024\nPRICE $5 or $6\nFREQ 1400\n"
+        );
+
+        assert_eq!(
+            HeaderTemplate::parse("A ${MISSING}\n")
+                .unwrap()
+                .render(&vars),
+            Err(PsrdadaError::TemplateUndefinedVariable(
+                "MISSING".to_owned()
+            ))
+        );
+        assert_eq!(
+            HeaderTemplate::parse("A ${SRC\n").unwrap().render(&vars),
+            Err(PsrdadaError::HeaderValueError {
+                key: "A".to_owned(),
+                value: "${SRC".to_owned()
+            })
+        );
+        let bad = HashMap::from([("X".to_owned(), "1\nB 2".to_owned())]);
+        assert!(matches!(
+            HeaderTemplate::parse("A ${X}\n").unwrap().render(&bad),
+            Err(PsrdadaError::HeaderInvalidEntry { .. })
+        ));
+    }
+
+    #[test]
+    fn test_env() {
+        env::set_var("PSRDADA_TEMPLATE_TEST_VAR", "from env");
+        let template = HeaderTemplate::parse("OBSERVER ${PSRDADA_TEMPLATE_TEST_VAR}\n").unwrap();
+        assert_eq!(
+            template.render_env().unwrap().get("OBSERVER"),
+            Some("from env")
+        );
+    }
+
+    #[test]
+    fn test_layers() {
+        let mut template = HeaderTemplate::parse("NBIT 8\nNPOL 2\nSOURCE ${SRC}\n").unwrap();
+        template.overlay(HeaderTemplate::parse("NBIT 4\n").unwrap());
+        template.set("SRC", "unused");
+        template.set("SOURCE", "B1937+21");
+        assert_eq!(template.layers().len(), 4);
+        assert_eq!(
+            template.render(&HashMap::new()).unwrap().to_bytes(),
+            b"NBIT 4\nNPOL 2\nSOURCE B1937+21\nSRC unused\n"
+        );
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("instruments")).unwrap();
+        fs::write(
+            dir.path().join("instruments/base.hdr"),
+            "HDR_VERSION 1.0\nTELESCOPE Parkes\nNBIT 8\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("instruments/caspsr.hdr"),
+            "#include base.hdr\nINSTRUMENT CASPSR\nNBIT 2\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("obs.hdr"),
+            "#include instruments/caspsr.hdr\n# include cal scans here\nSOURCE ${SRC}\nTELESCOPE Molonglo\n",
+        )
+        .unwrap();
+
+        let template = HeaderTemplate::from_file(dir.path().join("obs.hdr")).unwrap();
+        assert_eq!(template.layers().len(), 3);
+        let vars = HashMap::from([("SRC".to_owned(), "J1939+2134".to_owned())]);
+        assert_eq!(
+            template.render(&vars).unwrap().to_bytes(),
+            b"HDR_VERSION 1.0\nTELESCOPE Molonglo\nNBIT 2\nINSTRUMENT CASPSR\nSOURCE J1939+2134\n"
+        );
+
+        // Cycles are caught
+        fs::write(dir.path().join("a.hdr"), "#include b.hdr\n").unwrap();
+        fs::write(dir.path().join("b.hdr"), "#include a.hdr\n").unwrap();
+        assert!(matches!(
+            HeaderTemplate::from_file(dir.path().join("a.hdr")),
+            Err(PsrdadaError::TemplateIncludeCycle(_))
+        ));
+        // As are missing files
+        assert_eq!(
+            HeaderTemplate::from_file(dir.path().join("missing.hdr")),
+            Err(PsrdadaError::IoError(std::io::ErrorKind::NotFound))
+        );
+    }
+}
@@ -0,0 +1,429 @@
+//! Timestamps as they appear in DADA headers.
+//!
+//! `UTC_START` is written by the C library as `YYYY-MM-DD-hh:mm:ss` (see `dada_pwc_main`), always in UTC.
+//! We don't pull in a full date/time library for this, so [`UtcTime`] is a thin wrapper around seconds since the unix epoch.
+//! Some instruments also write `MJD_START`, which is an [`Mjd`]. Like everything else in radio astronomy headers, both ignore
+//! leap seconds (every day is 86400 seconds long).
+
+use std::{
+    fmt,
+    ops::Add,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::errors::PsrdadaError;
+
+/// A point in time (UTC), with nanosecond precision
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub struct UtcTime {
+    /// Whole seconds since 1970-01-01-00:00:00
+    secs: i64,
+    /// Nanoseconds past `secs`, always less than one billion
+    nanos: u32,
+}
+
+// Days since the unix epoch of a given date in the proleptic Gregorian calendar
+// From http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// The inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl UtcTime {
+    /// Create a time from a calendar date and time of day, returning `None` if any field is out of range
+    pub fn from_calendar(
+        year: i64,
+        month: u32,
+        day: u32,
+        hour: u32,
+        minute: u32,
+        second: u32,
+        nanos: u32,
+    ) -> Option<Self> {
+        if !(1..=12).contains(&month)
+            || day == 0
+            || day > days_in_month(year, month)
+            || hour > 23
+            || minute > 59
+            || second > 59
+            || nanos >= 1_000_000_000
+        {
+            return None;
+        }
+        let days = days_from_civil(year, month, day);
+        Some(Self {
+            secs: days * 86400 + (hour * 3600 + minute * 60 + second) as i64,
+            nanos,
+        })
+    }
+
+    /// Create a time from seconds and nanoseconds since the unix epoch
+    pub fn from_unix(secs: i64, nanos: u32) -> Self {
+        Self {
+            secs: secs + (nanos / 1_000_000_000) as i64,
+            nanos: nanos % 1_000_000_000,
+        }
+    }
+
+    /// Whole seconds since the unix epoch
+    pub fn unix_seconds(&self) -> i64 {
+        self.secs
+    }
+
+    /// Nanoseconds past [`UtcTime::unix_seconds`]
+    pub fn subsec_nanos(&self) -> u32 {
+        self.nanos
+    }
+
+    /// The modified julian date of this time
+    pub fn to_mjd(&self) -> Mjd {
+        Mjd {
+            day: self.secs.div_euclid(86400) + MJD_UNIX_EPOCH,
+            nanos: self.secs.rem_euclid(86400) as u64 * 1_000_000_000 + self.nanos as u64,
+        }
+    }
+
+    /// The time `byte_offset` bytes into a transfer.
+    ///
+    /// This is `self` (the `UTC_START` of the observation) plus `(obs_offset + byte_offset) / bytes_per_second`, computed
+    /// without rounding beyond the nearest nanosecond (rounding down). Returns `None` if `bytes_per_second` is zero.
+    pub fn at_byte(
+        &self,
+        obs_offset: u64,
+        bytes_per_second: u64,
+        byte_offset: u64,
+    ) -> Option<Self> {
+        if bytes_per_second == 0 {
+            return None;
+        }
+        let bytes = obs_offset as u128 + byte_offset as u128;
+        let bytes_per_second = bytes_per_second as u128;
+        let secs = bytes / bytes_per_second;
+        let nanos = (bytes % bytes_per_second) * 1_000_000_000 / bytes_per_second;
+        Some(Self::from_unix(
+            self.secs + secs as i64,
+            self.nanos + nanos as u32,
+        ))
+    }
+
+    /// The calendar date and time of day as `(year, month, day, hour, minute, second)`
+    pub fn to_calendar(&self) -> (i64, u32, u32, u32, u32, u32) {
+        let days = self.secs.div_euclid(86400);
+        let tod = self.secs.rem_euclid(86400) as u32;
+        let (year, month, day) = civil_from_days(days);
+        (year, month, day, tod / 3600, tod / 60 % 60, tod % 60)
+    }
+}
+
+impl Add<Duration> for UtcTime {
+    type Output = UtcTime;
+
+    fn add(self, rhs: Duration) -> UtcTime {
+        UtcTime::from_unix(
+            self.secs + rhs.as_secs() as i64,
+            self.nanos + rhs.subsec_nanos(),
+        )
+    }
+}
+
+impl From<SystemTime> for UtcTime {
+    fn from(t: SystemTime) -> Self {
+        match t.duration_since(UNIX_EPOCH) {
+            Ok(d) => Self::from_unix(d.as_secs() as i64, d.subsec_nanos()),
+            Err(e) => {
+                let d = e.duration();
+                let (secs, nanos) = (d.as_secs() as i64, d.subsec_nanos());
+                if nanos == 0 {
+                    Self::from_unix(-secs, 0)
+                } else {
+                    Self::from_unix(-secs - 1, 1_000_000_000 - nanos)
+                }
+            }
+        }
+    }
+}
+
+impl From<UtcTime> for SystemTime {
+    fn from(t: UtcTime) -> Self {
+        if t.secs >= 0 {
+            UNIX_EPOCH + Duration::new(t.secs as u64, t.nanos)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(t.secs.unsigned_abs())
+                + Duration::from_nanos(t.nanos as u64)
+        }
+    }
+}
+
+// Parse a field that must be all digits (no signs or whitespace)
+fn digits<T: FromStr>(s: &str) -> Result<T, PsrdadaError> {
+    if s.is_empty() || !s.bytes().all(|c| c.is_ascii_digit()) {
+        return Err(PsrdadaError::TimeParseError);
+    }
+    s.parse().map_err(|_| PsrdadaError::TimeParseError)
+}
+
+/// Parses `YYYY-MM-DD-hh:mm:ss`, optionally followed by fractional seconds (to nanosecond precision)
+impl FromStr for UtcTime {
+    type Err = PsrdadaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (date, time) = s
+            .trim()
+            .rsplit_once('-')
+            .ok_or(PsrdadaError::TimeParseError)?;
+        let date: Vec<_> = date.split('-').collect();
+        let time: Vec<_> = time.split(':').collect();
+        let (&[year, month, day], &[hour, minute, second]) = (&date[..], &time[..]) else {
+            return Err(PsrdadaError::TimeParseError);
+        };
+        let (second, frac) = second.split_once('.').unwrap_or((second, "0"));
+        // Anything past nanoseconds is truncated
+        let frac = &frac[..frac.len().min(9)];
+        let nanos = digits::<u32>(frac)? * 10u32.pow(9 - frac.len() as u32);
+        Self::from_calendar(
+            digits(year)?,
+            digits(month)?,
+            digits(day)?,
+            digits(hour)?,
+            digits(minute)?,
+            digits(second)?,
+            nanos,
+        )
+        .ok_or(PsrdadaError::TimeParseError)
+    }
+}
+
+/// Formats as `YYYY-MM-DD-hh:mm:ss`, only adding fractional seconds if there are any
+impl fmt::Display for UtcTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day, hour, minute, second) = self.to_calendar();
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}-{hour:02}:{minute:02}:{second:02}"
+        )?;
+        if self.nanos != 0 {
+            let frac = format!("{:09}", self.nanos);
+            write!(f, ".{}", frac.trim_end_matches('0'))?;
+        }
+        Ok(())
+    }
+}
+
+/// The MJD of the unix epoch, 1970-01-01
+const MJD_UNIX_EPOCH: i64 = 40587;
+const NANOS_PER_DAY: u64 = 86_400_000_000_000;
+/// Number of decimal places in a formatted [`Mjd`], which is enough to round-trip nanoseconds
+const MJD_DIGITS: u32 = 15;
+
+/// A modified julian date (days since 1858-11-17), split into whole days and nanoseconds into the day
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub struct Mjd {
+    /// Whole days
+    pub day: i64,
+    /// Nanoseconds into the day, always less than 86400e9
+    pub nanos: u64,
+}
+
+impl Mjd {
+    /// The fractional part of the day
+    pub fn fraction(&self) -> f64 {
+        self.nanos as f64 / NANOS_PER_DAY as f64
+    }
+
+    /// The MJD as a single float, which only has about a microsecond of precision for modern dates
+    pub fn as_f64(&self) -> f64 {
+        self.day as f64 + self.fraction()
+    }
+
+    /// The UTC time of this date
+    pub fn to_utc(&self) -> UtcTime {
+        UtcTime::from_unix(
+            (self.day - MJD_UNIX_EPOCH) * 86400 + (self.nanos / 1_000_000_000) as i64,
+            (self.nanos % 1_000_000_000) as u32,
+        )
+    }
+}
+
+impl From<UtcTime> for Mjd {
+    fn from(t: UtcTime) -> Self {
+        t.to_mjd()
+    }
+}
+
+impl From<Mjd> for UtcTime {
+    fn from(mjd: Mjd) -> Self {
+        mjd.to_utc()
+    }
+}
+
+/// Parses a decimal MJD like `60065.524259259259`, rounding to the nearest nanosecond
+impl FromStr for Mjd {
+    type Err = PsrdadaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (day, frac) = s.split_once('.').unwrap_or((s, "0"));
+        let (neg, digits_day) = match day.strip_prefix('-') {
+            Some(d) => (true, d),
+            None => (false, day),
+        };
+        let day: i64 = digits(digits_day)?;
+        // Anything past 18 digits (about a femtosecond) can't change the rounded result
+        let frac = &frac[..frac.len().min(18)];
+        let numerator: u128 = digits(frac)?;
+        let denominator = 10u128.pow(frac.len() as u32);
+        let nanos =
+            ((numerator * NANOS_PER_DAY as u128 * 2 + denominator) / (2 * denominator)) as u64;
+        let mjd = if neg {
+            // -1.25 is day -2 plus 0.75
+            Mjd {
+                day: -day - 1,
+                nanos: NANOS_PER_DAY - nanos,
+            }
+        } else {
+            Mjd { day, nanos }
+        };
+        // Normalize in case we rounded up to the next day
+        Ok(Mjd {
+            day: mjd.day + (mjd.nanos / NANOS_PER_DAY) as i64,
+            nanos: mjd.nanos % NANOS_PER_DAY,
+        })
+    }
+}
+
+/// Formats with 15 decimal places (trailing zeros removed), which round-trips through [`Mjd::from_str`]
+impl fmt::Display for Mjd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = 10u128.pow(MJD_DIGITS);
+        let frac =
+            (self.nanos as u128 * scale * 2 + NANOS_PER_DAY as u128) / (2 * NANOS_PER_DAY as u128);
+        // Rounding might carry into the next day
+        let (day, frac) = if frac == scale {
+            (self.day + 1, 0)
+        } else {
+            (self.day, frac)
+        };
+        write!(f, "{day}")?;
+        if frac != 0 {
+            let frac = format!("{:0width$}", frac, width = MJD_DIGITS as usize);
+            write!(f, ".{}", frac.trim_end_matches('0'))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        let t: UtcTime = "2023-05-01-12:34:56".parse().unwrap();
+        assert_eq!(t.unix_seconds(), 1682944496);
+        assert_eq!(t.subsec_nanos(), 0);
+        assert_eq!(t.to_string(), "2023-05-01-12:34:56");
+
+        let t: UtcTime = "2000-02-29-23:59:59.0000000015".parse().unwrap();
+        assert_eq!(t.subsec_nanos(), 1);
+        assert_eq!(t.to_string(), "2000-02-29-23:59:59.000000001");
+
+        let t: UtcTime = "1969-12-31-23:59:59.5".parse().unwrap();
+        assert_eq!(t.unix_seconds(), -1);
+        assert_eq!(t.to_string(), "1969-12-31-23:59:59.5");
+        assert_eq!(UtcTime::from(SystemTime::from(t)), t);
+    }
+
+    #[test]
+    fn test_bad_times() {
+        for bad in [
+            "",
+            "2023-05-01",
+            "2023-05-01T12:34:56",
+            "2023-02-29-12:34:56",
+            "2023-05-01-24:00:00",
+            "2023-05-01-12:34:+6",
+            "2023-05-01-12:34:56.",
+        ] {
+            assert_eq!(
+                bad.parse::<UtcTime>(),
+                Err(PsrdadaError::TimeParseError),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_mjd() {
+        let t: UtcTime = "2023-05-01-12:34:56".parse().unwrap();
+        let mjd = t.to_mjd();
+        assert_eq!(mjd.day, 60065);
+        assert_eq!(mjd.nanos, 45296 * 1_000_000_000);
+        assert_eq!(mjd.to_string(), "60065.524259259259259");
+        assert_eq!(mjd.to_utc(), t);
+        assert!((mjd.as_f64() - (60065.0 + 45296.0 / 86400.0)).abs() < 1e-9);
+
+        // Nanoseconds survive the round trip
+        let t = UtcTime::from_unix(1682944496, 123_456_789);
+        let mjd: Mjd = t.to_mjd().to_string().parse().unwrap();
+        assert_eq!(mjd.to_utc(), t);
+
+        assert_eq!(
+            "51544.5".parse::<Mjd>().unwrap().to_utc().to_string(),
+            "2000-01-01-12:00:00"
+        );
+        assert_eq!(
+            "40587".parse::<Mjd>().unwrap().to_utc(),
+            UtcTime::from_unix(0, 0)
+        );
+        assert_eq!("-0.25".parse::<Mjd>().unwrap(), Mjd {
+            day: -1,
+            nanos: NANOS_PER_DAY * 3 / 4
+        });
+        assert_eq!("1.x".parse::<Mjd>(), Err(PsrdadaError::TimeParseError));
+    }
+
+    #[test]
+    fn test_at_byte() {
+        let start: UtcTime = "2023-05-01-12:34:56".parse().unwrap();
+        // 3 bytes per second, so a byte is a third of a second
+        assert_eq!(start.at_byte(0, 3, 0), Some(start));
+        assert_eq!(start.at_byte(0, 0, 0), None);
+        assert_eq!(
+            start.at_byte(3, 3, 4).unwrap().to_string(),
+            "2023-05-01-12:34:58.333333333"
+        );
+        assert_eq!(
+            start.at_byte(1 << 40, 800_000_000, 0).unwrap() + Duration::from_nanos(250),
+            UtcTime::from_unix(start.unix_seconds() + 1374, 389_534_970)
+        );
+    }
+}
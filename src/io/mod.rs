@@ -28,11 +28,11 @@ pub trait DadaClient {
         unsafe { *self.buf(private::Token) }.state.into()
     }
 
-    fn reader(&mut self) -> PsrdadaResult<Reader> {
+    fn reader(&mut self) -> PsrdadaResult<Reader<'_>> {
         Reader::new(self)
     }
 
-    fn writer(&mut self) -> PsrdadaResult<Writer> {
+    fn writer(&mut self) -> PsrdadaResult<Writer<'_>> {
         Writer::new(self)
     }
 }
@@ -143,9 +143,70 @@ impl DadaClient for DataClient<'_> {
 }
 
 // Include the reading and writing modules
+pub mod overflow;
 pub mod read;
 pub mod write;
 
+/// Where a block sits in the stream of data, derived from the counters of the ringbuffer.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct BlockPosition {
+    /// Index of the block within its transfer, starting from zero
+    pub index: u64,
+    /// Offset in bytes of the first byte of the block since the start of data
+    pub byte_offset: u64,
+    /// The transfer this block belongs to, starting from zero
+    pub transfer: u64,
+}
+
+impl BlockPosition {
+    /// Compute the position of the block with absolute index `count` in transfer `xfer`.
+    ///
+    /// # Safety
+    /// `buf` must be a valid, connected ipcbuf
+    unsafe fn new(buf: *const ipcbuf_t, count: u64, xfer: u64) -> Self {
+        let sync = (*buf).sync;
+        let bufsz = (*sync).bufsz;
+        // The start of data markers are stored in a ring of their own
+        let slot = (xfer % IPCBUF_XFERS as u64) as usize;
+        let index = count.saturating_sub((*sync).s_buf[slot]);
+        Self {
+            index,
+            byte_offset: (index * bufsz).saturating_sub((*sync).s_byte[slot]),
+            transfer: xfer,
+        }
+    }
+
+    /// The position of the next block the writer will get
+    ///
+    /// # Safety
+    /// `buf` must be a valid ipcbuf, locked for writing
+    pub(crate) unsafe fn for_write(buf: *const ipcbuf_t) -> Self {
+        let count = ipcbuf_get_write_count(buf as *mut _);
+        Self::new(buf, count, (*(*buf).sync).w_xfer)
+    }
+
+    /// The position of the next block the reader will get
+    ///
+    /// # Safety
+    /// `buf` must be a valid ipcbuf, locked for reading
+    pub(crate) unsafe fn for_read(buf: *const ipcbuf_t) -> Self {
+        let count = ipcbuf_get_read_count(buf as *mut _);
+        let xfer = match usize::try_from((*buf).iread) {
+            Ok(iread) => (*(*buf).sync).r_xfers[iread],
+            // Viewers keep track of their own transfer
+            Err(_) => (*buf).xfer,
+        };
+        Self::new(buf, count, xfer)
+    }
+
+    /// Seconds between `UTC_START` and the first byte of this block.
+    ///
+    /// `obs_offset` and `bytes_per_second` are the `OBS_OFFSET` and `BYTES_PER_SECOND` fields from the header of this transfer.
+    pub fn seconds_since_start(&self, obs_offset: u64, bytes_per_second: f64) -> f64 {
+        (obs_offset + self.byte_offset) as f64 / bytes_per_second
+    }
+}
+
 #[repr(i32)]
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum State {
@@ -196,7 +257,10 @@ mod tests {
     use test_log::test;
 
     use crate::{
-        builder::DadaClientBuilder, client::HduClient, io::DadaClient, iter::DadaIterator,
+        builder::DadaClientBuilder,
+        client::HduClient,
+        io::{BlockPosition, DadaClient},
+        iter::DadaIterator,
         tests::next_key,
     };
 
@@ -266,4 +330,60 @@ mod tests {
 
     #[test]
     fn test_read_to_vec() {}
+
+    #[test]
+    fn test_block_positions() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key)
+            .num_bufs(4)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+
+        // Write one transfer of three blocks
+        let mut writer = dc.writer().unwrap();
+        for i in 0..3 {
+            let mut block = writer.next().unwrap();
+            assert_eq!(block.position(), BlockPosition {
+                index: i,
+                byte_offset: i * 4,
+                transfer: 0
+            });
+            block.write_all(&[0, 1, 2, 3]).unwrap();
+            if i == 2 {
+                block.mark_eod();
+            }
+        }
+        drop(writer);
+
+        // Read them back
+        let mut reader = dc.reader().unwrap();
+        let mut i = 0;
+        while let Some(block) = reader.next() {
+            assert_eq!(block.position().index, i);
+            assert_eq!(block.position().byte_offset, i * 4);
+            assert_eq!(block.position().transfer, 0);
+            i += 1;
+        }
+        assert_eq!(i, 3);
+        drop(reader);
+
+        // The next transfer starts counting from zero again
+        let mut writer = dc.writer().unwrap();
+        let block = writer.next().unwrap();
+        assert_eq!(block.position(), BlockPosition {
+            index: 0,
+            byte_offset: 0,
+            transfer: 1
+        });
+        block.commit();
+        drop(writer);
+        let mut reader = dc.reader().unwrap();
+        let block = reader.next().unwrap();
+        assert_eq!(block.position().index, 0);
+        assert_eq!(block.position().transfer, 1);
+        // With a 2 byte/s stream 10 bytes into the observation, the block starts 5 seconds after UTC_START
+        assert_eq!(block.position().seconds_since_start(10, 2.0), 5.0);
+    }
 }
@@ -0,0 +1,347 @@
+//! A writer that never blocks on a full ringbuffer.
+//!
+//! [`WriteBlock::new`] waits until the reader clears a block, which is exactly what you want for most pipelines. A packet capture
+//! front-end, however, can't stall - if the reader falls behind, packets are lost at the NIC and nobody notices. [`NonBlockingWriter`]
+//! instead applies an [`OverflowPolicy`] when there is no clear block and keeps count of everything it throws away in [`LossStats`].
+
+use std::collections::HashMap;
+
+use psrdada_sys::*;
+use tracing::{debug, warn};
+
+use super::{write::WriteBlock, DadaClient, State, Writer};
+use crate::{client::DataClient, errors::PsrdadaResult};
+
+/// The semaphore of `semid_connect` that only one reader at a time can hold, `IPCBUF_READ` in the C library
+const IPCBUF_READ: u16 = 1;
+/// `SEM_UNDO` has the same value on Linux and macOS, but isn't in every libc release for Linux
+const SEM_UNDO: i16 = 0x1000;
+
+/// Take the read lock like `ipcbuf_lock_read`, but give up rather than wait if another reader has it
+///
+/// # Safety
+/// `buf` has to be connected, and not already locked
+unsafe fn try_lock_read(buf: &mut ipcbuf_t) -> bool {
+    let mut op = libc::sembuf {
+        sem_num: IPCBUF_READ,
+        sem_op: -1,
+        sem_flg: libc::IPC_NOWAIT as i16 | SEM_UNDO,
+    };
+    if libc::semop(buf.semid_connect, &mut op, 1) != 0 {
+        return false;
+    }
+    // The rest of what `ipcbuf_lock_read` does for the first reader, which `ipcbuf_unlock_read` undoes along with the semaphore
+    buf.iread = 0;
+    buf.state = State::Reader as i32;
+    (*buf.sync).r_states[0] = State::Reader as i32;
+    true
+}
+
+/// What to do when the writer wants a block but the ring is full
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum OverflowPolicy {
+    /// Throw away the incoming block, keeping the data already in the ring
+    DropNewest,
+    /// Clear the oldest full block to make room for the new one.
+    ///
+    /// Clearing a block is the reader's job, so this only happens when the reader permits it by not holding the read lock
+    /// (i.e. while it is between transfers or restarting). Otherwise, this falls back to [`OverflowPolicy::DropNewest`].
+    OverwriteOldest,
+}
+
+/// Counters of the data thrown away by a [`NonBlockingWriter`]
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct LossStats {
+    /// Number of incoming blocks that were never written
+    pub dropped_blocks: u64,
+    /// Number of bytes in the blocks that were never written
+    pub dropped_bytes: u64,
+    /// Number of full blocks that were cleared before a reader saw them
+    pub overwritten_blocks: u64,
+    /// Number of bytes in the blocks that were cleared before a reader saw them
+    pub overwritten_bytes: u64,
+}
+
+impl LossStats {
+    /// Total number of blocks lost, regardless of policy
+    pub fn lost_blocks(&self) -> u64 {
+        self.dropped_blocks + self.overwritten_blocks
+    }
+
+    /// Total number of bytes lost, regardless of policy
+    pub fn lost_bytes(&self) -> u64 {
+        self.dropped_bytes + self.overwritten_bytes
+    }
+
+    /// Add the loss counters to a header, so readers can see them in the header stream.
+    ///
+    /// This sets `DROPPED_BLOCKS`, `DROPPED_BYTES`, `OVERWRITTEN_BLOCKS` and `OVERWRITTEN_BYTES`.
+    pub fn annotate(&self, header: &mut HashMap<String, String>) {
+        for (k, v) in [
+            ("DROPPED_BLOCKS", self.dropped_blocks),
+            ("DROPPED_BYTES", self.dropped_bytes),
+            ("OVERWRITTEN_BLOCKS", self.overwritten_blocks),
+            ("OVERWRITTEN_BYTES", self.overwritten_bytes),
+        ] {
+            header.insert(k.to_owned(), v.to_string());
+        }
+    }
+}
+
+/// A [`Writer`] that applies an [`OverflowPolicy`] instead of blocking when the ring is full.
+pub struct NonBlockingWriter<'a> {
+    writer: Writer<'a>,
+    key: i32,
+    policy: OverflowPolicy,
+    stats: LossStats,
+}
+
+impl<'a> NonBlockingWriter<'a> {
+    /// Lock the data ringbuffer for writing with the given overflow policy
+    pub fn new(client: &'a mut DataClient<'_>, policy: OverflowPolicy) -> PsrdadaResult<Self> {
+        let key = client.key;
+        Ok(Self {
+            writer: client.writer()?,
+            key,
+            policy,
+            stats: LossStats::default(),
+        })
+    }
+
+    /// The counters of everything lost so far
+    pub fn stats(&self) -> LossStats {
+        self.stats
+    }
+
+    /// Reset the loss counters, returning their previous values. Useful at the start of a new transfer.
+    pub fn reset_stats(&mut self) -> LossStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    /// Consume this writer, returning the underlying (blocking) [`Writer`]
+    pub fn into_inner(self) -> Writer<'a> {
+        self.writer
+    }
+
+    /// Get the next block if there is room for one, without blocking.
+    ///
+    /// Returns `None` if the block was dropped, in which case the caller should discard its data.
+    pub fn try_next(&mut self) -> Option<WriteBlock<'_>> {
+        // Safety: We hold the write lock for the lifetime of self
+        let (nclear, bufsz) = unsafe {
+            (
+                ipcbuf_get_nclear(self.writer.buf as *mut _),
+                ipcbuf_get_bufsz(self.writer.buf as *mut _),
+            )
+        };
+        if nclear == 0 {
+            let overwritten = match self.policy {
+                OverflowPolicy::DropNewest => None,
+                OverflowPolicy::OverwriteOldest => self.clear_oldest(),
+            };
+            match overwritten {
+                Some(bytes) => {
+                    self.stats.overwritten_blocks += 1;
+                    self.stats.overwritten_bytes += bytes;
+                }
+                None => {
+                    warn!("Ringbuffer full, dropping block");
+                    self.stats.dropped_blocks += 1;
+                    self.stats.dropped_bytes += bufsz;
+                    return None;
+                }
+            }
+        }
+        let block = WriteBlock::new(&mut self.writer);
+        if block.is_none() {
+            warn!("Couldn't get the next block, dropping it");
+            self.stats.dropped_blocks += 1;
+            self.stats.dropped_bytes += bufsz;
+        }
+        block
+    }
+
+    /// Whether a reader currently holds the read lock on our ring
+    fn reader_attached(&self) -> bool {
+        // Safety: The sync struct is valid as long as we're connected
+        unsafe {
+            let sync = (*self.writer.buf).sync;
+            let states = &(*sync).r_states;
+            states[..(*sync).n_readers as usize].iter().any(|s| {
+                matches!(
+                    State::from(*s),
+                    State::Reader | State::Reading | State::ReadStop
+                )
+            })
+        }
+    }
+
+    /// Temporarily become the reader to clear the oldest full block, returning the number of bytes it held.
+    fn clear_oldest(&mut self) -> Option<u64> {
+        if self.reader_attached() {
+            debug!("Reader is attached, not overwriting");
+            return None;
+        }
+        let mut buf = ipcbuf_t::default();
+        // Safety: We only ever get here with at least one full block, so reading won't block, and a reader that attached
+        // since we checked makes the lock fail instead of waiting
+        unsafe {
+            if ipcbuf_connect(&mut buf, self.key) != 0 {
+                return None;
+            }
+            let mut cleared = None;
+            if try_lock_read(&mut buf) {
+                let mut bytes = 0;
+                if !ipcbuf_get_next_read(&mut buf, &mut bytes).is_null()
+                    && ipcbuf_mark_cleared(&mut buf) == 0
+                {
+                    debug!(bytes, "Cleared the oldest block");
+                    cleared = Some(bytes);
+                }
+                ipcbuf_unlock_read(&mut buf);
+            }
+            ipcbuf_disconnect(&mut buf);
+            cleared
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use test_log::test;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, iter::DadaIterator, tests::next_key};
+
+    #[test]
+    fn test_drop_newest() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key)
+            .num_bufs(2)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+
+        let mut writer = NonBlockingWriter::new(&mut dc, OverflowPolicy::DropNewest).unwrap();
+        for i in 0..4 {
+            if let Some(mut block) = writer.try_next() {
+                block.write_all(&[i; 4]).unwrap();
+            }
+        }
+        assert_eq!(writer.stats(), LossStats {
+            dropped_blocks: 2,
+            dropped_bytes: 8,
+            ..Default::default()
+        });
+        let mut header = HashMap::new();
+        writer.stats().annotate(&mut header);
+        assert_eq!(header["DROPPED_BLOCKS"], "2");
+        drop(writer);
+
+        // The first two blocks survived
+        let mut reader = dc.reader().unwrap();
+        let mut buf = [0u8; 4];
+        reader.next().unwrap().read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0; 4]);
+        reader.next().unwrap().read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1; 4]);
+    }
+
+    #[test]
+    fn test_overwrite_oldest() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key)
+            .num_bufs(2)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+
+        let mut writer = NonBlockingWriter::new(&mut dc, OverflowPolicy::OverwriteOldest).unwrap();
+        for i in 0..4 {
+            let mut block = writer.try_next().unwrap();
+            block.write_all(&[i; 4]).unwrap();
+        }
+        assert_eq!(writer.stats().overwritten_blocks, 2);
+        assert_eq!(writer.stats().lost_bytes(), 8);
+        drop(writer);
+
+        // The last two blocks survived
+        let mut reader = dc.reader().unwrap();
+        let mut buf = [0u8; 4];
+        reader.next().unwrap().read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [2; 4]);
+        reader.next().unwrap().read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [3; 4]);
+    }
+
+    #[test]
+    fn test_overwrite_oldest_with_reader() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key)
+            .num_bufs(2)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+
+        // Hold the read lock from another connection
+        let mut reader_client = crate::client::HduClient::connect(key).unwrap();
+        let (_, mut reader_dc) = reader_client.split();
+        let _reader = reader_dc.reader().unwrap();
+
+        // So the writer isn't permitted to overwrite and drops instead
+        let mut writer = NonBlockingWriter::new(&mut dc, OverflowPolicy::OverwriteOldest).unwrap();
+        for i in 0..3 {
+            if let Some(mut block) = writer.try_next() {
+                block.write_all(&[i; 4]).unwrap();
+            }
+        }
+        assert_eq!(writer.stats().overwritten_blocks, 0);
+        assert_eq!(writer.stats().dropped_blocks, 1);
+    }
+
+    #[test]
+    fn test_overwrite_oldest_reader_attaching() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key)
+            .num_bufs(2)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+
+        // A reader partway through attaching has the read semaphore, but hasn't said so in the reader states yet
+        let mut buf = ipcbuf_t::default();
+        let mut op = libc::sembuf {
+            sem_num: IPCBUF_READ,
+            sem_op: -1,
+            sem_flg: SEM_UNDO,
+        };
+        // Safety: The buffer is connected before its semaphore is used
+        unsafe {
+            assert_eq!(ipcbuf_connect(&mut buf, key), 0);
+            assert_eq!(libc::semop(buf.semid_connect, &mut op, 1), 0);
+        }
+
+        // The writer drops rather than waiting for the lock
+        let mut writer = NonBlockingWriter::new(&mut dc, OverflowPolicy::OverwriteOldest).unwrap();
+        for i in 0..3 {
+            if let Some(mut block) = writer.try_next() {
+                block.write_all(&[i; 4]).unwrap();
+            }
+        }
+        assert_eq!(writer.stats().overwritten_blocks, 0);
+        assert_eq!(writer.stats().dropped_blocks, 1);
+
+        op.sem_op = 1;
+        // Safety: As above
+        unsafe {
+            libc::semop(buf.semid_connect, &mut op, 1);
+            ipcbuf_disconnect(&mut buf);
+        }
+    }
+}
@@ -3,8 +3,8 @@ use std::marker::PhantomData;
 use psrdada_sys::*;
 use tracing::{debug, error};
 
-use super::Reader;
-use crate::iter::DadaIterator;
+use super::{BlockPosition, Reader};
+use crate::iter::{DadaIterator, DadaIteratorItem};
 
 /// The state associated with an in-progress read. This must be dropped to perform more actions or consumed with [`done`].
 ///
@@ -13,6 +13,7 @@ pub struct ReadBlock<'a> {
     buf: *const ipcbuf_t,
     bytes_read: usize,
     bytes: &'a [u8],
+    position: BlockPosition,
     _phantom: PhantomData<&'a ipcbuf_t>,
 }
 
@@ -30,6 +31,8 @@ impl ReadBlock<'_> {
         // Following `ipcio` lines 493 onwards
         // Grab the pointer to the next available readable memory
         debug!("Grabbing next readable block");
+        // Safety: The reader holds the read lock
+        let position = unsafe { BlockPosition::for_read(reader.buf) };
         let mut block_size = 0;
         let ptr =
             unsafe { ipcbuf_get_next_read(reader.buf as *mut _, &mut block_size) } as *const u8;
@@ -47,6 +50,7 @@ impl ReadBlock<'_> {
             buf: reader.buf,
             bytes_read: 0,
             bytes,
+            position,
             _phantom: PhantomData,
         })
     }
@@ -58,6 +62,30 @@ impl ReadBlock<'_> {
     pub fn block(&mut self) -> &[u8] {
         self.bytes
     }
+
+    /// Get where this block sits in the stream of data.
+    pub fn position(&self) -> BlockPosition {
+        self.position
+    }
+}
+
+/// Anything that lends out blocks of data like a [`ReadBlock`], so processing can be written once for rings and files alike
+pub trait ReadableBlock: std::io::Read {
+    /// Get the underlying block of bytes for this block.
+    fn block(&mut self) -> &[u8];
+
+    /// Get where this block sits in the stream of data.
+    fn position(&self) -> BlockPosition;
+}
+
+impl ReadableBlock for ReadBlock<'_> {
+    fn block(&mut self) -> &[u8] {
+        self.bytes
+    }
+
+    fn position(&self) -> BlockPosition {
+        self.position
+    }
 }
 
 impl Drop for ReadBlock<'_> {
@@ -70,12 +98,12 @@ impl Drop for ReadBlock<'_> {
 }
 
 // Implement our lending iterator for the read blocks
-impl DadaIterator for Reader<'_> {
-    type Item<'next> = ReadBlock<'next>
-    where
-        Self: 'next;
+impl<'next> DadaIteratorItem<'next> for Reader<'_> {
+    type Item = ReadBlock<'next>;
+}
 
-    fn next(&mut self) -> Option<Self::Item<'_>> {
+impl DadaIterator for Reader<'_> {
+    fn next(&mut self) -> Option<ReadBlock<'_>> {
         ReadBlock::new(self)
     }
 }
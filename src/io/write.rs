@@ -1,10 +1,16 @@
-use std::{io::Write, marker::PhantomData};
+use std::{
+    io::{BufRead, ErrorKind, Write},
+    marker::PhantomData,
+};
 
 use psrdada_sys::*;
 use tracing::{debug, error};
 
-use super::Writer;
-use crate::iter::DadaIterator;
+use super::{BlockPosition, Writer};
+use crate::{
+    errors::{PsrdadaError, PsrdadaResult},
+    iter::{DadaIterator, DadaIteratorItem},
+};
 
 /// The state associated with an in-progress write. This must be dropped (or [`commit`]ed) to perform more actions.
 ///
@@ -16,6 +22,7 @@ pub struct WriteBlock<'a> {
     bytes: &'a mut [u8],
     _phantom: PhantomData<&'a ipcbuf_t>,
     eod: bool,
+    position: BlockPosition,
 }
 
 impl WriteBlock<'_> {
@@ -36,6 +43,8 @@ impl WriteBlock<'_> {
             }
             return None;
         }
+        // Safety: The writer holds the write lock and we now have the block
+        let position = unsafe { BlockPosition::for_write(writer.buf) };
         // Convert to a mutable slice
         let bufsz = unsafe { ipcbuf_get_bufsz(writer.buf as *mut _) } as usize;
         // Safety:
@@ -50,6 +59,7 @@ impl WriteBlock<'_> {
             eod: false,
             bytes,
             _phantom: PhantomData,
+            position,
         })
     }
 
@@ -67,6 +77,11 @@ impl WriteBlock<'_> {
         self.bytes
     }
 
+    /// Get where this block sits in the stream of data.
+    pub fn position(&self) -> BlockPosition {
+        self.position
+    }
+
     /// Increment our internal counter of how many bytes we have written, overriding the "write all" default
     /// behavior.
     pub fn increment_filled(&mut self, n: usize) {
@@ -104,16 +119,70 @@ impl Drop for WriteBlock<'_> {
 }
 
 // Implement the lending iterator
-impl DadaIterator for Writer<'_> {
-    type Item<'next> = WriteBlock<'next>
-    where
-        Self: 'next;
+impl<'next> DadaIteratorItem<'next> for Writer<'_> {
+    type Item = WriteBlock<'next>;
+}
 
-    fn next(&mut self) -> Option<Self::Item<'_>> {
+impl DadaIterator for Writer<'_> {
+    fn next(&mut self) -> Option<WriteBlock<'_>> {
         WriteBlock::new(self)
     }
 }
 
+impl Writer<'_> {
+    /// Fill blocks from `data` until it runs dry, ending the transfer after the last byte, and return the number of bytes written.
+    ///
+    /// `after_block` is called with the running total after each block is committed.
+    pub(crate) fn write_from(
+        &mut self,
+        data: &mut impl BufRead,
+        mut after_block: impl FnMut(u64),
+    ) -> PsrdadaResult<u64> {
+        let mut bytes = 0;
+        loop {
+            let mut block = self.next().ok_or(PsrdadaError::DadaWriteError)?;
+            let buf = block.block();
+            let len = buf.len();
+            let mut n = 0;
+            // Fill the block, then check whether there's anything left for the next one
+            let done = loop {
+                if n == len {
+                    break data.fill_buf().map(|rest| rest.is_empty());
+                }
+                match data.read(&mut buf[n..]) {
+                    Ok(0) => break Ok(true),
+                    Ok(read) => n += read,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => break Err(e),
+                }
+            };
+            let done = match done {
+                Ok(done) => done,
+                Err(e) => {
+                    // End the transfer after what was read, rather than committing the whole block
+                    block.increment_filled(n);
+                    block.mark_eod();
+                    block.commit();
+                    return Err(e.into());
+                }
+            };
+            let full = n == len;
+            if !full {
+                // A partial block implicitly ends the transfer
+                block.increment_filled(n);
+            } else if done {
+                block.mark_eod();
+            }
+            block.commit();
+            bytes += n as u64;
+            after_block(bytes);
+            if done {
+                return Ok(bytes);
+            }
+        }
+    }
+}
+
 // Implement std::io Write for the WriteBlock
 impl Write for WriteBlock<'_> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
@@ -158,6 +227,47 @@ mod tests {
         // And leaving scope should clean it all up
     }
 
+    /// Hands out some bytes, then fails
+    struct Failing(usize);
+
+    impl std::io::Read for Failing {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0 == 0 {
+                return Err(ErrorKind::InvalidData.into());
+            }
+            let n = self.0.min(buf.len());
+            buf[..n].fill(7);
+            self.0 -= n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_write_from_failing_reader() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key)
+            .num_bufs(4)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+        let mut writer = dc.writer().unwrap();
+        let mut data = std::io::BufReader::with_capacity(3, Failing(6));
+        assert_eq!(
+            writer.write_from(&mut data, |_| ()),
+            Err(PsrdadaError::IoError(ErrorKind::InvalidData))
+        );
+        drop(writer);
+
+        // The transfer ends after the bytes that were read
+        let mut reader = dc.reader().unwrap();
+        let mut bytes = vec![];
+        while let Some(mut block) = reader.next() {
+            bytes.extend_from_slice(block.block());
+        }
+        assert_eq!(bytes, [7; 6]);
+    }
+
     #[test]
     fn test_bad_write() {
         let key = next_key();
@@ -1,11 +1,316 @@
-//! A trait that we will use that leverages [generic associated types](https://blog.rust-lang.org/2022/10/28/gats-stabilization.html)
-//! to create a dada iterator that garuntees that references to a given buffer only exist when it is safe to do so.
+//! A lending iterator trait that guarantees that references to a given buffer only exist when it is safe to do so.
+//!
+//! We originally used [generic associated types](https://blog.rust-lang.org/2022/10/28/gats-stabilization.html) for this,
+//! but a GAT with a `where Self: 'next` bound can't be named inside a closure bound (like `FnMut(Self::Item<'_>)`)
+//! without the compiler demanding that the iterator is `'static`. Instead, the item type lives in [`DadaIteratorItem`], whose
+//! defaulted type parameter carries that bound implicitly. This is the workaround described
+//! [here](https://sabrinajewson.org/blog/the-better-alternative-to-lifetime-gats).
+//!
+//! Because every item borrows from the iterator that produced it, the usual [`Iterator`] adapters can't be used.
+//! Instead, this module provides lending versions of the common ones. None of them hand out a block in a way that lets it
+//! outlive the next call to [`DadaIterator::next`], so the block is always marked cleared (or filled) before the next one is grabbed.
 
-pub trait DadaIterator {
-    type Item<'next>
-    where
-        Self: 'next;
+mod sealed {
+    pub trait Sealed: Sized {}
+    pub struct Bounds<T>(T);
+    impl<T> Sealed for Bounds<T> {}
+}
+
+use sealed::{Bounds, Sealed};
+
+/// The type of item a [`DadaIterator`] lends out for a given borrow of the iterator.
+///
+/// The second type parameter should always be left as its default.
+pub trait DadaIteratorItem<'next, ImplicitBounds: Sealed = Bounds<&'next Self>> {
+    type Item;
+}
+
+/// Shorthand for the item of a [`DadaIterator`] `I` that borrows it for `'next`.
+pub type Item<'next, I> = <I as DadaIteratorItem<'next>>::Item;
 
+pub trait DadaIterator: for<'next> DadaIteratorItem<'next> {
     /// Gets the next block a la [`Iterator::next`].
-    fn next(&mut self) -> Option<Self::Item<'_>>;
+    fn next(&mut self) -> Option<Item<'_, Self>>;
+
+    /// Calls a closure on every block, a la [`Iterator::for_each`].
+    ///
+    /// Each block is dropped (and therefore released back to the ring) as soon as the closure returns.
+    fn for_each<F>(mut self, mut f: F)
+    where
+        Self: Sized,
+        F: FnMut(Item<'_, Self>),
+    {
+        while let Some(item) = self.next() {
+            f(item);
+        }
+    }
+
+    /// Calls a fallible closure on every block, stopping at the first error, a la [`Iterator::try_for_each`].
+    fn try_for_each<F, E>(mut self, mut f: F) -> Result<(), E>
+    where
+        Self: Sized,
+        F: FnMut(Item<'_, Self>) -> Result<(), E>,
+    {
+        while let Some(item) = self.next() {
+            f(item)?;
+        }
+        Ok(())
+    }
+
+    /// Creates an iterator that yields at most `n` blocks, a la [`Iterator::take`].
+    fn take(self, n: usize) -> Take<Self>
+    where
+        Self: Sized,
+    {
+        Take { iter: self, n }
+    }
+
+    /// Creates an iterator that pairs blocks from two iterators, a la [`Iterator::zip`].
+    ///
+    /// This is most useful to pair a [`Reader`](crate::io::Reader) with a [`Writer`](crate::io::Writer).
+    /// If `self` yields a block but `other` doesn't, the block from `self` is dropped before returning `None`.
+    fn zip<U>(self, other: U) -> Zip<Self, U>
+    where
+        Self: Sized,
+        U: DadaIterator,
+    {
+        Zip { a: self, b: other }
+    }
+
+    /// Creates an iterator that yields each block alongside its sequence number (starting from zero), a la [`Iterator::enumerate`].
+    fn enumerate(self) -> Enumerate<Self>
+    where
+        Self: Sized,
+    {
+        Enumerate {
+            iter: self,
+            count: 0,
+        }
+    }
+
+    /// Creates an iterator that calls a closure on every block and yields its result, a la [`Iterator::map`].
+    ///
+    /// The output type can't depend on the lifetime of the block, so data has to be copied out of it.
+    fn map_blocks<F, R>(self, f: F) -> MapBlocks<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(Item<'_, Self>) -> R,
+    {
+        MapBlocks { iter: self, f }
+    }
+
+    /// Borrows the iterator, rather than consuming it, a la [`Iterator::by_ref`].
+    fn by_ref(&mut self) -> &mut Self
+    where
+        Self: Sized,
+    {
+        self
+    }
+}
+
+impl<'next, I: DadaIterator + ?Sized> DadaIteratorItem<'next> for &mut I {
+    type Item = Item<'next, I>;
+}
+
+impl<I: DadaIterator + ?Sized> DadaIterator for &mut I {
+    fn next(&mut self) -> Option<Item<'_, Self>> {
+        (**self).next()
+    }
+}
+
+/// A lending iterator that only yields the first `n` blocks, created by [`DadaIterator::take`].
+pub struct Take<I> {
+    iter: I,
+    n: usize,
+}
+
+impl<'next, I: DadaIterator> DadaIteratorItem<'next> for Take<I> {
+    type Item = Item<'next, I>;
+}
+
+impl<I: DadaIterator> DadaIterator for Take<I> {
+    fn next(&mut self) -> Option<Item<'_, Self>> {
+        if self.n == 0 {
+            None
+        } else {
+            self.n -= 1;
+            self.iter.next()
+        }
+    }
+}
+
+/// A lending iterator that yields blocks from two iterators in lockstep, created by [`DadaIterator::zip`].
+pub struct Zip<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<'next, A: DadaIterator, B: DadaIterator> DadaIteratorItem<'next> for Zip<A, B> {
+    type Item = (Item<'next, A>, Item<'next, B>);
+}
+
+impl<A: DadaIterator, B: DadaIterator> DadaIterator for Zip<A, B> {
+    fn next(&mut self) -> Option<Item<'_, Self>> {
+        let a = self.a.next()?;
+        let b = self.b.next()?;
+        Some((a, b))
+    }
+}
+
+/// A lending iterator that yields blocks alongside their sequence number, created by [`DadaIterator::enumerate`].
+pub struct Enumerate<I> {
+    iter: I,
+    count: usize,
+}
+
+impl<'next, I: DadaIterator> DadaIteratorItem<'next> for Enumerate<I> {
+    type Item = (usize, Item<'next, I>);
+}
+
+impl<I: DadaIterator> DadaIterator for Enumerate<I> {
+    fn next(&mut self) -> Option<Item<'_, Self>> {
+        let item = self.iter.next()?;
+        let i = self.count;
+        self.count += 1;
+        Some((i, item))
+    }
+}
+
+/// A lending iterator that maps blocks to owned values, created by [`DadaIterator::map_blocks`].
+pub struct MapBlocks<I, F> {
+    iter: I,
+    f: F,
+}
+
+impl<'next, I, F, R> DadaIteratorItem<'next> for MapBlocks<I, F>
+where
+    I: DadaIterator,
+    F: FnMut(Item<'_, I>) -> R,
+{
+    type Item = R;
+}
+
+impl<I, F, R> DadaIterator for MapBlocks<I, F>
+where
+    I: DadaIterator,
+    F: FnMut(Item<'_, I>) -> R,
+{
+    fn next(&mut self) -> Option<R> {
+        self.iter.next().map(&mut self.f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use test_log::test;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, io::DadaClient, tests::next_key};
+
+    #[test]
+    fn test_take_and_for_each() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key)
+            .num_bufs(4)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+
+        let mut writer = dc.writer().unwrap();
+        let mut i = 0;
+        writer.by_ref().take(4).for_each(|mut block| {
+            block.write_all(&[i; 4]).unwrap();
+            i += 1;
+        });
+        assert_eq!(i, 4);
+        drop(writer);
+
+        let mut reader = dc.reader().unwrap();
+        let mut seen = vec![];
+        reader.by_ref().take(4).for_each(|mut block| {
+            let mut buf = [0u8; 4];
+            block.read_exact(&mut buf).unwrap();
+            seen.push(buf[0]);
+        });
+        assert_eq!(seen, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_try_for_each() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key)
+            .num_bufs(4)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+
+        let writer = dc.writer().unwrap();
+        let res = writer.enumerate().try_for_each(|(i, mut block)| {
+            block.write_all(&[0, 1, 2, 3]).unwrap();
+            if i == 2 {
+                Err(i)
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(res, Err(2));
+    }
+
+    #[test]
+    fn test_zip_enumerate_map_blocks() {
+        let in_key = next_key();
+        let out_key = next_key();
+        let mut in_client = DadaClientBuilder::new(in_key)
+            .num_bufs(4)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let mut out_client = DadaClientBuilder::new(out_key)
+            .num_bufs(4)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let (_, mut in_dc) = in_client.split();
+        let (_, mut out_dc) = out_client.split();
+
+        // Fill the input, marking the last block as EOD
+        let writer = in_dc.writer().unwrap();
+        writer.enumerate().take(4).for_each(|(i, mut block)| {
+            block.write_all(&[i as u8; 4]).unwrap();
+            if i == 3 {
+                block.mark_eod();
+            }
+        });
+
+        // Transform input into output
+        let reader = in_dc.reader().unwrap();
+        let writer = out_dc.writer().unwrap();
+        reader
+            .zip(writer)
+            .enumerate()
+            .for_each(|(i, (mut rb, mut wb))| {
+                assert_eq!(rb.block(), &[i as u8; 4]);
+                wb.block()
+                    .iter_mut()
+                    .zip(rb.block())
+                    .for_each(|(x, y)| *x = *y * 2);
+                if i == 3 {
+                    wb.mark_eod();
+                }
+            });
+
+        // And read back the output, copying out of each block
+        let reader = out_dc.reader().unwrap();
+        let mut sums =
+            reader.map_blocks(|mut rb| rb.block().iter().map(|x| *x as u32).sum::<u32>());
+        let mut collected = vec![];
+        while let Some(sum) = sums.next() {
+            collected.push(sum);
+        }
+        assert_eq!(collected, [0, 8, 16, 24]);
+    }
 }
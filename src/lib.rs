@@ -2,7 +2,7 @@
 //!
 //! [![license](https://img.shields.io/badge/license-Apache--2.0_OR_MIT-blue?style=flat-square)](#license)
 //! [![docs](https://img.shields.io/docsrs/psrdada?logo=rust&style=flat-square)](https://docs.rs/psrdada/latest/psrdada/index.html)
-//! [![rustc](https://img.shields.io/badge/rustc-1.60+-blue?style=flat-square&logo=rust)](https://www.rust-lang.org)
+//! [![rustc](https://img.shields.io/badge/rustc-1.65+-blue?style=flat-square&logo=rust)](https://www.rust-lang.org)
 //! [![build status](https://img.shields.io/github/actions/workflow/status/kiranshila/psrdada-rs/ci.yml?branch=main?style=flat-square&logo=github)](https://github.com/kiranshila/psrdada-rs/actions)
 //! [![Codecov](https://img.shields.io/codecov/c/github/kiranshila/psrdada-rs?style=flat-square)](https://app.codecov.io/gh/kiranshila/psrdada-rs)
 //!
@@ -80,12 +80,28 @@
 //!
 //! See [LICENSE-APACHE](LICENSE-APACHE) and [LICENSE-MIT](LICENSE-MIT) for details.
 
+pub mod bridge;
 pub mod builder;
+#[cfg(feature = "cli")]
+pub mod cli;
 pub mod client;
+#[cfg(target_os = "linux")]
+pub mod direct;
+pub mod disks;
 pub mod errors;
+pub mod file;
+mod fits;
+pub mod guppi;
 pub mod headers;
 pub mod io;
 pub mod iter;
 pub mod prelude;
+pub mod psrfits;
+pub mod pwc;
+pub mod recorder;
+pub mod replayer;
+pub mod sigproc;
 #[cfg(test)]
 mod tests;
+pub mod udp;
+pub mod vdif;
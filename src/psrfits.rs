@@ -0,0 +1,499 @@
+//! Writing search-mode PSRFITS files, without cfitsio.
+//!
+//! A FITS file is a series of header/data units (HDUs), each made of 2880 byte records. Headers are 80 character ASCII cards
+//! ending with `END`, and binary tables are big-endian. A search-mode PSRFITS file needs two of them:
+//!
+//! - The primary HDU, with no data, whose header describes the observation. It's filled in from the [`StandardHeader`] (`FREQ`,
+//!   `BW`, `NCHAN`, `SOURCE`, `RA`, `DEC`, `TELESCOPE`, and the start time from `UTC_START` and `OBS_OFFSET`), and the `OBSERVER`,
+//!   `PID`, `RECEIVER` and `INSTRUMENT` keywords if they're there.
+//! - The `SUBINT` binary table, with a row for every `NSBLK` samples holding `TSUBINT`, `OFFS_SUB`, `DAT_FREQ`, `DAT_WTS`,
+//!   `DAT_OFFS`, `DAT_SCL` and the `DATA` itself.
+//!
+//! The data is copied as is, so it has to be in the order PSRFITS expects: time-major, then polarization, then channel, with
+//! `NBIT` of 1, 2, 4 or 8. The weights and scales are all one and the offsets all zero. The number of rows isn't known until the
+//! end, so [`PsrfitsWriter::finish`] seeks back to fill it in; a last, partial subint is padded with zeros.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+use tracing::warn;
+
+use crate::{
+    errors::PsrdadaResult,
+    fits::{align, FitsHeader, Value, CARD, RECORD},
+    headers::{
+        standard::{required, value_error},
+        StandardHeader, UtcTime,
+    },
+    io::read::ReadableBlock,
+    iter::{DadaIterator, Item},
+};
+
+/// `YYYY-MM-DDThh:mm:ss`, as FITS writes dates
+fn fits_date(t: UtcTime) -> String {
+    let (year, month, day, hour, minute, second) = t.to_calendar();
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}")
+}
+
+/// Writes a search-mode PSRFITS file, see the [module docs](self)
+pub struct PsrfitsWriter<W: Write + Seek> {
+    inner: W,
+    /// Where the `NAXIS2` card of the `SUBINT` header is, to fill in the number of rows at the end
+    naxis2_at: u64,
+    /// Everything in a row that comes between `OFFS_SUB` and `DATA`, which is the same for every row
+    row_middle: Vec<u8>,
+    /// The data of the subint being filled
+    data: Vec<u8>,
+    filled: usize,
+    tsubint: f64,
+    rows: u64,
+}
+
+impl PsrfitsWriter<BufWriter<File>> {
+    /// Create (or truncate) a file at `path` with subints of `nsblk` samples, see [`PsrfitsWriter::new`]
+    pub fn create(
+        path: impl AsRef<Path>,
+        header: &StandardHeader,
+        nsblk: usize,
+    ) -> PsrdadaResult<Self> {
+        Self::new(BufWriter::new(File::create(path)?), header, nsblk)
+    }
+}
+
+impl<W: Write + Seek> PsrfitsWriter<W> {
+    /// Write the primary HDU and the header of the `SUBINT` table, with subints of `nsblk` samples.
+    ///
+    /// The header needs `NCHAN`, `NBIT`, `TSAMP`, `FREQ`, `BW` and `UTC_START`, failing with
+    /// [`PsrdadaError::HeaderMissingKey`](crate::errors::PsrdadaError::HeaderMissingKey) otherwise. Fails with
+    /// [`PsrdadaError::HeaderValueError`](crate::errors::PsrdadaError::HeaderValueError) for an `NBIT` PSRFITS can't hold as
+    /// bytes, complex data (`NDIM` other than 1), or if a subint wouldn't be a whole number of bytes.
+    pub fn new(mut inner: W, header: &StandardHeader, nsblk: usize) -> PsrdadaResult<Self> {
+        let nchan = required(header.nchan, "NCHAN")? as usize;
+        let nbit = required(header.nbit, "NBIT")? as usize;
+        let npol = header.npol.unwrap_or(1) as usize;
+        let tbin = required(header.tsamp, "TSAMP")? / 1e6;
+        let freq = required(header.freq, "FREQ")?;
+        let bw = required(header.bw, "BW")?;
+        let start = required(header.time_at_byte(0).or(header.utc_start), "UTC_START")?;
+        if ![1, 2, 4, 8].contains(&nbit) {
+            return Err(value_error("NBIT", nbit));
+        }
+        if let Some(ndim) = header.ndim.filter(|ndim| *ndim != 1) {
+            return Err(value_error("NDIM", ndim));
+        }
+        let data_bits = nsblk * nchan * npol * nbit;
+        if nsblk == 0 || data_bits % 8 != 0 {
+            return Err(value_error("NSBLK", nsblk));
+        }
+        let data_bytes = data_bits / 8;
+        let chan_bw = bw / nchan as f64;
+        let freqs: Vec<_> = (0..nchan)
+            .map(|i| freq - bw / 2.0 + chan_bw * (i as f64 + 0.5))
+            .collect();
+        let extra = |key: &str| header.extras.get(key).map(String::as_str).unwrap_or("");
+
+        let mjd = start.to_mjd();
+        let mut primary = FitsHeader::default();
+        primary
+            .card(
+                "SIMPLE",
+                Value::Logical(true),
+                "File conforms to FITS standard",
+            )
+            .card("BITPIX", Value::Int(8), "")
+            .card("NAXIS", Value::Int(0), "No data in the primary HDU")
+            .card("EXTEND", Value::Logical(true), "Extensions may be present")
+            .card("HDRVER", Value::Str("6.1"), "Header version")
+            .card(
+                "FITSTYPE",
+                Value::Str("PSRFITS"),
+                "FITS definition for pulsar data",
+            )
+            .card(
+                "DATE",
+                Value::Str(&fits_date(SystemTime::now().into())),
+                "File creation date",
+            )
+            .card(
+                "OBSERVER",
+                Value::Str(extra("OBSERVER")),
+                "Observer name(s)",
+            )
+            .card("PROJID", Value::Str(extra("PID")), "Project name")
+            .card(
+                "TELESCOP",
+                Value::Str(header.telescope.as_deref().unwrap_or("")),
+                "Telescope name",
+            )
+            .card("FRONTEND", Value::Str(extra("RECEIVER")), "Receiver ID")
+            .card("BACKEND", Value::Str(extra("INSTRUMENT")), "Backend ID")
+            .card("OBS_MODE", Value::Str("SEARCH"), "(PSR, CAL, SEARCH)")
+            .card(
+                "DATE-OBS",
+                Value::Str(&fits_date(start)),
+                "Date of observation",
+            )
+            .card("OBSFREQ", Value::Float(freq), "[MHz] Centre frequency")
+            .card("OBSBW", Value::Float(bw), "[MHz] Bandwidth")
+            .card(
+                "OBSNCHAN",
+                Value::Int(nchan as i64),
+                "Number of frequency channels",
+            )
+            .card(
+                "SRC_NAME",
+                Value::Str(header.source.as_deref().unwrap_or("")),
+                "Source name",
+            )
+            .card("COORD_MD", Value::Str("J2000"), "Coordinate mode")
+            .card("EQUINOX", Value::Float(2000.0), "Equinox of coords")
+            .card(
+                "RA",
+                Value::Str(&header.ra.map(|ra| ra.to_string()).unwrap_or_default()),
+                "Right ascension (hh:mm:ss)",
+            )
+            .card(
+                "DEC",
+                Value::Str(&header.dec.map(|dec| dec.to_string()).unwrap_or_default()),
+                "Declination (-dd:mm:ss)",
+            )
+            .card("TRK_MODE", Value::Str("TRACK"), "Track mode")
+            .card("STT_IMJD", Value::Int(mjd.day), "Start MJD (UTC days)")
+            .card(
+                "STT_SMJD",
+                Value::Int((mjd.nanos / 1_000_000_000) as i64),
+                "[s] Start time (sec past UTC 00h)",
+            )
+            .card(
+                "STT_OFFS",
+                Value::Float((mjd.nanos % 1_000_000_000) as f64 / 1e9),
+                "[s] Start time offset",
+            );
+
+        // TSUBINT, OFFS_SUB, DAT_FREQ, DAT_WTS, DAT_OFFS, DAT_SCL and DATA
+        let columns = [
+            ("TSUBINT", "1D".to_owned(), "s"),
+            ("OFFS_SUB", "1D".to_owned(), "s"),
+            ("DAT_FREQ", format!("{nchan}D"), "MHz"),
+            ("DAT_WTS", format!("{nchan}E"), ""),
+            ("DAT_OFFS", format!("{}E", nchan * npol), ""),
+            ("DAT_SCL", format!("{}E", nchan * npol), ""),
+            ("DATA", format!("{data_bytes}B"), "Jy"),
+        ];
+        let mut row_middle = vec![];
+        freqs
+            .iter()
+            .for_each(|f| row_middle.extend_from_slice(&f.to_be_bytes()));
+        (0..nchan).for_each(|_| row_middle.extend_from_slice(&1f32.to_be_bytes()));
+        (0..nchan * npol).for_each(|_| row_middle.extend_from_slice(&0f32.to_be_bytes()));
+        (0..nchan * npol).for_each(|_| row_middle.extend_from_slice(&1f32.to_be_bytes()));
+        let row_len = 16 + row_middle.len() + data_bytes;
+
+        let mut subint = FitsHeader::default();
+        subint
+            .card("XTENSION", Value::Str("BINTABLE"), "Binary table extension")
+            .card("BITPIX", Value::Int(8), "")
+            .card("NAXIS", Value::Int(2), "2-dimensional binary table")
+            .card(
+                "NAXIS1",
+                Value::Int(row_len as i64),
+                "Width of table in bytes",
+            )
+            .card("NAXIS2", Value::Int(0), "Number of rows")
+            .card("PCOUNT", Value::Int(0), "")
+            .card("GCOUNT", Value::Int(1), "")
+            .card(
+                "TFIELDS",
+                Value::Int(columns.len() as i64),
+                "Number of fields per row",
+            );
+        for (i, (name, form, unit)) in columns.iter().enumerate() {
+            let n = i + 1;
+            subint.card(&format!("TTYPE{n}"), Value::Str(name), "");
+            subint.card(&format!("TFORM{n}"), Value::Str(form), "");
+            if !unit.is_empty() {
+                subint.card(&format!("TUNIT{n}"), Value::Str(unit), "");
+            }
+        }
+        if nbit == 8 {
+            subint.card(
+                &format!("TDIM{}", columns.len()),
+                Value::Str(&format!("(1,{nchan},{npol},{nsblk})")),
+                "Dimensions (NBIN,NCHAN,NPOL,NSBLK)",
+            );
+        }
+        let pol_type = match npol {
+            1 => "AA+BB",
+            2 => "AABB",
+            _ => "AABBCRCI",
+        };
+        subint
+            .card(
+                "EXTNAME",
+                Value::Str("SUBINT"),
+                "Name of this binary table extension",
+            )
+            .card("INT_TYPE", Value::Str("TIME"), "Time axis")
+            .card("INT_UNIT", Value::Str("SEC"), "Unit of time axis")
+            .card("SCALE", Value::Str("FluxDen"), "Intensity units")
+            .card("POL_TYPE", Value::Str(pol_type), "Polarisation identifier")
+            .card("NPOL", Value::Int(npol as i64), "Nr of polarisations")
+            .card("TBIN", Value::Float(tbin), "[s] Time per bin or sample")
+            .card("NBIN", Value::Int(1), "Nr of bins (PSR/CAL mode; else 1)")
+            .card("NCHAN", Value::Int(nchan as i64), "Number of channels")
+            .card("CHAN_BW", Value::Float(chan_bw), "[MHz] Channel bandwidth")
+            .card("NCHNOFFS", Value::Int(0), "Channel offset in the file")
+            .card("NSBLK", Value::Int(nsblk as i64), "Samples per row")
+            .card("NBITS", Value::Int(nbit as i64), "Nr of bits per sample")
+            .card(
+                "ZERO_OFF",
+                Value::Float(0.0),
+                "Zero offset for SEARCH-mode data",
+            )
+            .card(
+                "SIGNINT",
+                Value::Int(0),
+                "1 for signed ints in SEARCH-mode data, else 0",
+            )
+            .card("NSUBOFFS", Value::Int(0), "Subint offset in the file");
+
+        let primary = primary.to_bytes();
+        inner.write_all(&primary)?;
+        inner.write_all(&subint.to_bytes())?;
+        Ok(Self {
+            inner,
+            naxis2_at: (primary.len() + 4 * CARD) as u64,
+            row_middle,
+            data: vec![0; data_bytes],
+            filled: 0,
+            tsubint: nsblk as f64 * tbin,
+            rows: 0,
+        })
+    }
+
+    /// The number of complete subints written so far
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    fn write_row(&mut self) -> PsrdadaResult<()> {
+        let offs_sub = (self.rows as f64 + 0.5) * self.tsubint;
+        self.inner.write_all(&self.tsubint.to_be_bytes())?;
+        self.inner.write_all(&offs_sub.to_be_bytes())?;
+        self.inner.write_all(&self.row_middle)?;
+        self.inner.write_all(&self.data)?;
+        self.rows += 1;
+        self.filled = 0;
+        Ok(())
+    }
+
+    /// Add data, writing out every subint that fills up
+    pub fn write_data(&mut self, mut data: &[u8]) -> PsrdadaResult<()> {
+        while !data.is_empty() {
+            let n = (self.data.len() - self.filled).min(data.len());
+            self.data[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if self.filled == self.data.len() {
+                self.write_row()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the data of every block lent out by `blocks` (a data [`Reader`](crate::io::Reader) or a
+    /// [`DadaFileReader`](crate::file::DadaFileReader)), returning the number of bytes added
+    pub fn write_blocks<I>(&mut self, blocks: &mut I) -> PsrdadaResult<u64>
+    where
+        I: DadaIterator,
+        for<'a> Item<'a, I>: ReadableBlock,
+    {
+        let mut bytes = 0;
+        while let Some(mut block) = blocks.next() {
+            let data = block.block();
+            bytes += data.len() as u64;
+            self.write_data(data)?;
+        }
+        Ok(bytes)
+    }
+
+    /// Write out the last subint, padded with zeros, fill in the number of rows and get back the underlying writer
+    pub fn finish(mut self) -> PsrdadaResult<W> {
+        if self.filled > 0 {
+            warn!(
+                missing = self.data.len() - self.filled,
+                "Padding the last subint with zeros"
+            );
+            self.data[self.filled..].fill(0);
+            self.write_row()?;
+        }
+        let end = self.inner.stream_position()?;
+        let padding = align(end as usize, RECORD) - end as usize;
+        self.inner.write_all(&vec![0; padding])?;
+
+        let mut naxis2 = FitsHeader::default();
+        naxis2.card("NAXIS2", Value::Int(self.rows as i64), "Number of rows");
+        self.inner.seek(SeekFrom::Start(self.naxis2_at))?;
+        self.inner.write_all(&naxis2.cards)?;
+        self.inner.seek(SeekFrom::End(0))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Cursor};
+
+    use test_log::test;
+
+    use super::*;
+    use crate::{
+        builder::DadaClientBuilder, errors::PsrdadaError, headers::bytes_to_header, io::DadaClient,
+        tests::next_key,
+    };
+
+    const DADA: &[u8] = b"UTC_START 2023-05-01-12:00:00
+OBS_OFFSET 1024
+BYTES_PER_SECOND 512
+FREQ 1400
+BW -400
+NCHAN 4
+NPOL 1
+NBIT 8
+TSAMP 7812.5
+SOURCE J0437-4715
+RA 04:37:15.8961
+DEC -47:15:09.1100
+TELESCOPE Parkes
+OBSERVER KS
+";
+
+    fn standard() -> StandardHeader {
+        StandardHeader::try_from(bytes_to_header(DADA).unwrap()).unwrap()
+    }
+
+    /// Split a header into its cards, up to `END`, as `key -> value`
+    fn cards(bytes: &[u8]) -> (HashMap<String, String>, usize) {
+        let mut cards = HashMap::new();
+        for (i, card) in bytes.chunks(CARD).enumerate() {
+            let card = std::str::from_utf8(card).unwrap();
+            if card.trim_end() == "END" {
+                return (cards, align((i + 1) * CARD, RECORD));
+            }
+            let (key, value) = card.split_once("= ").unwrap();
+            let value = value.split(" / ").next().unwrap().trim();
+            cards.insert(
+                key.trim().to_owned(),
+                value.trim_matches('\'').trim().to_owned(),
+            );
+        }
+        panic!("No END card");
+    }
+
+    fn f64s(bytes: &[u8]) -> Vec<f64> {
+        bytes
+            .chunks(8)
+            .map(|c| f64::from_be_bytes(c.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_write_psrfits() {
+        let mut writer = PsrfitsWriter::new(Cursor::new(vec![]), &standard(), 2).unwrap();
+        writer.write_data(&(0..12).collect::<Vec<_>>()).unwrap();
+        assert_eq!(writer.rows(), 1);
+        let bytes = writer.finish().unwrap().into_inner();
+        assert_eq!(bytes.len() % RECORD, 0);
+
+        let (primary, primary_len) = cards(&bytes);
+        assert_eq!(primary["SIMPLE"], "T");
+        assert_eq!(primary["OBS_MODE"], "SEARCH");
+        assert_eq!(primary["TELESCOP"], "Parkes");
+        assert_eq!(primary["OBSERVER"], "KS");
+        assert_eq!(primary["SRC_NAME"], "J0437-4715");
+        assert_eq!(primary["RA"], "04:37:15.8961");
+        assert_eq!(primary["OBSNCHAN"], "4");
+        // Two seconds in, from OBS_OFFSET
+        assert_eq!(primary["DATE-OBS"], "2023-05-01T12:00:02");
+        assert_eq!(primary["STT_IMJD"], "60065");
+        assert_eq!(primary["STT_SMJD"], "43202");
+        assert_eq!(primary["STT_OFFS"], "0.0");
+
+        let (subint, subint_len) = cards(&bytes[primary_len..]);
+        assert_eq!(subint["XTENSION"], "BINTABLE");
+        assert_eq!(subint["EXTNAME"], "SUBINT");
+        assert_eq!(subint["TTYPE7"], "DATA");
+        assert_eq!(subint["TFORM7"], "8B");
+        assert_eq!(subint["TDIM7"], "(1,4,1,2)");
+        assert_eq!(subint["NSBLK"], "2");
+        assert_eq!(subint["TBIN"], "0.0078125");
+        // The last four bytes make a second, padded row
+        assert_eq!(subint["NAXIS2"], "2");
+        let row_len: usize = subint["NAXIS1"].parse().unwrap();
+        assert_eq!(row_len, 8 + 8 + 4 * 8 + 4 * 4 * 3 + 8);
+
+        let table = &bytes[primary_len + subint_len..];
+        let rows: Vec<_> = table.chunks(row_len).take(2).collect();
+        assert_eq!(f64s(&rows[0][..16]), [0.015625, 0.0078125]);
+        assert_eq!(f64s(&rows[1][..16]), [0.015625, 0.0234375]);
+        assert_eq!(f64s(&rows[0][16..48]), [1550.0, 1450.0, 1350.0, 1250.0]);
+        assert_eq!(&rows[0][48..52], &1f32.to_be_bytes());
+        assert_eq!(&rows[0][row_len - 8..], &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(&rows[1][row_len - 8..], &[8, 9, 10, 11, 0, 0, 0, 0]);
+        assert!(table[2 * row_len..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_bad_headers() {
+        let mut header = standard();
+        header.nbit = Some(16);
+        assert_eq!(
+            PsrfitsWriter::new(Cursor::new(vec![]), &header, 2).err(),
+            Some(value_error("NBIT", 16))
+        );
+        let mut header = standard();
+        header.nbit = Some(1);
+        // One sample of four channels is half a byte
+        assert_eq!(
+            PsrfitsWriter::new(Cursor::new(vec![]), &header, 1).err(),
+            Some(value_error("NSBLK", 1))
+        );
+        header.nchan = None;
+        assert_eq!(
+            PsrfitsWriter::new(Cursor::new(vec![]), &header, 2).err(),
+            Some(PsrdadaError::HeaderMissingKey("NCHAN".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_from_ring() {
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_bufs(4)
+            .buf_size(8)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+        {
+            let mut writer = dc.writer().unwrap();
+            for i in 0..3u8 {
+                let mut block = writer.next().unwrap();
+                block.write_all(&[i; 8]).unwrap();
+                if i == 2 {
+                    block.mark_eod();
+                }
+            }
+        }
+        let mut writer = PsrfitsWriter::new(Cursor::new(vec![]), &standard(), 4).unwrap();
+        assert_eq!(writer.write_blocks(&mut dc.reader().unwrap()).unwrap(), 24);
+        assert_eq!(writer.rows(), 1);
+        let bytes = writer.finish().unwrap().into_inner();
+        let (_, primary_len) = cards(&bytes);
+        let (subint, _) = cards(&bytes[primary_len..]);
+        assert_eq!(subint["NAXIS2"], "2");
+    }
+}
@@ -0,0 +1,928 @@
+//! A primary write client (PWC), controlled over TCP like `dada_pwc`.
+//!
+//! A [`Pwc`] takes line-based commands, from `dada_pwc_command` or anything else that speaks the protocol: a connection is
+//! greeted with a line of welcome, then every command gets a `> ` prompt, and is answered with `ok` or `fail` (after the
+//! reason, or the answer to `state`). The commands move it through the states of [`PwcState`]:
+//!
+//! - `header <header>` sets the header of the next observation, with its newlines replaced by `\`, going from idle to prepared
+//! - `clock [utc]` starts taking data, at the given time or straight away, without recording it
+//! - `start [utc]` starts taking data and recording all of it
+//! - `rec_start <utc>` starts recording the data from the given time, while clocking
+//! - `rec_stop <utc>` stops recording at the given time, going back to clocking
+//!
+//! `rec_start` and `rec_stop` can be sent ahead of time for as many stretches of the observation as needed, as long as each
+//! one starts after the last one stopped.
+//!
+//! - `stop [utc]` stops taking data, at the given time or straight away, going back to idle
+//! - `set_utc_start <utc>` sets the `UTC_START` of an observation that was started without a time, if the source didn't know it
+//! - `state` answers with the current state
+//!
+//! Times are `YYYY-MM-DD-hh:mm:ss`, and are turned into byte offsets with the `BYTES_PER_SECOND` (and `RESOLUTION`, if there is
+//! one) of the header, counting from `UTC_START`. The data itself comes from a [`DataSource`], read by [`Pwc::observe`] in
+//! whatever thread owns the ring. Every stretch of recorded data is a transfer in the ring, with the header it was prepared
+//! with plus `UTC_START` and the `OBS_OFFSET` of its first byte. Since readers need the header before the data, a recording
+//! that starts before `UTC_START` is known doesn't read any more from the source until `set_utc_start` arrives (or `stop`), so
+//! the source has to hold on to its data in the meantime.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    client::HduClient,
+    errors::{PsrdadaError, PsrdadaResult},
+    headers::{DadaHeader, UtcTime},
+    io::DadaClient,
+};
+
+/// The first line sent on every connection
+const WELCOME: &str = "dada_pwc command";
+const PROMPT: &str = "> ";
+/// How often the server checks whether it should shut down while nothing is happening
+const POLL: Duration = Duration::from_millis(100);
+
+/// Where a [`Pwc`] is in an observation
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum PwcState {
+    /// Waiting for a header
+    #[default]
+    Idle,
+    /// Has a header, waiting to start
+    Prepared,
+    /// Taking data without recording it
+    Clocking,
+    /// Taking data into the ring
+    Recording,
+}
+
+impl fmt::Display for PwcState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Idle => "idle",
+            Self::Prepared => "prepared",
+            Self::Clocking => "clocking",
+            Self::Recording => "recording",
+        })
+    }
+}
+
+/// A command to a [`Pwc`], see the [module docs](self)
+#[derive(Debug, PartialEq, Clone)]
+pub enum Command {
+    Header(DadaHeader),
+    Clock(Option<UtcTime>),
+    Start(Option<UtcTime>),
+    RecStart(UtcTime),
+    RecStop(UtcTime),
+    Stop(Option<UtcTime>),
+    SetUtcStart(UtcTime),
+    State,
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Header(_) => "header",
+            Self::Clock(_) => "clock",
+            Self::Start(_) => "start",
+            Self::RecStart(_) => "rec_start",
+            Self::RecStop(_) => "rec_stop",
+            Self::Stop(_) => "stop",
+            Self::SetUtcStart(_) => "set_utc_start",
+            Self::State => "state",
+        }
+    }
+}
+
+fn command_error(message: impl Into<String>) -> PsrdadaError {
+    PsrdadaError::PwcCommandError(message.into())
+}
+
+/// Parses a command line, as it's sent by `dada_pwc_command`
+impl FromStr for Command {
+    type Err = PsrdadaError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let (name, args) = match line.split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (line, ""),
+        };
+        let optional_time = || match args {
+            "" => Ok(None),
+            time => time.parse().map(Some),
+        };
+        let time = || match args {
+            "" => Err(command_error(format!("{name} needs a time"))),
+            time => time.parse(),
+        };
+        Ok(match name {
+            "header" if args.is_empty() => return Err(command_error("header needs a header")),
+            "header" => Self::Header(DadaHeader::parse(args.replace('\\', "\n").as_bytes())?),
+            "clock" => Self::Clock(optional_time()?),
+            "start" => Self::Start(optional_time()?),
+            "rec_start" => Self::RecStart(time()?),
+            "rec_stop" => Self::RecStop(time()?),
+            "stop" => Self::Stop(optional_time()?),
+            "set_utc_start" => Self::SetUtcStart(time()?),
+            "state" => Self::State,
+            _ => return Err(command_error(format!("unknown command {name}"))),
+        })
+    }
+}
+
+/// Where a source of data for a [`Pwc`] comes from, like the hardware-specific half of a `dada_pwc` program
+pub trait DataSource {
+    /// Start taking data for an observation with this header, at `at` if it's given or as soon as possible otherwise.
+    ///
+    /// Returns the time of the first byte, if the source knows it. A time that was asked for is used as `UTC_START` either way.
+    fn start(&mut self, header: &DadaHeader, at: Option<UtcTime>)
+        -> PsrdadaResult<Option<UtcTime>>;
+
+    /// Read the next data into `buf`, returning how many bytes were read, or 0 once the source has run dry
+    fn read(&mut self, buf: &mut [u8]) -> PsrdadaResult<usize>;
+
+    /// Stop taking data
+    fn stop(&mut self) -> PsrdadaResult<()>;
+}
+
+/// What happened in an observation run by [`Pwc::observe`]
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Observation {
+    /// When the first byte was taken, if it's known
+    pub utc_start: Option<UtcTime>,
+    /// Number of bytes taken from the source
+    pub bytes: u64,
+    /// Number of bytes in each transfer written to the ring
+    pub transfers: Vec<u64>,
+}
+
+#[derive(Default)]
+struct Control {
+    state: PwcState,
+    header: DadaHeader,
+    /// Set by `clock` and `start` until an observation picks it up
+    starting: bool,
+    start_at: Option<UtcTime>,
+    /// Whether an observation is taking data
+    running: bool,
+    utc_start: Option<UtcTime>,
+    /// The stretches to record, as the byte offsets they start and (once `rec_stop` is sent) end at, in order
+    windows: VecDeque<(u64, Option<u64>)>,
+    stop_at: Option<u64>,
+}
+
+impl Control {
+    /// The byte offset of a time in the observation
+    fn byte_at(&self, time: UtcTime) -> PsrdadaResult<u64> {
+        let utc_start = self
+            .utc_start
+            .ok_or_else(|| command_error("UTC_START isn't known yet"))?;
+        let number = |key| match self.header.get(key) {
+            Some(v) => v
+                .parse::<u64>()
+                .ok()
+                .filter(|n| *n > 0)
+                .map(Some)
+                .ok_or_else(|| command_error(format!("{key} should be a positive integer"))),
+            None => Ok(None),
+        };
+        let bytes_per_second = number("BYTES_PER_SECOND")?
+            .ok_or_else(|| command_error("the header needs BYTES_PER_SECOND to use times"))?;
+        let resolution = number("RESOLUTION")?.unwrap_or(1);
+        if time <= utc_start {
+            return Ok(0);
+        }
+        let nanos = (time.unix_seconds() - utc_start.unix_seconds()) as i128 * 1_000_000_000
+            + time.subsec_nanos() as i128
+            - utc_start.subsec_nanos() as i128;
+        let bytes = (nanos as u128 * bytes_per_second as u128 / 1_000_000_000) as u64;
+        Ok(bytes / resolution * resolution)
+    }
+
+    fn begin(&mut self, at: Option<UtcTime>) -> PsrdadaResult<()> {
+        if self.running {
+            return Err(command_error("the last observation is still stopping"));
+        }
+        self.starting = true;
+        self.start_at = at;
+        self.utc_start = at;
+        self.windows.clear();
+        self.stop_at = None;
+        Ok(())
+    }
+
+    /// Apply a command, returning what it answers with, if anything
+    fn apply(&mut self, command: Command) -> PsrdadaResult<Option<String>> {
+        use PwcState::*;
+        match (command, self.state) {
+            (Command::State, state) => return Ok(Some(state.to_string())),
+            (Command::Header(header), Idle | Prepared) => {
+                self.header = header;
+                self.state = Prepared;
+            }
+            (Command::Clock(at), Prepared) => {
+                self.begin(at)?;
+                self.state = Clocking;
+            }
+            (Command::Start(at), Prepared) => {
+                self.begin(at)?;
+                self.windows.push_back((0, None));
+                self.state = Recording;
+            }
+            (Command::RecStart(time), Clocking) => {
+                let from = self.byte_at(time)?;
+                if matches!(self.windows.back(), Some((_, Some(until))) if from < *until) {
+                    return Err(command_error("rec_start can't be before the last rec_stop"));
+                }
+                self.windows.push_back((from, None));
+                self.state = Recording;
+            }
+            (Command::RecStop(time), Recording) => {
+                let until = self.byte_at(time)?;
+                match self.windows.back_mut() {
+                    Some((from, end)) if until > *from => *end = Some(until),
+                    _ => return Err(command_error("rec_stop has to be after rec_start")),
+                }
+                self.state = Clocking;
+            }
+            (Command::Stop(at), Clocking | Recording) => {
+                self.stop_at = Some(match at {
+                    Some(time) => self.byte_at(time)?,
+                    None => 0,
+                });
+                self.state = Idle;
+            }
+            (Command::SetUtcStart(_), Clocking | Recording) if self.utc_start.is_some() => {
+                return Err(command_error("UTC_START is already set"));
+            }
+            (Command::SetUtcStart(time), Clocking | Recording) => self.utc_start = Some(time),
+            (command, state) => {
+                return Err(command_error(format!(
+                    "can't {} while {state}",
+                    command.name()
+                )))
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    control: Mutex<Control>,
+    changed: Condvar,
+    quit: AtomicBool,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Control> {
+        // Nothing is left half-changed by a panic while holding the lock, so carry on regardless
+        self.control.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn command(&self, line: &str) -> PsrdadaResult<Option<String>> {
+        let command = line.parse()?;
+        let answer = self.lock().apply(command);
+        self.changed.notify_all();
+        answer
+    }
+}
+
+/// Answer the commands on one connection until it's closed
+fn serve(shared: &Shared, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(POLL))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut out = stream;
+    writeln!(out, "{WELCOME}")?;
+    let mut line = vec![];
+    loop {
+        write!(out, "{PROMPT}")?;
+        out.flush()?;
+        line.clear();
+        // Wait for a whole line, keeping what's arrived of it over timeouts
+        loop {
+            match reader.read_until(b'\n', &mut line) {
+                Ok(_) => break,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    if shared.quit.load(Ordering::Relaxed) {
+                        return Ok(());
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let command = String::from_utf8_lossy(&line);
+        match command.trim() {
+            "" if line.is_empty() => return Ok(()),
+            "" => continue,
+            "quit" | "exit" => return Ok(()),
+            command => match shared.command(command) {
+                Ok(answer) => {
+                    if let Some(answer) = answer {
+                        writeln!(out, "{answer}")?;
+                    }
+                    writeln!(out, "ok")?;
+                }
+                Err(e) => {
+                    warn!(command, ?e, "Command failed");
+                    match e {
+                        PsrdadaError::PwcCommandError(message) => writeln!(out, "{message}")?,
+                        e => writeln!(out, "{e:?}")?,
+                    }
+                    writeln!(out, "fail")?;
+                }
+            },
+        }
+    }
+}
+
+/// Accept connections until it's time to quit, answering each on its own thread
+fn listen(shared: Arc<Shared>, listener: TcpListener) {
+    let mut connections = vec![];
+    while !shared.quit.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                info!(%peer, "Control connection");
+                let shared = shared.clone();
+                connections.push(thread::spawn(move || {
+                    let result = stream
+                        .set_nonblocking(false)
+                        .and_then(|_| serve(&shared, stream));
+                    if let Err(e) = result {
+                        warn!(%peer, %e, "Control connection failed");
+                    }
+                }));
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL),
+            Err(e) => warn!(%e, "Couldn't accept a control connection"),
+        }
+    }
+    for connection in connections {
+        let _ = connection.join();
+    }
+}
+
+/// The data of one transfer, as a stream of bytes that ends with the recording, or at the first error (which is kept for
+/// later)
+struct Recording<'a, S> {
+    shared: &'a Shared,
+    source: &'a mut S,
+    chunk: &'a mut [u8],
+    pos: usize,
+    len: usize,
+    offset: &'a mut u64,
+    done: bool,
+    dry: bool,
+    error: Option<PsrdadaError>,
+}
+
+impl<S: DataSource> Recording<'_, S> {
+    fn read_chunk(&mut self) -> PsrdadaResult<()> {
+        let end = {
+            let control = self.shared.lock();
+            let until = control.windows.front().and_then(|(_, until)| *until);
+            match (until, control.stop_at) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        };
+        let room = match end {
+            Some(end) if end <= *self.offset => 0,
+            Some(end) => (end - *self.offset).min(self.chunk.len() as u64) as usize,
+            None => self.chunk.len(),
+        };
+        if room == 0 {
+            self.done = true;
+        } else {
+            self.len = self.source.read(&mut self.chunk[..room])?;
+            *self.offset += self.len as u64;
+            self.dry = self.len == 0;
+            self.done = self.dry;
+        }
+        Ok(())
+    }
+}
+
+impl<S: DataSource> BufRead for Recording<'_, S> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.len && !self.done {
+            self.pos = 0;
+            self.len = 0;
+            if let Err(e) = self.read_chunk() {
+                self.error = Some(e);
+                self.len = 0;
+                self.done = true;
+            }
+        }
+        Ok(&self.chunk[self.pos..self.len])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+impl<S: DataSource> Read for Recording<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+/// A primary write client, see the [module docs](self)
+#[derive(Default)]
+pub struct Pwc {
+    shared: Arc<Shared>,
+    addr: Option<SocketAddr>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl Pwc {
+    /// A PWC that only takes commands through [`Pwc::command`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A PWC that takes commands on `addr`, as well as through [`Pwc::command`]
+    pub fn bind(addr: impl ToSocketAddrs) -> PsrdadaResult<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let shared = Arc::new(Shared::default());
+        let addr = listener.local_addr()?;
+        let server = {
+            let shared = shared.clone();
+            thread::spawn(move || listen(shared, listener))
+        };
+        Ok(Self {
+            shared,
+            addr: Some(addr),
+            server: Some(server),
+        })
+    }
+
+    /// The address commands are taken on, if there is one
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// The current state
+    pub fn state(&self) -> PwcState {
+        self.shared.lock().state
+    }
+
+    /// Run a command line, as if it came over TCP, returning what it answers with, if anything
+    pub fn command(&self, line: &str) -> PsrdadaResult<Option<String>> {
+        self.shared.command(line)
+    }
+
+    /// Run the next observation, blocking until it's started with `clock` or `start`, and returning once it's stopped or
+    /// the source runs dry.
+    ///
+    /// Data is read from the source a data block at a time, with reads cut short so that they end on the byte offsets of
+    /// the commands that are waiting to happen. Commands take effect between reads.
+    pub fn observe(
+        &self,
+        client: &mut HduClient,
+        source: &mut impl DataSource,
+    ) -> PsrdadaResult<Observation> {
+        let mut chunk = vec![0u8; client.data_buf_size()];
+        let (mut header_client, mut data_client) = client.split();
+        let (header, at) = {
+            let mut control = self.shared.lock();
+            while !control.starting {
+                control = self
+                    .shared
+                    .changed
+                    .wait(control)
+                    .unwrap_or_else(|e| e.into_inner());
+            }
+            control.starting = false;
+            control.running = true;
+            (control.header.clone(), control.start_at)
+        };
+        info!(?at, "Starting observation");
+
+        let mut offset = 0;
+        let mut transfers = vec![];
+        let result = (|| -> PsrdadaResult<()> {
+            let utc_start = source.start(&header, at)?;
+            {
+                let mut control = self.shared.lock();
+                if control.utc_start.is_none() {
+                    control.utc_start = utc_start;
+                }
+            }
+            loop {
+                let (from, stop_at) = {
+                    let mut control = self.shared.lock();
+                    while matches!(control.windows.front(), Some((_, Some(until))) if *until <= offset)
+                    {
+                        control.windows.pop_front();
+                    }
+                    (
+                        control.windows.front().map(|(from, _)| *from),
+                        control.stop_at,
+                    )
+                };
+                if stop_at.map_or(false, |stop| stop <= offset) {
+                    return Ok(());
+                }
+                if from.map_or(false, |from| from <= offset) {
+                    // Readers need the header before the data, so leave the data with the source until it can be written
+                    let utc_start = {
+                        let mut control = self.shared.lock();
+                        while control.utc_start.is_none() && control.stop_at.is_none() {
+                            control = self
+                                .shared
+                                .changed
+                                .wait(control)
+                                .unwrap_or_else(|e| e.into_inner());
+                        }
+                        control.utc_start
+                    };
+                    let Some(utc_start) = utc_start else {
+                        continue;
+                    };
+                    let mut header = header.clone();
+                    header.insert("UTC_START", utc_start.to_string());
+                    header.insert("OBS_OFFSET", offset.to_string());
+                    header_client.write_dada_header(&header)?;
+                    let mut recording = Recording {
+                        shared: &self.shared,
+                        source: &mut *source,
+                        chunk: &mut chunk,
+                        pos: 0,
+                        len: 0,
+                        offset: &mut offset,
+                        done: false,
+                        dry: false,
+                        error: None,
+                    };
+                    let bytes = data_client.writer()?.write_from(&mut recording, |_| ())?;
+                    info!(bytes, "Recorded transfer");
+                    transfers.push(bytes);
+                    if let Some(e) = recording.error {
+                        return Err(e);
+                    }
+                    if recording.dry {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                // Clock up to whatever happens next
+                let room = [from, stop_at]
+                    .iter()
+                    .flatten()
+                    .filter(|at| **at > offset)
+                    .map(|at| at - offset)
+                    .fold(chunk.len() as u64, u64::min) as usize;
+                let n = source.read(&mut chunk[..room])?;
+                if n == 0 {
+                    return Ok(());
+                }
+                offset += n as u64;
+            }
+        })();
+        let stopped = source.stop();
+
+        let mut control = self.shared.lock();
+        control.running = false;
+        control.state = PwcState::Idle;
+        control.windows.clear();
+        control.stop_at = None;
+        let utc_start = control.utc_start;
+        drop(control);
+        self.shared.changed.notify_all();
+        result?;
+        stopped?;
+        info!(bytes = offset, "Stopped observation");
+        Ok(Observation {
+            utc_start,
+            bytes: offset,
+            transfers,
+        })
+    }
+}
+
+impl Drop for Pwc {
+    fn drop(&mut self) {
+        self.shared.quit.store(true, Ordering::Relaxed);
+        if let Some(server) = self.server.take() {
+            let _ = server.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, iter::DadaIterator, tests::next_key};
+
+    /// Counts up from 0, for `len` bytes (or forever)
+    struct Counter {
+        utc_start: Option<UtcTime>,
+        len: Option<u64>,
+        offset: u64,
+        started: Option<Option<UtcTime>>,
+        stopped: bool,
+    }
+
+    impl Counter {
+        fn new(utc_start: Option<UtcTime>, len: Option<u64>) -> Self {
+            Self {
+                utc_start,
+                len,
+                offset: 0,
+                started: None,
+                stopped: false,
+            }
+        }
+    }
+
+    impl DataSource for Counter {
+        fn start(
+            &mut self,
+            _header: &DadaHeader,
+            at: Option<UtcTime>,
+        ) -> PsrdadaResult<Option<UtcTime>> {
+            self.started = Some(at);
+            Ok(self.utc_start)
+        }
+
+        fn read(&mut self, buf: &mut [u8]) -> PsrdadaResult<usize> {
+            let n = match self.len {
+                Some(len) => (len - self.offset).min(buf.len() as u64) as usize,
+                None => buf.len(),
+            };
+            for b in &mut buf[..n] {
+                *b = self.offset as u8;
+                self.offset += 1;
+            }
+            Ok(n)
+        }
+
+        fn stop(&mut self) -> PsrdadaResult<()> {
+            self.stopped = true;
+            Ok(())
+        }
+    }
+
+    const HEADER: &str = "header HDR_VERSION 1.0\\BYTES_PER_SECOND 1000\\SOURCE J0534+2200";
+
+    fn time(s: &str) -> UtcTime {
+        s.parse().unwrap()
+    }
+
+    fn read_transfer(client: &mut HduClient) -> (DadaHeader, Vec<u8>) {
+        let (mut hc, mut dc) = client.split();
+        let header = hc.read_dada_header().unwrap();
+        let mut data = vec![];
+        let mut reader = dc.reader().unwrap();
+        while let Some(mut block) = reader.next() {
+            data.extend_from_slice(block.block());
+        }
+        (header, data)
+    }
+
+    #[test]
+    fn test_parse_command() {
+        match "header HDR_VERSION 1.0\\BYTES_PER_SECOND 1000\\".parse::<Command>() {
+            Ok(Command::Header(header)) => {
+                assert_eq!(header.keys().collect::<Vec<_>>(), [
+                    "HDR_VERSION",
+                    "BYTES_PER_SECOND"
+                ]);
+                assert_eq!(header.get("BYTES_PER_SECOND"), Some("1000"));
+            }
+            command => panic!("{command:?}"),
+        }
+        assert_eq!("clock".parse::<Command>().unwrap(), Command::Clock(None));
+        assert_eq!(
+            "rec_start 2024-01-01-00:00:01\n"
+                .parse::<Command>()
+                .unwrap(),
+            Command::RecStart(time("2024-01-01-00:00:01"))
+        );
+        assert_eq!(
+            "rec_start".parse::<Command>(),
+            Err(PsrdadaError::PwcCommandError(
+                "rec_start needs a time".to_owned()
+            ))
+        );
+        assert_eq!(
+            "start tomorrow".parse::<Command>(),
+            Err(PsrdadaError::TimeParseError)
+        );
+        assert!(matches!(
+            "record".parse::<Command>(),
+            Err(PsrdadaError::PwcCommandError(_))
+        ));
+    }
+
+    #[test]
+    fn test_state_machine() {
+        let pwc = Pwc::new();
+        assert_eq!(pwc.command("state"), Ok(Some("idle".to_owned())));
+        assert!(pwc.command("clock").is_err());
+        pwc.command(HEADER).unwrap();
+        assert_eq!(pwc.state(), PwcState::Prepared);
+        assert!(pwc.command("rec_start 2024-01-01-00:00:01").is_err());
+        pwc.command("clock").unwrap();
+        assert_eq!(pwc.state(), PwcState::Clocking);
+        // Times need to know when the observation started
+        assert_eq!(
+            pwc.command("rec_start 2024-01-01-00:00:01"),
+            Err(PsrdadaError::PwcCommandError(
+                "UTC_START isn't known yet".to_owned()
+            ))
+        );
+        pwc.command("set_utc_start 2024-01-01-00:00:00").unwrap();
+        assert!(pwc.command("set_utc_start 2024-01-01-00:00:00").is_err());
+        assert!(pwc.command(HEADER).is_err());
+        pwc.command("rec_start 2024-01-01-00:00:01").unwrap();
+        assert_eq!(pwc.state(), PwcState::Recording);
+        // A stretch has to end after it starts
+        assert_eq!(
+            pwc.command("rec_stop 2024-01-01-00:00:00.5"),
+            Err(PsrdadaError::PwcCommandError(
+                "rec_stop has to be after rec_start".to_owned()
+            ))
+        );
+        pwc.command("rec_stop 2024-01-01-00:00:02").unwrap();
+        assert_eq!(pwc.state(), PwcState::Clocking);
+        // And the next one can't start before the last one ended
+        assert!(pwc.command("rec_start 2024-01-01-00:00:01.5").is_err());
+        assert_eq!(pwc.state(), PwcState::Clocking);
+        pwc.command("stop").unwrap();
+        assert_eq!(pwc.state(), PwcState::Idle);
+    }
+
+    #[test]
+    fn test_clock_and_record() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key)
+            .num_bufs(8)
+            .buf_size(256)
+            .build()
+            .unwrap();
+        let pwc = Pwc::new();
+        pwc.command(HEADER).unwrap();
+        pwc.command("clock 2024-01-01-00:00:00").unwrap();
+        pwc.command("rec_start 2024-01-01-00:00:01.5").unwrap();
+        pwc.command("rec_stop 2024-01-01-00:00:02.5").unwrap();
+        pwc.command("stop 2024-01-01-00:00:03").unwrap();
+
+        let mut source = Counter::new(None, None);
+        let observation = pwc.observe(&mut client, &mut source).unwrap();
+        assert_eq!(observation, Observation {
+            utc_start: Some(time("2024-01-01-00:00:00")),
+            bytes: 3000,
+            transfers: vec![1000],
+        });
+        assert_eq!(source.started, Some(Some(time("2024-01-01-00:00:00"))));
+        assert!(source.stopped);
+        assert_eq!(pwc.state(), PwcState::Idle);
+
+        let (header, data) = read_transfer(&mut client);
+        assert_eq!(header.get("UTC_START"), Some("2024-01-01-00:00:00"));
+        assert_eq!(header.get("OBS_OFFSET"), Some("1500"));
+        assert_eq!(header.get("SOURCE"), Some("J0534+2200"));
+        assert_eq!(data, (1500..2500).map(|i| i as u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_two_windows() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key)
+            .num_bufs(8)
+            .buf_size(256)
+            .build()
+            .unwrap();
+        let pwc = Pwc::new();
+        pwc.command(HEADER).unwrap();
+        pwc.command("clock 2024-01-01-00:00:00").unwrap();
+        pwc.command("rec_start 2024-01-01-00:00:00.5").unwrap();
+        pwc.command("rec_stop 2024-01-01-00:00:01").unwrap();
+        pwc.command("rec_start 2024-01-01-00:00:01.5").unwrap();
+        pwc.command("rec_stop 2024-01-01-00:00:02.5").unwrap();
+        pwc.command("stop 2024-01-01-00:00:03").unwrap();
+
+        let mut source = Counter::new(None, None);
+        let observation = pwc.observe(&mut client, &mut source).unwrap();
+        assert_eq!(observation.transfers, vec![500, 1000]);
+        let (header, data) = read_transfer(&mut client);
+        assert_eq!(header.get("OBS_OFFSET"), Some("500"));
+        assert_eq!(data, (500..1000).map(|i| i as u8).collect::<Vec<_>>());
+        let (header, data) = read_transfer(&mut client);
+        assert_eq!(header.get("OBS_OFFSET"), Some("1500"));
+        assert_eq!(data, (1500..2500).map(|i| i as u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_start_until_dry() {
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key)
+            .num_bufs(8)
+            .buf_size(256)
+            .build()
+            .unwrap();
+        let pwc = Pwc::new();
+        pwc.command(HEADER).unwrap();
+        pwc.command("start").unwrap();
+        pwc.command("set_utc_start 2024-01-01-00:00:00").unwrap();
+
+        // The source doesn't know when it started, so it's set by hand
+        let mut source = Counter::new(None, Some(500));
+        let observation = pwc.observe(&mut client, &mut source).unwrap();
+        assert_eq!(observation.transfers, vec![500]);
+        assert_eq!(pwc.state(), PwcState::Idle);
+        let (header, data) = read_transfer(&mut client);
+        assert_eq!(header.get("UTC_START"), Some("2024-01-01-00:00:00"));
+        assert_eq!(header.get("OBS_OFFSET"), Some("0"));
+        assert_eq!(data.len(), 500);
+    }
+
+    #[test]
+    fn test_utc_start_later() {
+        // Less room in the ring than the source has data
+        let key = next_key();
+        let mut client = DadaClientBuilder::new(key)
+            .num_bufs(2)
+            .buf_size(256)
+            .build()
+            .unwrap();
+        let pwc = Arc::new(Pwc::new());
+        pwc.command(HEADER).unwrap();
+        pwc.command("start").unwrap();
+        let observer = {
+            let pwc = pwc.clone();
+            thread::spawn(move || {
+                let mut client = HduClient::connect(key).unwrap();
+                let mut source = Counter::new(None, Some(2000));
+                pwc.observe(&mut client, &mut source).unwrap()
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(pwc.state(), PwcState::Recording);
+        pwc.command("set_utc_start 2024-01-01-00:00:00").unwrap();
+
+        let (header, data) = read_transfer(&mut client);
+        assert_eq!(header.get("UTC_START"), Some("2024-01-01-00:00:00"));
+        assert_eq!(data, (0..2000).map(|i| i as u8).collect::<Vec<_>>());
+        assert_eq!(observer.join().unwrap().transfers, vec![2000]);
+    }
+
+    /// Read everything up to and including the next prompt
+    fn until_prompt(reader: &mut impl Read) -> String {
+        let mut answer = vec![];
+        while !answer.ends_with(PROMPT.as_bytes()) {
+            let mut byte = [0u8];
+            reader.read_exact(&mut byte).unwrap();
+            answer.push(byte[0]);
+        }
+        String::from_utf8(answer).unwrap()
+    }
+
+    #[test]
+    fn test_server() {
+        let pwc = Pwc::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(pwc.local_addr().unwrap()).unwrap();
+        assert_eq!(until_prompt(&mut stream), "dada_pwc command\n> ");
+        let mut send = |command: &str| {
+            writeln!(stream, "{command}").unwrap();
+            until_prompt(&mut stream)
+        };
+        assert_eq!(send("state"), "idle\nok\n> ");
+        assert_eq!(send(HEADER), "ok\n> ");
+        assert_eq!(send(""), "> ");
+        assert_eq!(send("clock"), "ok\n> ");
+        assert_eq!(send("start"), "can't start while clocking\nfail\n> ");
+        assert_eq!(send("bogus"), "unknown command bogus\nfail\n> ");
+        assert_eq!(pwc.state(), PwcState::Clocking);
+    }
+}
@@ -0,0 +1,494 @@
+//! Recording rings to disk, like `dada_dbdisk`.
+//!
+//! A [`DiskRecorder`] reads a header with [`HeaderClient::read_dada_header`], then the data of that transfer with the data
+//! [`Reader`](crate::io::Reader) until the end of data, writing it to `.dada` files (see [`crate::file`]).
+//! Every transfer gets a directory of its own, named after its `UTC_START`, and its data is split over files of at most `FILE_SIZE`
+//! bytes (not counting the header). Each file gets a copy of the header with `FILE_NUMBER` and `OBS_OFFSET` updated, and is named
+//! the same way as `dada_dbdisk` names them, `<UTC_START>_<OBS_OFFSET>.<FILE_NUMBER>.dada`.
+//!
+//! Files can be spread over several disks with a [`DiskSet`], each disk getting its own copy of the transfer's directory. Every
+//! file goes to whichever disk the set picks at the time it's opened, so a transfer moves on to another disk when one fills up.
+
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, info, warn};
+
+use crate::{
+    client::{DataClient, HduClient, HeaderClient},
+    disks::DiskSet,
+    errors::{PsrdadaError, PsrdadaResult},
+    file::{get_u64, DadaFileWriter, DEFAULT_HDR_SIZE},
+    headers::DadaHeader,
+    io::DadaClient,
+    iter::DadaIterator,
+};
+
+/// What a [`DiskRecorder`] wrote for one transfer
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RecordedTransfer {
+    /// The directories the files were written to, one per disk used, in the order they were first used
+    pub directories: Vec<PathBuf>,
+    /// Every file written, in order
+    pub files: Vec<PathBuf>,
+    /// The number of data bytes written, not counting headers
+    pub bytes: u64,
+    /// The number of data bytes that were read but not written, as every disk was full
+    pub dropped: u64,
+}
+
+/// Records transfers from a ring into `.dada` files
+#[derive(Debug, Clone)]
+pub struct DiskRecorder {
+    disks: DiskSet,
+    file_size: Option<u64>,
+    write: fn(&Path, &mut DadaFileWriter<File>, &[u8]) -> io::Result<usize>,
+}
+
+/// Write to a file on `disk`, as normal
+fn write_file(_disk: &Path, writer: &mut DadaFileWriter<File>, data: &[u8]) -> io::Result<usize> {
+    writer.write(data)
+}
+
+/// The file currently being written
+struct OpenFile {
+    disk: PathBuf,
+    path: PathBuf,
+    writer: DadaFileWriter<File>,
+}
+
+/// Where a transfer is being recorded to
+struct Recording {
+    name: String,
+    /// The space to check for before opening a file
+    needed: u64,
+    directories: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+    /// Disks that failed a write during this transfer
+    failed: Vec<PathBuf>,
+}
+
+impl DiskRecorder {
+    /// Create a recorder that puts the observation directories in `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self::with_disks(DiskSet::new([root.into()]))
+    }
+
+    /// Create a recorder that spreads its files over a set of disks, each getting its own copy of the observation directories
+    pub fn with_disks(disks: DiskSet) -> Self {
+        Self {
+            disks,
+            file_size: None,
+            write: write_file,
+        }
+    }
+
+    /// Roll over to a new file after this many data bytes, instead of using `FILE_SIZE` from the header
+    pub fn file_size(mut self, bytes: u64) -> Self {
+        self.file_size = Some(bytes);
+        self
+    }
+
+    /// Use something other than a plain write to write to the files, to make disks fail
+    #[cfg(test)]
+    pub(crate) fn write_with(
+        mut self,
+        write: fn(&Path, &mut DadaFileWriter<File>, &[u8]) -> io::Result<usize>,
+    ) -> Self {
+        self.write = write;
+        self
+    }
+
+    /// Pick a name for a transfer's directories, adding a suffix if the name is already taken on any disk
+    fn observation_name(&self, header: &DadaHeader) -> String {
+        let base = header.get("UTC_START").unwrap_or("unknown");
+        let mut name = base.to_owned();
+        let mut suffix = 0;
+        while self
+            .disks
+            .disks()
+            .iter()
+            .any(|disk| disk.join(&name).exists())
+        {
+            suffix += 1;
+            name = format!("{base}.{suffix}");
+        }
+        name
+    }
+
+    /// Open a file on a disk with room for it, or return `None` if every disk is full.
+    ///
+    /// Only I/O errors count against a disk, anything wrong with the header is returned as is.
+    fn open(
+        &self,
+        recording: &mut Recording,
+        header: DadaHeader,
+    ) -> PsrdadaResult<Option<OpenFile>> {
+        let file_name = format!(
+            "{}_{:016}.{:06}.dada",
+            header.get("UTC_START").unwrap_or("unknown"),
+            get_u64(&header, "OBS_OFFSET")?.unwrap_or_default(),
+            get_u64(&header, "FILE_NUMBER")?.unwrap_or_default(),
+        );
+        while let Some(disk) = self.disks.select(recording.needed, &recording.failed) {
+            let dir = disk.join(&recording.name);
+            let path = dir.join(&file_name);
+            debug!(?path, "Opening new file");
+            let opened = fs::create_dir_all(&dir)
+                .and_then(|_| File::create(&path))
+                .map_err(Into::into)
+                .and_then(|file| DadaFileWriter::new(file, header.clone()));
+            match opened {
+                Ok(writer) => {
+                    if !recording.directories.contains(&dir) {
+                        recording.directories.push(dir);
+                    }
+                    return Ok(Some(OpenFile { disk, path, writer }));
+                }
+                Err(e @ PsrdadaError::IoError(_)) => {
+                    warn!(?disk, ?e, "Couldn't open a file, trying another disk");
+                    let _ = fs::remove_file(&path);
+                    recording.failed.push(disk);
+                }
+                // Anything else is wrong with the header, and would be just as wrong on another disk
+                Err(e) => {
+                    let _ = fs::remove_file(&path);
+                    return Err(e);
+                }
+            }
+        }
+        warn!("Every disk is full");
+        Ok(None)
+    }
+
+    /// Close a file, opening the next one of the transfer
+    fn roll_over(
+        &self,
+        recording: &mut Recording,
+        file: OpenFile,
+    ) -> PsrdadaResult<Option<OpenFile>> {
+        let header = file.writer.next_header();
+        file.writer.finish()?;
+        recording.files.push(file.path);
+        self.open(recording, header)
+    }
+
+    /// Give up on the disk of a file that couldn't be written to, carrying on from the same point on another disk
+    fn fail_over(
+        &self,
+        recording: &mut Recording,
+        file: OpenFile,
+    ) -> PsrdadaResult<Option<OpenFile>> {
+        recording.failed.push(file.disk.clone());
+        if file.writer.bytes_written() > 0 {
+            return self.roll_over(recording, file);
+        }
+        // Nothing made it into this file, so try it again somewhere else
+        let header = file.writer.header().clone();
+        drop(file.writer);
+        let _ = fs::remove_file(&file.path);
+        self.open(recording, header)
+    }
+
+    /// Record the next transfer, blocking until its header arrives and returning once the end of data is reached.
+    ///
+    /// Every file is put on a disk with room for all of it. If a write fails anyway (the disk filled up, or went away), the
+    /// rest of the data goes to a new file on another disk, so nothing is lost. Once every disk is full, the files are closed
+    /// and the rest of the transfer is read and counted in [`RecordedTransfer::dropped`], leaving the ring ready for the next
+    /// transfer.
+    pub fn record_transfer(
+        &self,
+        header_client: &mut HeaderClient,
+        data_client: &mut DataClient,
+    ) -> PsrdadaResult<RecordedTransfer> {
+        let mut header = header_client.read_dada_header()?;
+        for key in ["FILE_NUMBER", "OBS_OFFSET"] {
+            if !header.contains_key(key) {
+                header.insert(key, "0");
+            }
+        }
+        let file_size = match self.file_size {
+            Some(file_size) => Some(file_size),
+            None => get_u64(&header, "FILE_SIZE")?,
+        }
+        .filter(|size| *size > 0);
+        let hdr_size = get_u64(&header, "HDR_SIZE")?.unwrap_or(DEFAULT_HDR_SIZE as u64);
+
+        let mut recording = Recording {
+            name: self.observation_name(&header),
+            needed: hdr_size + file_size.unwrap_or_default(),
+            directories: vec![],
+            files: vec![],
+            failed: vec![],
+        };
+        info!(name = recording.name, "Recording new transfer");
+        let mut file = self.open(&mut recording, header)?;
+        let mut bytes = 0;
+        let mut dropped = 0;
+
+        let mut reader = data_client.reader()?;
+        while let Some(mut block) = reader.next() {
+            let mut data = block.block();
+            while !data.is_empty() {
+                let Some(current) = file.as_mut() else {
+                    dropped += data.len() as u64;
+                    break;
+                };
+                let room = match file_size {
+                    Some(file_size) if current.writer.bytes_written() >= file_size => {
+                        // Safety of the unwrap: we just matched on it
+                        file = self.roll_over(&mut recording, file.take().unwrap())?;
+                        continue;
+                    }
+                    Some(file_size) => file_size - current.writer.bytes_written(),
+                    None => data.len() as u64,
+                };
+                let n = data.len().min(room as usize);
+                match (self.write)(&current.disk, &mut current.writer, &data[..n]) {
+                    Ok(n) if n > 0 => {
+                        bytes += n as u64;
+                        data = &data[n..];
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                    result => {
+                        warn!(disk = ?current.disk, ?result, "Write failed, failing over to another disk");
+                        file = self.fail_over(&mut recording, file.take().unwrap())?;
+                    }
+                }
+            }
+        }
+        if let Some(file) = file {
+            file.writer.finish()?;
+            recording.files.push(file.path);
+        }
+        Ok(RecordedTransfer {
+            directories: recording.directories,
+            files: recording.files,
+            bytes,
+            dropped,
+        })
+    }
+
+    /// Record the next transfer from a paired client
+    pub fn record(&self, client: &mut HduClient) -> PsrdadaResult<RecordedTransfer> {
+        let (mut header_client, mut data_client) = client.split();
+        self.record_transfer(&mut header_client, &mut data_client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap};
+
+    use test_log::test;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, file::DadaFileReader, tests::next_key};
+
+    fn write_transfer(client: &mut HduClient, utc_start: &str, blocks: u8) {
+        let (mut hc, mut dc) = client.split();
+        hc.write_header(&HashMap::from([
+            ("UTC_START".to_owned(), utc_start.to_owned()),
+            ("FILE_SIZE".to_owned(), "8".to_owned()),
+            ("SOURCE".to_owned(), "J0437-4715".to_owned()),
+        ]))
+        .unwrap();
+        let mut writer = dc.writer().unwrap();
+        for i in 0..blocks {
+            let mut block = writer.next().unwrap();
+            block.write_all(&[i; 4]).unwrap();
+            if i == blocks - 1 {
+                block.mark_eod();
+            }
+        }
+    }
+
+    #[test]
+    fn test_rollover() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_bufs(4)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let recorder = DiskRecorder::new(dir.path());
+
+        write_transfer(&mut client, "2023-05-01-12:34:56", 3);
+        let recorded = recorder.record(&mut client).unwrap();
+        assert_eq!(recorded.directories, [dir
+            .path()
+            .join("2023-05-01-12:34:56")]);
+        assert_eq!(recorded.bytes, 12);
+        let names: Vec<_> = recorded
+            .files
+            .iter()
+            .map(|f| f.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, [
+            "2023-05-01-12:34:56_0000000000000000.000000.dada",
+            "2023-05-01-12:34:56_0000000000000008.000001.dada"
+        ]);
+
+        let mut first = DadaFileReader::open(&recorded.files[0]).unwrap();
+        assert_eq!(first.header().get("FILE_NUMBER"), Some("0"));
+        assert_eq!(first.header().get("OBS_OFFSET"), Some("0"));
+        assert_eq!(first.next().unwrap().block(), &[0, 0, 0, 0, 1, 1, 1, 1]);
+        let mut second = DadaFileReader::open(&recorded.files[1]).unwrap();
+        assert_eq!(second.header().get("FILE_NUMBER"), Some("1"));
+        assert_eq!(second.header().get("OBS_OFFSET"), Some("8"));
+        assert_eq!(second.header().get("SOURCE"), Some("J0437-4715"));
+        assert_eq!(second.next().unwrap().block(), &[2, 2, 2, 2]);
+
+        // The next transfer goes in a new directory, even with the same start time
+        write_transfer(&mut client, "2023-05-01-12:34:56", 1);
+        let recorded = recorder.file_size(2).record(&mut client).unwrap();
+        assert_eq!(recorded.directories, [dir
+            .path()
+            .join("2023-05-01-12:34:56.1")]);
+        assert_eq!(recorded.files.len(), 2);
+    }
+
+    thread_local! {
+        /// The size of each fake disk
+        static QUOTAS: RefCell<HashMap<PathBuf, u64>> = RefCell::new(HashMap::new());
+    }
+
+    fn used_space(path: &Path) -> u64 {
+        fs::read_dir(path)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                match entry.metadata().unwrap() {
+                    meta if meta.is_dir() => used_space(&entry.path()),
+                    meta => meta.len(),
+                }
+            })
+            .sum()
+    }
+
+    /// Pretend each disk is only as big as its quota
+    fn fake_free(disk: &Path) -> io::Result<u64> {
+        let quota = QUOTAS.with(|quotas| quotas.borrow()[disk]);
+        Ok(quota.saturating_sub(used_space(disk)))
+    }
+
+    #[test]
+    fn test_failover() {
+        let root = tempfile::tempdir().unwrap();
+        let (a, b) = (root.path().join("a"), root.path().join("b"));
+        for (disk, quota) in [(&a, 9000), (&b, 5000)] {
+            fs::create_dir(disk).unwrap();
+            QUOTAS.with(|quotas| quotas.borrow_mut().insert(disk.clone(), quota));
+        }
+        let recorder = DiskRecorder::with_disks(DiskSet::new([&a, &b]).free_space_with(fake_free));
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_bufs(8)
+            .buf_size(4)
+            .build()
+            .unwrap();
+
+        // Each file takes 4104 bytes with its header, so there's room for two on a and one on b, handed out by free space
+        write_transfer(&mut client, "2023-05-01-12:34:56", 7);
+        let recorded = recorder.record(&mut client).unwrap();
+        let dir_a = a.join("2023-05-01-12:34:56");
+        let dir_b = b.join("2023-05-01-12:34:56");
+        assert_eq!(recorded.directories, [dir_a.clone(), dir_b.clone()]);
+        assert_eq!(recorded.files, [
+            dir_a.join("2023-05-01-12:34:56_0000000000000000.000000.dada"),
+            dir_b.join("2023-05-01-12:34:56_0000000000000008.000001.dada"),
+            dir_a.join("2023-05-01-12:34:56_0000000000000016.000002.dada"),
+        ]);
+        assert_eq!(recorded.bytes, 24);
+        assert_eq!(recorded.dropped, 4);
+        let mut last = DadaFileReader::open(&recorded.files[2]).unwrap();
+        assert_eq!(last.next().unwrap().block(), &[4, 4, 4, 4, 5, 5, 5, 5]);
+
+        // With every disk full, the next transfer is read but not written
+        write_transfer(&mut client, "2023-05-01-13:00:00", 2);
+        let recorded = recorder.record(&mut client).unwrap();
+        assert!(recorded.files.is_empty());
+        assert_eq!(recorded.dropped, 8);
+    }
+
+    /// Disk a gives out once a file on it has 4 bytes of data
+    fn failing_write(
+        disk: &Path,
+        writer: &mut DadaFileWriter<File>,
+        data: &[u8],
+    ) -> io::Result<usize> {
+        if disk.ends_with("a") && writer.bytes_written() >= 4 {
+            return Err(io::Error::new(ErrorKind::Other, "disk on fire"));
+        }
+        writer.write(data)
+    }
+
+    #[test]
+    fn test_failover_mid_file() {
+        let root = tempfile::tempdir().unwrap();
+        let (a, b) = (root.path().join("a"), root.path().join("b"));
+        for (disk, quota) in [(&a, 100_000), (&b, 50_000)] {
+            fs::create_dir(disk).unwrap();
+            QUOTAS.with(|quotas| quotas.borrow_mut().insert(disk.clone(), quota));
+        }
+        let recorder = DiskRecorder::with_disks(DiskSet::new([&a, &b]).free_space_with(fake_free))
+            .write_with(failing_write);
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_bufs(8)
+            .buf_size(4)
+            .build()
+            .unwrap();
+
+        // The first file is cut short by the failure, and the rest carries on from there on b
+        write_transfer(&mut client, "2023-05-01-12:34:56", 4);
+        let recorded = recorder.record(&mut client).unwrap();
+        let dir_a = a.join("2023-05-01-12:34:56");
+        let dir_b = b.join("2023-05-01-12:34:56");
+        assert_eq!(recorded.files, [
+            dir_a.join("2023-05-01-12:34:56_0000000000000000.000000.dada"),
+            dir_b.join("2023-05-01-12:34:56_0000000000000004.000001.dada"),
+            dir_b.join("2023-05-01-12:34:56_0000000000000012.000002.dada"),
+        ]);
+        assert_eq!(recorded.bytes, 16);
+        assert_eq!(recorded.dropped, 0);
+
+        let mut offset = 0;
+        let mut data = vec![];
+        for path in &recorded.files {
+            let mut file = DadaFileReader::open(path).unwrap();
+            assert_eq!(get_u64(file.header(), "OBS_OFFSET").unwrap(), Some(offset));
+            while let Some(mut block) = file.next() {
+                offset += block.block().len() as u64;
+                data.extend_from_slice(block.block());
+            }
+        }
+        assert_eq!(data, [[0; 4], [1; 4], [2; 4], [3; 4]].concat());
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_bufs(4)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        {
+            let (mut hc, _) = client.split();
+            hc.write_header(&HashMap::from([
+                ("UTC_START".to_owned(), "2023-05-01-12:34:56".to_owned()),
+                ("HDR_SIZE".to_owned(), "64".to_owned()),
+                ("SOURCE".to_owned(), "J0437-4715".to_owned()),
+            ]))
+            .unwrap();
+        }
+        // Once FILE_NUMBER and OBS_OFFSET are added, the header doesn't fit in HDR_SIZE, which is no fault of the disk
+        assert!(matches!(
+            DiskRecorder::new(dir.path()).record(&mut client),
+            Err(PsrdadaError::HdrSizeError { hdr_size: 64, .. })
+        ));
+        assert_eq!(used_space(dir.path()), 0);
+    }
+}
@@ -0,0 +1,360 @@
+//! Replaying `.dada` files into rings, the reverse of [`crate::recorder`].
+//!
+//! A [`Replayer`] writes the header of a file with [`HeaderClient::write_dada_header`], then its data with the data
+//! [`Writer`](crate::io::Writer), ending the transfer after the last byte. Files are played in the order they're given.
+//!
+//! With stitching turned on, consecutive files of the same observation (the same `UTC_START`, with each `OBS_OFFSET` picking up
+//! where the previous file's data left off) are played as a single transfer under the header of the first one, undoing the
+//! rollover of the recorder. Playback can be throttled to a multiple of `BYTES_PER_SECOND`, and the whole sequence can be looped.
+
+use std::{
+    fs,
+    io::{self, BufReader, Read},
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, warn};
+
+use crate::{
+    client::{DataClient, HduClient, HeaderClient},
+    errors::{PsrdadaError, PsrdadaResult},
+    file::{get_u64, DadaFileReader},
+    headers::DadaHeader,
+    io::DadaClient,
+};
+
+/// What a [`Replayer`] wrote
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct ReplayStats {
+    /// The number of transfers (headers) written
+    pub transfers: u64,
+    /// The number of data bytes written
+    pub bytes: u64,
+}
+
+/// Plays `.dada` files into a ring
+#[derive(Debug, Clone)]
+pub struct Replayer {
+    files: Vec<PathBuf>,
+    speed: Option<f64>,
+    loops: Option<usize>,
+    stitch: bool,
+}
+
+/// A file to play, with what we need to know to stitch it to its neighbours
+struct Segment {
+    path: PathBuf,
+    header: DadaHeader,
+    data_len: u64,
+}
+
+impl Segment {
+    fn load(path: &PathBuf) -> PsrdadaResult<Self> {
+        let reader = DadaFileReader::open(path)?;
+        let data_len = fs::metadata(path)?
+            .len()
+            .saturating_sub(reader.hdr_size() as u64);
+        Ok(Self {
+            path: path.clone(),
+            header: reader.header().clone(),
+            data_len,
+        })
+    }
+
+    /// Whether `next` carries on from the end of this file
+    fn continued_by(&self, next: &Segment) -> PsrdadaResult<bool> {
+        let same_obs = self.header.get("UTC_START").is_some()
+            && self.header.get("UTC_START") == next.header.get("UTC_START");
+        let offset = get_u64(&self.header, "OBS_OFFSET")?.unwrap_or_default();
+        let next_offset = get_u64(&next.header, "OBS_OFFSET")?.unwrap_or_default();
+        Ok(same_obs && next_offset == offset + self.data_len)
+    }
+}
+
+impl Replayer {
+    /// Create a replayer for these files, played in order
+    pub fn new<P: Into<PathBuf>>(files: impl IntoIterator<Item = P>) -> Self {
+        Self {
+            files: files.into_iter().map(Into::into).collect(),
+            speed: None,
+            loops: Some(1),
+            stitch: false,
+        }
+    }
+
+    /// Throttle playback to `multiple` times the `BYTES_PER_SECOND` of each header, instead of writing as fast as possible.
+    ///
+    /// Playing fails with [`PsrdadaError::HeaderValueError`] if that doesn't come to a finite, positive rate.
+    pub fn speed(mut self, multiple: f64) -> Self {
+        self.speed = Some(multiple);
+        self
+    }
+
+    /// Play the files this many times over
+    pub fn loops(mut self, n: usize) -> Self {
+        self.loops = Some(n);
+        self
+    }
+
+    /// Play the files over and over, until writing to the ring fails
+    pub fn loop_forever(mut self) -> Self {
+        self.loops = None;
+        self
+    }
+
+    /// Play consecutive files of an observation as a single transfer
+    pub fn stitch(mut self, stitch: bool) -> Self {
+        self.stitch = stitch;
+        self
+    }
+
+    /// Group the files into the transfers they'll be played as
+    fn transfers(&self) -> PsrdadaResult<Vec<Vec<Segment>>> {
+        let mut transfers: Vec<Vec<Segment>> = vec![];
+        for path in &self.files {
+            let segment = Segment::load(path)?;
+            match transfers.last_mut() {
+                Some(last) if self.stitch => {
+                    // Safety of the unwrap: we never push an empty transfer
+                    if last.last().unwrap().continued_by(&segment)? {
+                        last.push(segment);
+                    } else {
+                        debug!(
+                            ?path,
+                            "File doesn't continue the last one, starting a new transfer"
+                        );
+                        transfers.push(vec![segment]);
+                    }
+                }
+                _ => transfers.push(vec![segment]),
+            }
+        }
+        Ok(transfers)
+    }
+
+    /// Write one transfer, returning the number of data bytes written
+    fn play(
+        &self,
+        header_client: &mut HeaderClient,
+        data_client: &mut DataClient,
+        segments: &[Segment],
+    ) -> PsrdadaResult<u64> {
+        let header = &segments[0].header;
+        let rate = match self.speed {
+            Some(multiple) => {
+                let bytes_per_second = get_u64(header, "BYTES_PER_SECOND")?
+                    .ok_or_else(|| PsrdadaError::HeaderMissingKey("BYTES_PER_SECOND".to_owned()))?;
+                let rate = bytes_per_second as f64 * multiple;
+                if !(rate.is_finite() && rate > 0.0) {
+                    return Err(PsrdadaError::HeaderValueError {
+                        key: "BYTES_PER_SECOND".to_owned(),
+                        value: format!("{bytes_per_second} (at {multiple} times speed)"),
+                    });
+                }
+                Some(rate)
+            }
+            None => None,
+        };
+        header_client.write_dada_header(header)?;
+
+        let mut data: Box<dyn Read> = Box::new(io::empty());
+        for segment in segments {
+            data = Box::new(data.chain(DadaFileReader::open(&segment.path)?));
+        }
+        let mut data = BufReader::new(data);
+
+        let start = Instant::now();
+        data_client.writer()?.write_from(&mut data, |bytes| {
+            if let Some(rate) = rate {
+                let due = Duration::from_secs_f64(bytes as f64 / rate);
+                if let Some(wait) = due.checked_sub(start.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+        })
+    }
+
+    /// Play the files into the header and data rings
+    pub fn replay_to(
+        &self,
+        header_client: &mut HeaderClient,
+        data_client: &mut DataClient,
+    ) -> PsrdadaResult<ReplayStats> {
+        let transfers = self.transfers()?;
+        let mut stats = ReplayStats::default();
+        if transfers.is_empty() {
+            warn!("No files to replay");
+            return Ok(stats);
+        }
+        let mut played = 0;
+        while self.loops.map_or(true, |n| played < n) {
+            for segments in &transfers {
+                stats.bytes += self.play(header_client, data_client, segments)?;
+                stats.transfers += 1;
+            }
+            played += 1;
+        }
+        Ok(stats)
+    }
+
+    /// Play the files into a paired client
+    pub fn replay(&self, client: &mut HduClient) -> PsrdadaResult<ReplayStats> {
+        let (mut header_client, mut data_client) = client.split();
+        self.replay_to(&mut header_client, &mut data_client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::Path};
+
+    use test_log::test;
+
+    use super::*;
+    use crate::{
+        builder::DadaClientBuilder, file::DadaFileWriter, iter::DadaIterator, tests::next_key,
+    };
+
+    const HEADER: &[u8] =
+        b"UTC_START 2023-05-01-12:34:56\nOBS_OFFSET 0\nFILE_NUMBER 0\nBYTES_PER_SECOND 400\n";
+
+    /// Write an observation split into files of the given sizes, returning their paths
+    fn write_files(dir: &Path, sizes: &[usize]) -> Vec<PathBuf> {
+        write_files_with(dir, HEADER, sizes)
+    }
+
+    /// Write an observation starting with `header` split into files of the given sizes, returning their paths
+    fn write_files_with(dir: &Path, header: &[u8], sizes: &[usize]) -> Vec<PathBuf> {
+        let mut header = DadaHeader::parse(header).unwrap();
+        let mut paths = vec![];
+        let mut value = 0u8;
+        for size in sizes {
+            let path = dir.join(format!("{}.dada", paths.len()));
+            let mut writer = DadaFileWriter::create(&path, header.clone()).unwrap();
+            for _ in 0..*size {
+                writer.write_all(&[value]).unwrap();
+                value += 1;
+            }
+            header = writer.next_header();
+            writer.finish().unwrap();
+            paths.push(path);
+        }
+        paths
+    }
+
+    /// Read every transfer in the ring, returning their headers' OBS_OFFSETs and data
+    fn read_transfers(client: &mut HduClient, n: usize) -> Vec<(String, Vec<u8>)> {
+        let (mut hc, mut dc) = client.split();
+        (0..n)
+            .map(|_| {
+                let header = hc.read_header().unwrap();
+                let mut data = vec![];
+                let mut reader = dc.reader().unwrap();
+                while let Some(mut block) = reader.next() {
+                    data.extend_from_slice(block.block());
+                }
+                (header["OBS_OFFSET"].clone(), data)
+            })
+            .collect()
+    }
+
+    fn client() -> HduClient {
+        DadaClientBuilder::new(next_key())
+            .num_bufs(8)
+            .buf_size(4)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_replay_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = write_files(dir.path(), &[8, 2]);
+        let mut client = client();
+        let stats = Replayer::new(&files).replay(&mut client).unwrap();
+        assert_eq!(stats, ReplayStats {
+            transfers: 2,
+            bytes: 10
+        });
+        assert_eq!(read_transfers(&mut client, 2), [
+            ("0".to_owned(), (0..8).collect()),
+            ("8".to_owned(), vec![8, 9])
+        ]);
+    }
+
+    #[test]
+    fn test_stitch_and_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = write_files(dir.path(), &[6, 2]);
+        let mut client = client();
+        let stats = Replayer::new(&files)
+            .stitch(true)
+            .loops(2)
+            .replay(&mut client)
+            .unwrap();
+        assert_eq!(stats, ReplayStats {
+            transfers: 2,
+            bytes: 16
+        });
+        let expected = ("0".to_owned(), (0..8).collect::<Vec<_>>());
+        assert_eq!(read_transfers(&mut client, 2), [expected.clone(), expected]);
+
+        // Files from different observations aren't stitched, even if the offsets line up
+        let other = tempfile::tempdir().unwrap();
+        let mut files = write_files(other.path(), &[4]);
+        let later = tempfile::tempdir().unwrap();
+        let header = String::from_utf8(HEADER.to_vec())
+            .unwrap()
+            .replace("12:34:56", "13:00:00");
+        files.push(write_files_with(later.path(), header.as_bytes(), &[4, 4]).remove(1));
+        let stats = Replayer::new(&files)
+            .stitch(true)
+            .replay(&mut client)
+            .unwrap();
+        assert_eq!(stats.transfers, 2);
+    }
+
+    #[test]
+    fn test_bad_rate() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = write_files(dir.path(), &[4]);
+        let mut client = client();
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                Replayer::new(&files).speed(speed).replay(&mut client),
+                Err(PsrdadaError::HeaderValueError { .. })
+            ));
+        }
+        let header = String::from_utf8(HEADER.to_vec())
+            .unwrap()
+            .replace("BYTES_PER_SECOND 400", "BYTES_PER_SECOND 0");
+        let files = write_files_with(dir.path(), header.as_bytes(), &[4]);
+        assert_eq!(
+            Replayer::new(&files).speed(1.0).replay(&mut client),
+            Err(PsrdadaError::HeaderValueError {
+                key: "BYTES_PER_SECOND".to_owned(),
+                value: "0 (at 1 times speed)".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn test_rate_control() {
+        let dir = tempfile::tempdir().unwrap();
+        // 400 bytes/s at double speed is 5ms per block
+        let files = write_files(dir.path(), &[24]);
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_bufs(8)
+            .buf_size(4)
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        Replayer::new(&files)
+            .speed(2.0)
+            .replay(&mut client)
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+}
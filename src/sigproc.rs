@@ -0,0 +1,653 @@
+//! Converting to and from SIGPROC filterbank (`.fil`) files.
+//!
+//! A filterbank file starts with a binary header: the string `HEADER_START`, then keyword/value pairs, then `HEADER_END`. Every
+//! string (keywords included) is written as a little-endian `i32` length followed by the bytes, and every value is an `i32`, an
+//! `f64` or a string, depending on the keyword. The data follows straight after, time-major with the channels of each sample
+//! (starting with `fch1`) fastest, which is also how filterbank data is laid out in DADA rings, so data is copied as is.
+//!
+//! The heart of this module is the mapping between [`StandardHeader`] and [`FilterbankHeader`]:
+//!
+//! | DADA               | SIGPROC                                 |
+//! |--------------------|-----------------------------------------|
+//! | `NCHAN`            | `nchans`                                |
+//! | `NBIT`             | `nbits`                                 |
+//! | `NPOL`             | `nifs`                                  |
+//! | `TSAMP` (µs)       | `tsamp` (s)                             |
+//! | `FREQ`, `BW` (MHz) | `fch1`, `foff` (MHz), channel centres   |
+//! | `UTC_START`        | `tstart` (MJD)                          |
+//! | `SOURCE`           | `source_name`                           |
+//! | `RA`, `DEC`        | `src_raj`, `src_dej` (`hhmmss.s`)       |
+//! | `TELESCOPE`        | `telescope_id`                          |
+//!
+//! `tstart` is the time of the first sample of the transfer, so it takes `OBS_OFFSET` into account. Going the other way,
+//! `UTC_START` only has whole seconds, so the exact start is kept in `MJD_START` as well, which is used in preference to
+//! `UTC_START` when there is one.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufReader, Read, Write},
+    str::FromStr,
+};
+
+use tracing::warn;
+
+use crate::{
+    client::{DataClient, HeaderClient},
+    errors::{PsrdadaError, PsrdadaResult},
+    file::{DadaFileReader, DadaFileWriter},
+    headers::{
+        standard::{required, value_error},
+        DadaHeader, Declination, Mjd, RightAscension, StandardHeader, UtcTime,
+    },
+    io::DadaClient,
+    iter::DadaIterator,
+};
+
+/// Telescope IDs from SIGPROC's `aliases.c`
+const TELESCOPES: &[(i32, &str)] = &[
+    (0, "Fake"),
+    (1, "Arecibo"),
+    (2, "Ooty"),
+    (3, "Nancay"),
+    (4, "Parkes"),
+    (5, "Jodrell"),
+    (6, "GBT"),
+    (7, "GMRT"),
+    (8, "Effelsberg"),
+    (9, "ATA"),
+    (10, "SRT"),
+    (11, "LOFAR"),
+    (12, "VLA"),
+    (64, "MeerKAT"),
+    (65, "KAT-7"),
+];
+
+/// The longest string we accept in a header, to catch files that aren't filterbanks at all
+const MAX_STRING_LEN: usize = 256;
+
+/// A SIGPROC filterbank header. Every keyword is optional, as in the files themselves.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct FilterbankHeader {
+    /// `telescope_id` - See SIGPROC's `aliases.c`
+    pub telescope_id: Option<i32>,
+    /// `machine_id` - The backend, also from `aliases.c`
+    pub machine_id: Option<i32>,
+    /// `data_type` - 1 for filterbank data
+    pub data_type: Option<i32>,
+    /// `rawdatafile` - The file this was made from
+    pub rawdatafile: Option<String>,
+    /// `source_name` - Name of the observed source
+    pub source_name: Option<String>,
+    /// `barycentric` - Whether the data have been barycentred
+    pub barycentric: Option<i32>,
+    /// `pulsarcentric` - Whether the data are in the pulsar's frame
+    pub pulsarcentric: Option<i32>,
+    /// `az_start` - Telescope azimuth at the start, in degrees
+    pub az_start: Option<f64>,
+    /// `za_start` - Telescope zenith angle at the start, in degrees
+    pub za_start: Option<f64>,
+    /// `src_raj` - Right ascension, as `hhmmss.s`
+    pub src_raj: Option<f64>,
+    /// `src_dej` - Declination, as `ddmmss.s`
+    pub src_dej: Option<f64>,
+    /// `tstart` - Time of the first sample, as an MJD
+    pub tstart: Option<f64>,
+    /// `tsamp` - Sampling interval in seconds
+    pub tsamp: Option<f64>,
+    /// `nbits` - Number of bits per sample
+    pub nbits: Option<i32>,
+    /// `nsamples` - Number of time samples, which most readers work out from the file size instead
+    pub nsamples: Option<i32>,
+    /// `fch1` - Centre frequency of the first channel in MHz
+    pub fch1: Option<f64>,
+    /// `foff` - Channel bandwidth in MHz, negative if the channels are in decreasing frequency
+    pub foff: Option<f64>,
+    /// `nchans` - Number of frequency channels
+    pub nchans: Option<i32>,
+    /// `nifs` - Number of IFs (polarizations)
+    pub nifs: Option<i32>,
+    /// `refdm` - Reference dispersion measure, for dedispersed data
+    pub refdm: Option<f64>,
+    /// `period` - Folding period in seconds, for folded data
+    pub period: Option<f64>,
+    /// `nbeams` - Number of beams of a multibeam receiver
+    pub nbeams: Option<i32>,
+    /// `ibeam` - The beam this is from
+    pub ibeam: Option<i32>,
+}
+
+fn read_i32(reader: &mut impl Read) -> PsrdadaResult<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+fn read_f64(reader: &mut impl Read) -> PsrdadaResult<f64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+fn read_string(reader: &mut impl Read) -> PsrdadaResult<String> {
+    let len = read_i32(reader)?;
+    if len <= 0 || len as usize > MAX_STRING_LEN {
+        return Err(PsrdadaError::SigprocMalformedHeader);
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| PsrdadaError::SigprocMalformedHeader)
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as i32).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn put_i32(out: &mut Vec<u8>, key: &str, value: Option<i32>) {
+    if let Some(v) = value {
+        write_string(out, key);
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+}
+
+fn put_f64(out: &mut Vec<u8>, key: &str, value: Option<f64>) {
+    if let Some(v) = value {
+        write_string(out, key);
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+}
+
+fn put_string(out: &mut Vec<u8>, key: &str, value: &Option<String>) {
+    if let Some(v) = value {
+        write_string(out, key);
+        write_string(out, v);
+    }
+}
+
+/// Pack an angle into SIGPROC's `ddmmss.s` form
+fn to_sigproc_angle(units: f64) -> f64 {
+    let whole = units.abs().trunc();
+    let minutes = (units.abs() - whole) * 60.0;
+    let seconds = minutes.fract() * 60.0;
+    (whole * 10000.0 + minutes.trunc() * 100.0 + seconds).copysign(units)
+}
+
+/// Unpack an angle from SIGPROC's `ddmmss.s` form
+fn from_sigproc_angle(packed: f64) -> f64 {
+    let whole = (packed.abs() / 10000.0).trunc();
+    let minutes = ((packed.abs() - whole * 10000.0) / 100.0).trunc();
+    let seconds = packed.abs() - whole * 10000.0 - minutes * 100.0;
+    (whole + minutes / 60.0 + seconds / 3600.0).copysign(packed)
+}
+
+/// A keyword without a field in [`StandardHeader`], if it's there and parses
+fn extra<T: FromStr>(dada: &StandardHeader, key: &str) -> Option<T> {
+    dada.extras.get(key).and_then(|v| v.parse().ok())
+}
+
+impl FilterbankHeader {
+    /// Read a header, leaving `reader` at the start of the data.
+    ///
+    /// Fails with [`PsrdadaError::SigprocMalformedHeader`] if it doesn't start with `HEADER_START` (or a string is garbled), and
+    /// [`PsrdadaError::SigprocUnknownKey`] for a keyword we don't know the type of.
+    pub fn read(reader: &mut impl Read) -> PsrdadaResult<Self> {
+        if read_string(reader)? != "HEADER_START" {
+            return Err(PsrdadaError::SigprocMalformedHeader);
+        }
+        let mut h = Self::default();
+        loop {
+            let key = read_string(reader)?;
+            match key.as_str() {
+                "HEADER_END" => return Ok(h),
+                "telescope_id" => h.telescope_id = Some(read_i32(reader)?),
+                "machine_id" => h.machine_id = Some(read_i32(reader)?),
+                "data_type" => h.data_type = Some(read_i32(reader)?),
+                "rawdatafile" => h.rawdatafile = Some(read_string(reader)?),
+                "source_name" => h.source_name = Some(read_string(reader)?),
+                "barycentric" => h.barycentric = Some(read_i32(reader)?),
+                "pulsarcentric" => h.pulsarcentric = Some(read_i32(reader)?),
+                "az_start" => h.az_start = Some(read_f64(reader)?),
+                "za_start" => h.za_start = Some(read_f64(reader)?),
+                "src_raj" => h.src_raj = Some(read_f64(reader)?),
+                "src_dej" => h.src_dej = Some(read_f64(reader)?),
+                "tstart" => h.tstart = Some(read_f64(reader)?),
+                "tsamp" => h.tsamp = Some(read_f64(reader)?),
+                "nbits" => h.nbits = Some(read_i32(reader)?),
+                "nsamples" => h.nsamples = Some(read_i32(reader)?),
+                "fch1" => h.fch1 = Some(read_f64(reader)?),
+                "foff" => h.foff = Some(read_f64(reader)?),
+                "nchans" => h.nchans = Some(read_i32(reader)?),
+                "nifs" => h.nifs = Some(read_i32(reader)?),
+                "refdm" => h.refdm = Some(read_f64(reader)?),
+                "period" => h.period = Some(read_f64(reader)?),
+                "nbeams" => h.nbeams = Some(read_i32(reader)?),
+                "ibeam" => h.ibeam = Some(read_i32(reader)?),
+                _ => return Err(PsrdadaError::SigprocUnknownKey(key)),
+            }
+        }
+    }
+
+    /// Serialize the header, from `HEADER_START` to `HEADER_END`, in the order SIGPROC writes the keywords
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        write_string(&mut out, "HEADER_START");
+        put_i32(&mut out, "telescope_id", self.telescope_id);
+        put_i32(&mut out, "machine_id", self.machine_id);
+        put_i32(&mut out, "data_type", self.data_type);
+        put_string(&mut out, "rawdatafile", &self.rawdatafile);
+        put_string(&mut out, "source_name", &self.source_name);
+        put_i32(&mut out, "barycentric", self.barycentric);
+        put_i32(&mut out, "pulsarcentric", self.pulsarcentric);
+        put_f64(&mut out, "az_start", self.az_start);
+        put_f64(&mut out, "za_start", self.za_start);
+        put_f64(&mut out, "src_raj", self.src_raj);
+        put_f64(&mut out, "src_dej", self.src_dej);
+        put_f64(&mut out, "tstart", self.tstart);
+        put_f64(&mut out, "tsamp", self.tsamp);
+        put_i32(&mut out, "nbits", self.nbits);
+        put_i32(&mut out, "nsamples", self.nsamples);
+        put_f64(&mut out, "fch1", self.fch1);
+        put_f64(&mut out, "foff", self.foff);
+        put_i32(&mut out, "nchans", self.nchans);
+        put_i32(&mut out, "nifs", self.nifs);
+        put_f64(&mut out, "refdm", self.refdm);
+        put_f64(&mut out, "period", self.period);
+        put_i32(&mut out, "nbeams", self.nbeams);
+        put_i32(&mut out, "ibeam", self.ibeam);
+        write_string(&mut out, "HEADER_END");
+        out
+    }
+
+    /// Write the header to `writer`
+    pub fn write(&self, writer: &mut impl Write) -> PsrdadaResult<()> {
+        Ok(writer.write_all(&self.to_bytes())?)
+    }
+}
+
+fn line<T: fmt::Display>(f: &mut fmt::Formatter<'_>, key: &str, value: &Option<T>) -> fmt::Result {
+    match value {
+        Some(v) => writeln!(f, "{key} {v}"),
+        None => Ok(()),
+    }
+}
+
+/// Lists the keywords that are set as `keyword value` lines, like SIGPROC's `header` tool
+impl fmt::Display for FilterbankHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        line(f, "telescope_id", &self.telescope_id)?;
+        line(f, "machine_id", &self.machine_id)?;
+        line(f, "data_type", &self.data_type)?;
+        line(f, "rawdatafile", &self.rawdatafile)?;
+        line(f, "source_name", &self.source_name)?;
+        line(f, "barycentric", &self.barycentric)?;
+        line(f, "pulsarcentric", &self.pulsarcentric)?;
+        line(f, "az_start", &self.az_start)?;
+        line(f, "za_start", &self.za_start)?;
+        line(f, "src_raj", &self.src_raj)?;
+        line(f, "src_dej", &self.src_dej)?;
+        line(f, "tstart", &self.tstart)?;
+        line(f, "tsamp", &self.tsamp)?;
+        line(f, "nbits", &self.nbits)?;
+        line(f, "nsamples", &self.nsamples)?;
+        line(f, "fch1", &self.fch1)?;
+        line(f, "foff", &self.foff)?;
+        line(f, "nchans", &self.nchans)?;
+        line(f, "nifs", &self.nifs)?;
+        line(f, "refdm", &self.refdm)?;
+        line(f, "period", &self.period)?;
+        line(f, "nbeams", &self.nbeams)?;
+        line(f, "ibeam", &self.ibeam)?;
+        Ok(())
+    }
+}
+
+/// Needs `NCHAN`, `NBIT`, `TSAMP`, `FREQ` and `BW`, failing with [`PsrdadaError::HeaderMissingKey`] otherwise
+impl TryFrom<&StandardHeader> for FilterbankHeader {
+    type Error = PsrdadaError;
+
+    fn try_from(dada: &StandardHeader) -> Result<Self, Self::Error> {
+        let nchan = required(dada.nchan, "NCHAN")?;
+        let bw = required(dada.bw, "BW")?;
+        let freq = required(dada.freq, "FREQ")?;
+        if let Some(ndim) = dada.ndim.filter(|ndim| *ndim != 1) {
+            // Filterbanks are detected, so complex data can't go in one
+            return Err(value_error("NDIM", ndim));
+        }
+        let foff = bw / nchan as f64;
+        let telescope_id = dada.telescope.as_ref().and_then(|name| {
+            let id = TELESCOPES
+                .iter()
+                .find(|(_, known)| known.eq_ignore_ascii_case(name));
+            if id.is_none() {
+                warn!(name, "No SIGPROC telescope ID for this telescope");
+            }
+            id.map(|(id, _)| *id)
+        });
+        // MJD_START has the fractional seconds UTC_START is missing
+        let start = extra(dada, "MJD_START")
+            .map(|mjd: Mjd| mjd.to_utc())
+            .or(dada.utc_start)
+            .map(|t| {
+                dada.bytes_per_second
+                    .and_then(|bps| t.at_byte(dada.obs_offset.unwrap_or_default(), bps, 0))
+                    .unwrap_or(t)
+            });
+        Ok(Self {
+            telescope_id,
+            data_type: Some(1),
+            source_name: dada.source.clone(),
+            az_start: extra(dada, "AZ_START"),
+            za_start: extra(dada, "ZA_START"),
+            src_raj: dada.ra.map(|ra| to_sigproc_angle(ra.hours())),
+            src_dej: dada.dec.map(|dec| to_sigproc_angle(dec.degrees())),
+            tstart: start.map(|t| t.to_mjd().as_f64()),
+            tsamp: Some(required(dada.tsamp, "TSAMP")? / 1e6),
+            nbits: Some(required(dada.nbit, "NBIT")? as i32),
+            fch1: Some(freq - bw / 2.0 + foff / 2.0),
+            foff: Some(foff),
+            nchans: Some(nchan as i32),
+            nifs: Some(dada.npol.unwrap_or(1) as i32),
+            nbeams: extra(dada, "NBEAM"),
+            ibeam: extra(dada, "BEAM"),
+            ..Default::default()
+        })
+    }
+}
+
+/// A count from the header, failing with [`PsrdadaError::HeaderValueError`] unless it's positive
+fn positive(value: i32, key: &str) -> PsrdadaResult<u32> {
+    u32::try_from(value)
+        .ok()
+        .filter(|value| *value > 0)
+        .ok_or_else(|| value_error(key, value))
+}
+
+/// Needs `nchans`, `nbits`, `tsamp`, `fch1` and `foff`, failing with [`PsrdadaError::HeaderMissingKey`] (naming the SIGPROC
+/// keyword) otherwise, and with [`PsrdadaError::HeaderValueError`] if `nchans`, `nbits` or `nifs` isn't positive
+impl TryFrom<&FilterbankHeader> for StandardHeader {
+    type Error = PsrdadaError;
+
+    fn try_from(fil: &FilterbankHeader) -> Result<Self, Self::Error> {
+        let nchans = positive(required(fil.nchans, "nchans")?, "nchans")?;
+        let nbits = positive(required(fil.nbits, "nbits")?, "nbits")?;
+        let npol = positive(fil.nifs.unwrap_or(1), "nifs")?;
+        let tsamp = required(fil.tsamp, "tsamp")?;
+        let fch1 = required(fil.fch1, "fch1")?;
+        let foff = required(fil.foff, "foff")?;
+        let bw = foff * nchans as f64;
+        let bits = u64::from(nchans)
+            .checked_mul(nbits.into())
+            .and_then(|bits| bits.checked_mul(npol.into()))
+            .ok_or_else(|| value_error("nchans", nchans))?;
+
+        let mut extras = HashMap::new();
+        let mut utc_start = None;
+        if let Some(tstart) = fil.tstart {
+            let start = tstart.to_string().parse::<Mjd>()?.to_utc();
+            // A float MJD is only good to about a microsecond, so round off the noise
+            let micros = (start.subsec_nanos() + 500) / 1000;
+            let start = UtcTime::from_unix(start.unix_seconds(), micros * 1000);
+            utc_start = Some(UtcTime::from_unix(start.unix_seconds(), 0));
+            extras.insert("MJD_START".to_owned(), start.to_mjd().to_string());
+        }
+        for (key, value) in [("AZ_START", fil.az_start), ("ZA_START", fil.za_start)] {
+            if let Some(value) = value {
+                extras.insert(key.to_owned(), value.to_string());
+            }
+        }
+        for (key, value) in [("NBEAM", fil.nbeams), ("BEAM", fil.ibeam)] {
+            if let Some(value) = value {
+                extras.insert(key.to_owned(), value.to_string());
+            }
+        }
+        let dec = match fil.src_dej {
+            Some(packed) => Some(
+                Declination::from_degrees(from_sigproc_angle(packed), 4)
+                    .ok_or_else(|| value_error("src_dej", packed))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            utc_start,
+            obs_offset: utc_start.map(|_| 0),
+            freq: Some(fch1 - foff / 2.0 + bw / 2.0),
+            bw: Some(bw),
+            nchan: Some(nchans),
+            npol: Some(npol),
+            nbit: Some(nbits),
+            ndim: Some(1),
+            tsamp: Some(tsamp * 1e6),
+            bytes_per_second: Some((bits as f64 / 8.0 / tsamp).round() as u64),
+            source: fil.source_name.clone(),
+            ra: fil
+                .src_raj
+                .map(|packed| RightAscension::from_hours(from_sigproc_angle(packed), 4)),
+            dec,
+            telescope: fil.telescope_id.and_then(|id| {
+                TELESCOPES
+                    .iter()
+                    .find(|(known, _)| *known == id)
+                    .map(|(_, name)| (*name).to_owned())
+            }),
+            extras,
+            ..Default::default()
+        })
+    }
+}
+
+/// Read the next transfer from a ring and write it to `out` as a filterbank, returning the number of data bytes written
+pub fn record_filterbank(
+    header_client: &mut HeaderClient,
+    data_client: &mut DataClient,
+    out: &mut impl Write,
+) -> PsrdadaResult<u64> {
+    let dada = StandardHeader::try_from(header_client.read_header()?)?;
+    FilterbankHeader::try_from(&dada)?.write(out)?;
+    let mut bytes = 0;
+    let mut reader = data_client.reader()?;
+    while let Some(mut block) = reader.next() {
+        let data = block.block();
+        out.write_all(data)?;
+        bytes += data.len() as u64;
+    }
+    Ok(bytes)
+}
+
+/// Write a filterbank into a ring as a single transfer, returning the number of data bytes written
+pub fn play_filterbank(
+    input: impl Read,
+    header_client: &mut HeaderClient,
+    data_client: &mut DataClient,
+) -> PsrdadaResult<u64> {
+    let mut input = BufReader::new(input);
+    let fil = FilterbankHeader::read(&mut input)?;
+    header_client.write_header(&StandardHeader::try_from(&fil)?.into())?;
+    data_client.writer()?.write_from(&mut input, |_| ())
+}
+
+/// Convert a `.dada` file (or a stream of one) to a filterbank, returning the number of data bytes copied
+pub fn dada_to_filterbank(input: impl Read, out: &mut impl Write) -> PsrdadaResult<u64> {
+    let mut reader = DadaFileReader::new(input)?;
+    let dada = StandardHeader::try_from(HashMap::from(reader.header()))?;
+    FilterbankHeader::try_from(&dada)?.write(out)?;
+    Ok(io::copy(&mut reader, out)?)
+}
+
+/// Convert a filterbank (or a stream of one) to a `.dada` file, returning the number of data bytes copied
+pub fn filterbank_to_dada(input: impl Read, out: &mut impl Write) -> PsrdadaResult<u64> {
+    let mut input = BufReader::new(input);
+    let fil = FilterbankHeader::read(&mut input)?;
+    let mut dada: Vec<_> = HashMap::from(StandardHeader::try_from(&fil)?)
+        .into_iter()
+        .collect();
+    dada.sort();
+    let mut writer = DadaFileWriter::new(out, dada.into_iter().collect::<DadaHeader>())?;
+    io::copy(&mut input, &mut writer)?;
+    Ok(writer.bytes_written())
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, headers::bytes_to_header, tests::next_key};
+
+    const DADA: &[u8] = b"HDR_SIZE 4096
+UTC_START 2023-05-01-12:00:00
+OBS_OFFSET 512
+BYTES_PER_SECOND 256
+FREQ 1400
+BW -400
+NCHAN 4
+NPOL 1
+NBIT 8
+NDIM 1
+TSAMP 15625
+SOURCE J0437-4715
+RA 04:37:15.8961
+DEC -47:15:09.1100
+TELESCOPE Parkes
+";
+
+    fn standard() -> StandardHeader {
+        StandardHeader::try_from(bytes_to_header(DADA).unwrap()).unwrap()
+    }
+
+    fn close(a: Option<f64>, b: f64) -> bool {
+        (a.unwrap() - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn test_dada_to_filterbank() {
+        let fil = FilterbankHeader::try_from(&standard()).unwrap();
+        assert_eq!(fil.nchans, Some(4));
+        assert_eq!(fil.nbits, Some(8));
+        assert_eq!(fil.nifs, Some(1));
+        assert_eq!(fil.telescope_id, Some(4));
+        assert_eq!(fil.source_name.as_deref(), Some("J0437-4715"));
+        assert!(close(fil.tsamp, 0.015625));
+        assert!(close(fil.foff, -100.0));
+        // The highest channel is centred 50 MHz below the top of the band
+        assert!(close(fil.fch1, 1550.0));
+        // Two seconds in, from OBS_OFFSET
+        assert!(close(fil.tstart, 60065.5 + 2.0 / 86400.0));
+        assert!(close(fil.src_raj, 43715.8961));
+        assert!(close(fil.src_dej, -471509.11));
+
+        let mut missing = standard();
+        missing.bw = None;
+        assert_eq!(
+            FilterbankHeader::try_from(&missing),
+            Err(PsrdadaError::HeaderMissingKey("BW".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let fil = FilterbankHeader::try_from(&standard()).unwrap();
+        let bytes = fil.to_bytes();
+        assert_eq!(&bytes[..16], b"\x0c\0\0\0HEADER_START");
+        assert_eq!(FilterbankHeader::read(&mut &bytes[..]).unwrap(), fil);
+        assert!(fil.to_string().contains("nchans 4\nnifs 1\n"));
+
+        let dada = StandardHeader::try_from(&fil).unwrap();
+        let original = standard();
+        assert_eq!(dada.nchan, original.nchan);
+        assert_eq!(dada.bytes_per_second, original.bytes_per_second);
+        assert_eq!(dada.ra, original.ra);
+        assert_eq!(dada.dec, original.dec);
+        assert_eq!(dada.telescope.as_deref(), Some("Parkes"));
+        assert!(close(dada.freq, 1400.0));
+        assert!(close(dada.bw, -400.0));
+        assert!(close(dada.tsamp, 15625.0));
+        assert_eq!(dada.utc_start, Some("2023-05-01-12:00:02".parse().unwrap()));
+        assert!(dada.extras["MJD_START"].starts_with("60065.50002314"));
+    }
+
+    #[test]
+    fn test_bad_headers() {
+        let mut bytes = FilterbankHeader::default().to_bytes();
+        bytes[4] = b'X';
+        assert_eq!(
+            FilterbankHeader::read(&mut &bytes[..]),
+            Err(PsrdadaError::SigprocMalformedHeader)
+        );
+        let mut bytes = vec![];
+        write_string(&mut bytes, "HEADER_START");
+        write_string(&mut bytes, "mystery");
+        assert_eq!(
+            FilterbankHeader::read(&mut &bytes[..]),
+            Err(PsrdadaError::SigprocUnknownKey("mystery".to_owned()))
+        );
+        assert_eq!(
+            FilterbankHeader::read(&mut &bytes[..10]),
+            Err(PsrdadaError::IoError(io::ErrorKind::UnexpectedEof))
+        );
+
+        let fil = FilterbankHeader::try_from(&standard()).unwrap();
+        for (key, bad) in [
+            ("nchans", FilterbankHeader {
+                nchans: Some(-4),
+                ..fil.clone()
+            }),
+            ("nbits", FilterbankHeader {
+                nbits: Some(0),
+                ..fil.clone()
+            }),
+            ("nifs", FilterbankHeader {
+                nifs: Some(i32::MIN),
+                ..fil.clone()
+            }),
+        ] {
+            assert!(matches!(
+                StandardHeader::try_from(&bad),
+                Err(PsrdadaError::HeaderValueError { key: k, .. }) if k == key
+            ));
+        }
+        // Too big to work out the data rate from, rather than a wrong one
+        let big = FilterbankHeader {
+            nchans: Some(i32::MAX),
+            nbits: Some(i32::MAX),
+            nifs: Some(i32::MAX),
+            ..fil
+        };
+        assert_eq!(
+            StandardHeader::try_from(&big),
+            Err(value_error("nchans", i32::MAX))
+        );
+    }
+
+    #[test]
+    fn test_file_conversion() {
+        let mut dada = DADA.to_vec();
+        dada.resize(4096, 0);
+        dada.extend(0..32);
+
+        let mut fil = vec![];
+        assert_eq!(dada_to_filterbank(&dada[..], &mut fil).unwrap(), 32);
+        let mut back = vec![];
+        assert_eq!(filterbank_to_dada(&fil[..], &mut back).unwrap(), 32);
+        let reader = DadaFileReader::new(&back[..]).unwrap();
+        assert_eq!(reader.header().get("NCHAN"), Some("4"));
+        assert_eq!(&back[4096..], &dada[4096..]);
+    }
+
+    #[test]
+    fn test_ring_round_trip() {
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_bufs(8)
+            .buf_size(8)
+            .build()
+            .unwrap();
+        let (mut hc, mut dc) = client.split();
+        let mut fil = FilterbankHeader::try_from(&standard()).unwrap().to_bytes();
+        fil.extend(0..20);
+
+        assert_eq!(play_filterbank(&fil[..], &mut hc, &mut dc).unwrap(), 20);
+        let mut out = vec![];
+        assert_eq!(record_filterbank(&mut hc, &mut dc, &mut out).unwrap(), 20);
+        assert_eq!(out, fil);
+    }
+}
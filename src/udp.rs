@@ -0,0 +1,479 @@
+//! Capturing UDP packets into a ring, like `udpdb`.
+//!
+//! Every packet is a fixed size header followed by a fixed size payload, and carries a sequence number that a user-supplied
+//! function pulls out of it (see [`be_counter`] and [`le_counter`] for the usual cases). The payload of packet `n` after the
+//! first one goes `n` payloads into the transfer, straight into the [`WriteBlock`](crate::io::write::WriteBlock) it belongs in,
+//! so packets can arrive in any order within a block.
+//!
+//! A block is finished once a packet arrives more than a window's worth of packets past its end. Any packets still missing
+//! from it are zero-filled and counted, packets in the window that belong to the next block are held on to until it opens, and
+//! anything that turns up for a finished block is counted as late and dropped. Packets with the wrong size, or that the
+//! sequence function rejects, are ignored, as are packets that skip more than [`max_jump`](UdpCapture::max_jump) packets past
+//! the latest one, rather than zero-filling everything up to a corrupt sequence number. Everything is counted in
+//! [`CaptureStats`].
+
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+use crate::{
+    client::DataClient,
+    errors::{PsrdadaError, PsrdadaResult},
+    io::DadaClient,
+    iter::DadaIterator,
+};
+
+/// The most packets a capture skips over in one go by default, see [`UdpCapture::max_jump`]
+pub const DEFAULT_MAX_JUMP: u64 = 1 << 20;
+
+/// How often a capture checks whether it should stop while no packets are arriving
+const POLL: Duration = Duration::from_millis(100);
+
+/// A sequence number stored as a big-endian `u64` starting `offset` bytes into the packet
+pub fn be_counter(offset: usize) -> impl FnMut(&[u8]) -> Option<u64> {
+    move |packet| {
+        Some(u64::from_be_bytes(
+            packet.get(offset..offset + 8)?.try_into().ok()?,
+        ))
+    }
+}
+
+/// A sequence number stored as a little-endian `u64` starting `offset` bytes into the packet
+pub fn le_counter(offset: usize) -> impl FnMut(&[u8]) -> Option<u64> {
+    move |packet| {
+        Some(u64::from_le_bytes(
+            packet.get(offset..offset + 8)?.try_into().ok()?,
+        ))
+    }
+}
+
+/// Counters of the packets seen by a [`UdpCapture`]
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct CaptureStats {
+    /// Number of packets written into the ring
+    pub packets: u64,
+    /// Number of bytes written into the ring, zero-fill included
+    pub bytes: u64,
+    /// Number of packets that never arrived, replaced by zeros
+    pub missing: u64,
+    /// Number of packets dropped for arriving after their block was finished, or twice
+    pub late: u64,
+    /// Number of packets ignored for being the wrong size, not having a sequence number, or jumping too far ahead
+    pub ignored: u64,
+}
+
+impl CaptureStats {
+    /// The fraction of the packets that should have been written but weren't
+    pub fn loss(&self) -> f64 {
+        let expected = self.packets + self.missing;
+        if expected == 0 {
+            0.0
+        } else {
+            self.missing as f64 / expected as f64
+        }
+    }
+}
+
+/// Zero the slots of the packets that never arrived, returning how many there were
+fn zero_missing(block: &mut [u8], received: &[bool], payload_size: usize) -> u64 {
+    let mut missing = 0;
+    for (slot, _) in received.iter().enumerate().filter(|(_, r)| !**r) {
+        block[slot * payload_size..(slot + 1) * payload_size].fill(0);
+        missing += 1;
+    }
+    missing
+}
+
+/// Move the held packets that belong in the block starting at packet `start` into it
+fn place_pending(
+    block: &mut [u8],
+    received: &mut [bool],
+    pending: &mut BTreeMap<u64, Vec<u8>>,
+    start: u64,
+) {
+    let later = pending.split_off(&(start + received.len() as u64));
+    for (seq, payload) in std::mem::replace(pending, later) {
+        let slot = (seq - start) as usize;
+        block[slot * payload.len()..(slot + 1) * payload.len()].copy_from_slice(&payload);
+        received[slot] = true;
+    }
+}
+
+/// Receives UDP packets into a ring, see the [module docs](self)
+pub struct UdpCapture<F> {
+    socket: UdpSocket,
+    payload_size: usize,
+    header_len: usize,
+    sequence: F,
+    window: u64,
+    max_jump: u64,
+    idle_timeout: Option<Duration>,
+    packet: Vec<u8>,
+    last_packet: Option<Instant>,
+    stats: CaptureStats,
+}
+
+impl<F: FnMut(&[u8]) -> Option<u64>> UdpCapture<F> {
+    /// Bind a socket to `addr` to receive packets with `payload_size` bytes of payload, numbered by `sequence`
+    pub fn bind(addr: impl ToSocketAddrs, payload_size: usize, sequence: F) -> PsrdadaResult<Self> {
+        Ok(Self::new(UdpSocket::bind(addr)?, payload_size, sequence))
+    }
+
+    /// Receive packets with `payload_size` bytes of payload, numbered by `sequence`, from a socket that's already set up
+    pub fn new(socket: UdpSocket, payload_size: usize, sequence: F) -> Self {
+        Self {
+            socket,
+            payload_size: payload_size.max(1),
+            header_len: 0,
+            sequence,
+            window: 0,
+            max_jump: DEFAULT_MAX_JUMP,
+            idle_timeout: None,
+            packet: vec![],
+            last_packet: None,
+            stats: CaptureStats::default(),
+        }
+    }
+
+    /// The number of bytes before the payload of every packet, 0 by default. The sequence function sees the whole packet.
+    pub fn header_len(mut self, header_len: usize) -> Self {
+        self.header_len = header_len;
+        self
+    }
+
+    /// How many packets past the end of a block to wait for stragglers before finishing it, 0 by default
+    pub fn window(mut self, packets: u64) -> Self {
+        self.window = packets;
+        self
+    }
+
+    /// The most packets that can be skipped between the latest packet and the next, [`DEFAULT_MAX_JUMP`] by default. Packets
+    /// further ahead are ignored, as their sequence number is most likely garbage.
+    pub fn max_jump(mut self, packets: u64) -> Self {
+        self.max_jump = packets;
+        self
+    }
+
+    /// End a capture once no packets have arrived for this long (after the first one)
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// The address the socket is bound to
+    pub fn local_addr(&self) -> PsrdadaResult<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// The counters of every capture so far
+    pub fn stats(&self) -> CaptureStats {
+        self.stats
+    }
+
+    /// Wait for the next packet with a sequence number, returning `None` once it's time to stop
+    fn recv(&mut self, stop: &AtomicBool) -> PsrdadaResult<Option<u64>> {
+        let packet_len = self.header_len + self.payload_size;
+        loop {
+            if stop.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            match self.socket.recv(&mut self.packet) {
+                Ok(n) if n == packet_len => match (self.sequence)(&self.packet[..n]) {
+                    Some(seq) => {
+                        self.last_packet = Some(Instant::now());
+                        return Ok(Some(seq));
+                    }
+                    None => self.stats.ignored += 1,
+                },
+                Ok(_) => self.stats.ignored += 1,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::Interrupted
+                    ) => {}
+                Err(e) => return Err(e.into()),
+            }
+            if let (Some(timeout), Some(last)) = (self.idle_timeout, self.last_packet) {
+                if last.elapsed() >= timeout {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Capture packets into a single transfer, until `stop` is set or the idle timeout passes, returning the counters of just
+    /// this capture.
+    ///
+    /// The transfer starts with the first packet that arrives, and ends after the latest one. The ring's blocks have to hold a
+    /// whole number of payloads, failing with [`PsrdadaError::UdpPacketSize`] otherwise.
+    pub fn capture(
+        &mut self,
+        data_client: &mut DataClient,
+        stop: &AtomicBool,
+    ) -> PsrdadaResult<CaptureStats> {
+        let before = self.stats;
+        self.socket.set_read_timeout(Some(POLL))?;
+        // One byte extra, to tell packets that are too long
+        self.packet
+            .resize(self.header_len + self.payload_size + 1, 0);
+        self.last_packet = None;
+        let first = match self.recv(stop)? {
+            Some(seq) => seq,
+            None => return Ok(CaptureStats::default()),
+        };
+
+        let payload_size = self.payload_size;
+        let mut writer = data_client.writer()?;
+        let mut block = writer.next().ok_or(PsrdadaError::DadaWriteError)?;
+        let block_size = block.block().len();
+        if block_size % payload_size != 0 {
+            block.increment_filled(0);
+            return Err(PsrdadaError::UdpPacketSize {
+                payload_size,
+                block_size,
+            });
+        }
+        let per_block = (block_size / payload_size) as u64;
+        let mut received = vec![false; per_block as usize];
+        let mut pending = BTreeMap::new();
+        // The packet the current block starts at, and one past the latest packet, counting from the first
+        let mut start = 0;
+        let mut end = 0u64;
+        let mut seq = first;
+        loop {
+            match seq.checked_sub(first) {
+                Some(n) if n > end.saturating_add(self.max_jump) => self.stats.ignored += 1,
+                Some(n) => {
+                    while n >= start + per_block + self.window {
+                        self.stats.missing += zero_missing(block.block(), &received, payload_size);
+                        self.stats.bytes += block_size as u64;
+                        drop(block);
+                        block = writer.next().ok_or(PsrdadaError::DadaWriteError)?;
+                        start += per_block;
+                        received.fill(false);
+                        place_pending(block.block(), &mut received, &mut pending, start);
+                    }
+                    let payload = &self.packet[self.header_len..self.header_len + payload_size];
+                    if n < start
+                        || (n < start + per_block && received[(n - start) as usize])
+                        || pending.contains_key(&n)
+                    {
+                        self.stats.late += 1;
+                    } else {
+                        if n < start + per_block {
+                            let slot = (n - start) as usize;
+                            block.block()[slot * payload_size..(slot + 1) * payload_size]
+                                .copy_from_slice(payload);
+                            received[slot] = true;
+                        } else {
+                            pending.insert(n, payload.to_vec());
+                        }
+                        self.stats.packets += 1;
+                        end = end.max(n + 1);
+                    }
+                }
+                None => self.stats.late += 1,
+            }
+            seq = match self.recv(stop)? {
+                Some(seq) => seq,
+                None => break,
+            };
+        }
+
+        // Write out everything up to the latest packet, ending the transfer there
+        loop {
+            if end <= start + per_block {
+                let slots = (end - start) as usize;
+                self.stats.missing += zero_missing(block.block(), &received[..slots], payload_size);
+                self.stats.bytes += (slots * payload_size) as u64;
+                if slots == received.len() {
+                    block.mark_eod();
+                } else {
+                    block.increment_filled(slots * payload_size);
+                }
+                break;
+            }
+            self.stats.missing += zero_missing(block.block(), &received, payload_size);
+            self.stats.bytes += block_size as u64;
+            drop(block);
+            block = writer.next().ok_or(PsrdadaError::DadaWriteError)?;
+            start += per_block;
+            received.fill(false);
+            place_pending(block.block(), &mut received, &mut pending, start);
+        }
+        let stats = CaptureStats {
+            packets: self.stats.packets - before.packets,
+            bytes: self.stats.bytes - before.bytes,
+            missing: self.stats.missing - before.missing,
+            late: self.stats.late - before.late,
+            ignored: self.stats.ignored - before.ignored,
+        };
+        if stats.missing > 0 {
+            warn!(
+                missing = stats.missing,
+                loss = stats.loss(),
+                "Zero-filled missing packets"
+            );
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use test_log::test;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, tests::next_key};
+
+    /// A packet with an 8 byte big-endian sequence number and `payload_size` bytes of `seq as u8`
+    fn packet(seq: u64, payload_size: usize) -> Vec<u8> {
+        let mut packet = seq.to_be_bytes().to_vec();
+        packet.resize(8 + payload_size, seq as u8);
+        packet
+    }
+
+    fn capture(
+        sends: Vec<Vec<u8>>,
+        window: u64,
+        num_bufs: u64,
+        buf_size: u64,
+    ) -> (CaptureStats, Vec<u8>) {
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_bufs(num_bufs)
+            .buf_size(buf_size)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+        let mut capture = UdpCapture::bind("127.0.0.1:0", 16, be_counter(0))
+            .unwrap()
+            .header_len(8)
+            .window(window)
+            .idle_timeout(Duration::from_millis(300));
+        let addr = capture.local_addr().unwrap();
+        let sender = thread::spawn(move || {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            for packet in sends {
+                socket.send_to(&packet, addr).unwrap();
+                // Loopback doesn't reorder or drop, but don't overrun the receive buffer
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        let stats = capture.capture(&mut dc, &AtomicBool::new(false)).unwrap();
+        sender.join().unwrap();
+        let mut data = vec![];
+        let mut reader = dc.reader().unwrap();
+        while let Some(mut block) = reader.next() {
+            data.extend_from_slice(block.block());
+        }
+        (stats, data)
+    }
+
+    fn expected(seqs: impl IntoIterator<Item = u64>) -> Vec<u8> {
+        seqs.into_iter()
+            .flat_map(|seq| vec![seq as u8; 16])
+            .collect()
+    }
+
+    #[test]
+    fn test_in_order() {
+        let sends = (100..110).map(|seq| packet(seq, 16)).collect();
+        let (stats, data) = capture(sends, 0, 8, 64);
+        assert_eq!(data, expected(100..110));
+        assert_eq!(stats, CaptureStats {
+            packets: 10,
+            bytes: 160,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_loss_and_reordering() {
+        // 102 never arrives, 105 comes after the next block has started (within the window), 101 after its block was finished,
+        // and 104 twice. Then some junk.
+        let order = [
+            100, 103, 104, 106, 107, 105, 108, 109, 110, 111, 112, 101, 104,
+        ];
+        let mut sends: Vec<_> = order.iter().map(|seq| packet(*seq, 16)).collect();
+        sends.push(vec![1, 2, 3]);
+        let (stats, data) = capture(sends, 3, 8, 64);
+        let mut expected = expected(100..113);
+        expected[16..48].fill(0);
+        assert_eq!(data, expected);
+        assert_eq!(stats, CaptureStats {
+            packets: 11,
+            bytes: 13 * 16,
+            missing: 2,
+            late: 2,
+            ignored: 1,
+        });
+        assert!((stats.loss() - 2.0 / 13.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_max_jump() {
+        // Without the limit, this would zero-fill blocks until the ring ran out of room
+        let order = [100, 101, u64::MAX / 2, 102, 103];
+        let sends = order.iter().map(|seq| packet(*seq, 16)).collect();
+        let (stats, data) = capture(sends, 0, 8, 64);
+        assert_eq!(data, expected(100..104));
+        assert_eq!(stats, CaptureStats {
+            packets: 4,
+            bytes: 64,
+            ignored: 1,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_block_size() {
+        let mut client = DadaClientBuilder::new(next_key())
+            .buf_size(40)
+            .build()
+            .unwrap();
+        let (_, mut dc) = client.split();
+        let mut capture = UdpCapture::bind("127.0.0.1:0", 16, be_counter(0))
+            .unwrap()
+            .header_len(8);
+        let addr = capture.local_addr().unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.send_to(&packet(0, 16), addr).unwrap();
+        assert_eq!(
+            capture.capture(&mut dc, &AtomicBool::new(false)),
+            Err(PsrdadaError::UdpPacketSize {
+                payload_size: 16,
+                block_size: 40
+            })
+        );
+    }
+
+    #[test]
+    fn test_stop() {
+        let mut client = DadaClientBuilder::new(next_key()).build().unwrap();
+        let (_, mut dc) = client.split();
+        let mut capture = UdpCapture::bind("127.0.0.1:0", 16, le_counter(0)).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopper = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                stop.store(true, Ordering::Relaxed);
+            })
+        };
+        assert_eq!(
+            capture.capture(&mut dc, &stop).unwrap(),
+            CaptureStats::default()
+        );
+        stopper.join().unwrap();
+    }
+}
@@ -0,0 +1,860 @@
+//! Packing ring data into VDIF frames and unpacking VDIF frames into rings.
+//!
+//! A VDIF frame is a header of eight little-endian 32-bit words (or four, for the legacy variant) followed by the data. The header
+//! says when the frame starts, as whole seconds since a reference epoch (every half year since 2000) and a frame number within
+//! that second, along with the thread, the sample format and whether the frame is invalid. How many frames there are in a second
+//! isn't in the header, so [`VdifAssembler`] has to be told.
+//!
+//! Going into a ring, [`VdifAssembler`] puts frames back in time order within a small window, fills any missing frames (and the
+//! data of invalid ones) so that every byte stays at the right offset, and keeps count of all of it in [`VdifStats`]. Going the
+//! other way, [`VdifPacketizer`] cuts data into frames timestamped from `UTC_START`, `OBS_OFFSET` and `BYTES_PER_SECOND`.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, BufReader, Read, Write},
+};
+
+use tracing::warn;
+
+use crate::{
+    client::{DataClient, HeaderClient},
+    errors::{PsrdadaError, PsrdadaResult},
+    file::read_up_to,
+    headers::{
+        standard::{required, value_error},
+        StandardHeader, UtcTime,
+    },
+    io::DadaClient,
+    iter::DadaIterator,
+};
+
+/// The most frames [`VdifAssembler`] fills in for in one go by default, see [`VdifAssembler::max_gap`]
+pub const DEFAULT_MAX_GAP: u64 = 1 << 20;
+
+/// The size of a VDIF header, in bytes
+pub const HEADER_LEN: usize = 32;
+/// The size of a legacy VDIF header, without the extended user data
+pub const LEGACY_HEADER_LEN: usize = 16;
+
+fn word(bytes: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[4 * i],
+        bytes[4 * i + 1],
+        bytes[4 * i + 2],
+        bytes[4 * i + 3],
+    ])
+}
+
+/// The start of reference epoch `epoch`: the 1st of January or July, `epoch / 2` years after 2000
+pub fn epoch_start(epoch: u8) -> UtcTime {
+    let month = if epoch % 2 == 0 { 1 } else { 7 };
+    UtcTime::from_calendar(2000 + epoch as i64 / 2, month, 1, 0, 0, 0, 0)
+        .expect("The start of an epoch is a valid date")
+}
+
+/// The latest reference epoch that starts at or before `t`, if there is one that fits in six bits
+fn epoch_at(t: UtcTime) -> Option<u8> {
+    let (year, month, ..) = t.to_calendar();
+    let epoch = (year - 2000) * 2 + if month >= 7 { 1 } else { 0 };
+    (0..64).contains(&epoch).then_some(epoch as u8)
+}
+
+/// The header of a VDIF frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VdifHeader {
+    /// The data of this frame shouldn't be used
+    pub invalid: bool,
+    /// This is a legacy header, without the extended user data
+    pub legacy: bool,
+    /// Seconds since the start of the reference epoch
+    pub seconds: u32,
+    /// Half years since 2000
+    pub ref_epoch: u8,
+    /// Frame number within the second, from 0
+    pub frame_number: u32,
+    /// VDIF version
+    pub version: u8,
+    /// Log2 of the number of channels
+    pub log2_channels: u8,
+    /// Length of the frame in bytes, header included. Always a multiple of 8.
+    pub frame_length: u32,
+    /// The samples are complex rather than real
+    pub complex: bool,
+    /// Bits per sample (of each part, for complex samples)
+    pub bits_per_sample: u8,
+    /// Thread ID
+    pub thread_id: u16,
+    /// Station ID
+    pub station_id: u16,
+    /// Extended user data, which legacy headers don't have
+    pub extended: [u32; 4],
+}
+
+impl VdifHeader {
+    /// Parse the header at the start of `bytes`, failing with [`PsrdadaError::VdifMalformedFrame`] if there aren't enough bytes
+    /// or the frame length is too short to hold the header
+    pub fn parse(bytes: &[u8]) -> PsrdadaResult<Self> {
+        if bytes.len() < LEGACY_HEADER_LEN {
+            return Err(PsrdadaError::VdifMalformedFrame);
+        }
+        let (w0, w1, w2, w3) = (
+            word(bytes, 0),
+            word(bytes, 1),
+            word(bytes, 2),
+            word(bytes, 3),
+        );
+        let mut header = Self {
+            invalid: w0 >> 31 != 0,
+            legacy: (w0 >> 30) & 1 != 0,
+            seconds: w0 & 0x3fff_ffff,
+            ref_epoch: ((w1 >> 24) & 0x3f) as u8,
+            frame_number: w1 & 0xff_ffff,
+            version: (w2 >> 29) as u8,
+            log2_channels: ((w2 >> 24) & 0x1f) as u8,
+            frame_length: (w2 & 0xff_ffff) * 8,
+            complex: w3 >> 31 != 0,
+            bits_per_sample: ((w3 >> 26) & 0x1f) as u8 + 1,
+            thread_id: ((w3 >> 16) & 0x3ff) as u16,
+            station_id: (w3 & 0xffff) as u16,
+            extended: [0; 4],
+        };
+        if !header.legacy {
+            if bytes.len() < HEADER_LEN {
+                return Err(PsrdadaError::VdifMalformedFrame);
+            }
+            for (i, extended) in header.extended.iter_mut().enumerate() {
+                *extended = word(bytes, 4 + i);
+            }
+        }
+        if (header.frame_length as usize) < header.header_len() {
+            return Err(PsrdadaError::VdifMalformedFrame);
+        }
+        Ok(header)
+    }
+
+    /// The header as it's sent, 32 bytes or 16 for a legacy header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let words = [
+            (self.invalid as u32) << 31 | (self.legacy as u32) << 30 | (self.seconds & 0x3fff_ffff),
+            (self.ref_epoch as u32 & 0x3f) << 24 | (self.frame_number & 0xff_ffff),
+            (self.version as u32 & 0x7) << 29
+                | (self.log2_channels as u32 & 0x1f) << 24
+                | ((self.frame_length / 8) & 0xff_ffff),
+            (self.complex as u32) << 31
+                | ((self.bits_per_sample as u32).saturating_sub(1) & 0x1f) << 26
+                | (self.thread_id as u32 & 0x3ff) << 16
+                | self.station_id as u32,
+        ];
+        let extended = if self.legacy {
+            &[][..]
+        } else {
+            &self.extended[..]
+        };
+        words
+            .iter()
+            .chain(extended)
+            .flat_map(|w| w.to_le_bytes())
+            .collect()
+    }
+
+    /// The size of this header in bytes
+    pub fn header_len(&self) -> usize {
+        if self.legacy {
+            LEGACY_HEADER_LEN
+        } else {
+            HEADER_LEN
+        }
+    }
+
+    /// The size of the data in this frame in bytes
+    pub fn data_len(&self) -> usize {
+        self.frame_length as usize - self.header_len()
+    }
+
+    /// The number of channels
+    pub fn channels(&self) -> u32 {
+        1 << self.log2_channels
+    }
+
+    /// When this frame starts, given the number of frames per second
+    pub fn time(&self, frames_per_second: u64) -> UtcTime {
+        let nanos = self.frame_number as u64 * 1_000_000_000 / frames_per_second;
+        let start = epoch_start(self.ref_epoch);
+        UtcTime::from_unix(start.unix_seconds() + self.seconds as i64, nanos as u32)
+    }
+
+    /// The number of frames between the unix epoch and this one, to put frames in order across reference epochs
+    fn index(&self, frames_per_second: u64) -> u64 {
+        let seconds = epoch_start(self.ref_epoch).unix_seconds() as u64 + self.seconds as u64;
+        seconds * frames_per_second + self.frame_number as u64
+    }
+}
+
+/// Read the next frame from a stream of back-to-back frames into `buf`, returning `None` at the end of the stream
+pub fn read_frame(reader: &mut impl Read, buf: &mut Vec<u8>) -> PsrdadaResult<Option<VdifHeader>> {
+    buf.resize(LEGACY_HEADER_LEN, 0);
+    match read_up_to(reader, buf)? {
+        0 => return Ok(None),
+        LEGACY_HEADER_LEN => (),
+        _ => return Err(PsrdadaError::VdifMalformedFrame),
+    }
+    let frame_length = (word(buf, 2) & 0xff_ffff) as usize * 8;
+    if frame_length < LEGACY_HEADER_LEN {
+        return Err(PsrdadaError::VdifMalformedFrame);
+    }
+    buf.resize(frame_length, 0);
+    if read_up_to(reader, &mut buf[LEGACY_HEADER_LEN..])? != frame_length - LEGACY_HEADER_LEN {
+        return Err(PsrdadaError::VdifMalformedFrame);
+    }
+    VdifHeader::parse(buf).map(Some)
+}
+
+/// Counters of the frames seen by a [`VdifAssembler`]
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct VdifStats {
+    /// Number of frames of the thread being assembled
+    pub frames: u64,
+    /// Number of those frames marked invalid, whose data was replaced by fill
+    pub invalid: u64,
+    /// Number of frames that never arrived, replaced by fill
+    pub missing: u64,
+    /// Number of frames thrown away for arriving after their place in the data was written, or twice
+    pub late: u64,
+    /// Number of frames ignored for belonging to another thread
+    pub other_threads: u64,
+}
+
+/// Puts VDIF frames of one thread back together into a stream of data, see the [module docs](self)
+pub struct VdifAssembler {
+    frames_per_second: u64,
+    window: usize,
+    max_gap: u64,
+    thread: Option<u16>,
+    fill: u8,
+    first: Option<VdifHeader>,
+    /// The index of the next frame to write out
+    next: u64,
+    /// Frames waiting to be written, by index, with `None` for invalid ones
+    pending: BTreeMap<u64, Option<Vec<u8>>>,
+    stats: VdifStats,
+}
+
+impl VdifAssembler {
+    /// Assemble frames of a stream with `frames_per_second` frames every second
+    pub fn new(frames_per_second: u64) -> Self {
+        Self {
+            frames_per_second: frames_per_second.max(1),
+            window: 0,
+            max_gap: DEFAULT_MAX_GAP,
+            thread: None,
+            fill: 0,
+            first: None,
+            next: 0,
+            pending: BTreeMap::new(),
+            stats: VdifStats::default(),
+        }
+    }
+
+    /// Hold on to up to `frames` frames to put them back in order before writing them out. With the default of 0, any frame
+    /// that arrives after a later one is late.
+    pub fn window(mut self, frames: usize) -> Self {
+        self.window = frames;
+        self
+    }
+
+    /// The most frames that can go missing between the latest frame and the next, [`DEFAULT_MAX_GAP`] by default. A frame
+    /// further ahead than that is taken to be malformed, rather than filling in for everything before it.
+    pub fn max_gap(mut self, frames: u64) -> Self {
+        self.max_gap = frames;
+        self
+    }
+
+    /// Only assemble frames of this thread, ignoring the rest. By default, frames of every thread are taken to be one stream.
+    pub fn thread(mut self, thread_id: u16) -> Self {
+        self.thread = Some(thread_id);
+        self
+    }
+
+    /// The byte written in place of missing and invalid data, 0 by default
+    pub fn fill(mut self, fill: u8) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    /// The header of the first frame taken, which the data starts at
+    pub fn first(&self) -> Option<&VdifHeader> {
+        self.first.as_ref()
+    }
+
+    /// The counters so far
+    pub fn stats(&self) -> VdifStats {
+        self.stats
+    }
+
+    /// Take a frame, writing out the data of any frames that leave the window.
+    ///
+    /// Fails with [`PsrdadaError::VdifMalformedFrame`] for a frame that doesn't parse, is shorter than its header says, has a
+    /// different amount of data than the first frame, a frame number past the end of the second, or that's more than
+    /// [`max_gap`](Self::max_gap) frames past the latest one.
+    pub fn push(&mut self, frame: &[u8], out: &mut impl Write) -> PsrdadaResult<()> {
+        let header = VdifHeader::parse(frame)?;
+        if self
+            .thread
+            .map_or(false, |thread| thread != header.thread_id)
+        {
+            self.stats.other_threads += 1;
+            return Ok(());
+        }
+        let data = frame
+            .get(header.header_len()..header.frame_length as usize)
+            .ok_or(PsrdadaError::VdifMalformedFrame)?;
+        if header.frame_number as u64 >= self.frames_per_second
+            || self
+                .first
+                .map_or(false, |first| first.data_len() != data.len())
+        {
+            return Err(PsrdadaError::VdifMalformedFrame);
+        }
+        let index = header.index(self.frames_per_second);
+        if self.first.is_none() {
+            self.first = Some(header);
+            self.next = index;
+        }
+        let latest = self
+            .pending
+            .keys()
+            .next_back()
+            .map_or(self.next, |index| index + 1);
+        if index > latest.saturating_add(self.max_gap) {
+            return Err(PsrdadaError::VdifMalformedFrame);
+        }
+        self.stats.frames += 1;
+        if index < self.next || self.pending.contains_key(&index) {
+            self.stats.late += 1;
+            return Ok(());
+        }
+        if header.invalid {
+            self.stats.invalid += 1;
+            self.pending.insert(index, None);
+        } else {
+            self.pending.insert(index, Some(data.to_vec()));
+        }
+        while self.pending.len() > self.window {
+            self.write_first(out)?;
+        }
+        Ok(())
+    }
+
+    /// Write out the earliest pending frame, filling in for any missing before it
+    fn write_first(&mut self, out: &mut impl Write) -> PsrdadaResult<()> {
+        let index = match self.pending.keys().next() {
+            Some(index) => *index,
+            None => return Ok(()),
+        };
+        let data = self.pending.remove(&index).flatten();
+        let data_len = self.first.map(|first| first.data_len()).unwrap_or_default();
+        let filler = vec![self.fill; data_len];
+        let missing = index - self.next;
+        if missing > 0 {
+            warn!(missing, "Filling missing VDIF frames");
+            self.stats.missing += missing;
+            for _ in 0..missing {
+                out.write_all(&filler)?;
+            }
+        }
+        out.write_all(data.as_deref().unwrap_or(&filler))?;
+        self.next = index + 1;
+        Ok(())
+    }
+
+    /// Write out every pending frame, as at the end of the stream
+    pub fn finish(&mut self, out: &mut impl Write) -> PsrdadaResult<()> {
+        while !self.pending.is_empty() {
+            self.write_first(out)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Cuts data into VDIF frames, see the [module docs](self)
+pub struct VdifPacketizer {
+    /// The header of the next frame
+    header: VdifHeader,
+    frames_per_second: u32,
+    /// Data waiting for a whole frame
+    data: Vec<u8>,
+    frames: u64,
+}
+
+impl VdifPacketizer {
+    /// Make frames with `data_len` bytes of data, timestamped from the header.
+    ///
+    /// The header needs `UTC_START` (in whole seconds, from 2000 to 2031), `BYTES_PER_SECOND` and `NBIT`, and `NCHAN` × `NPOL`
+    /// has to be a power of two; `NDIM` of 2 makes the frames complex. A second has to be a whole number of frames, and the data
+    /// has to start at the start of a frame, so `BYTES_PER_SECOND` and `OBS_OFFSET` have to be multiples of `data_len`.
+    /// Fails with [`PsrdadaError::HeaderMissingKey`] or [`PsrdadaError::HeaderValueError`] otherwise, or with
+    /// [`PsrdadaError::VdifMalformedFrame`] if `data_len` isn't a multiple of 8.
+    pub fn new(dada: &StandardHeader, data_len: usize) -> PsrdadaResult<Self> {
+        if data_len == 0 || data_len % 8 != 0 {
+            return Err(PsrdadaError::VdifMalformedFrame);
+        }
+        let utc_start = required(dada.utc_start, "UTC_START")?;
+        let bytes_per_second = required(dada.bytes_per_second, "BYTES_PER_SECOND")?;
+        let nbit = required(dada.nbit, "NBIT")?;
+        let obs_offset = dada.obs_offset.unwrap_or(0);
+        let channels = dada.nchan.unwrap_or(1) * dada.npol.unwrap_or(1);
+        if !(1..=32).contains(&nbit) {
+            return Err(value_error("NBIT", nbit));
+        }
+        if !channels.is_power_of_two() {
+            return Err(value_error("NCHAN", dada.nchan.unwrap_or(1)));
+        }
+        if bytes_per_second == 0 || bytes_per_second % data_len as u64 != 0 {
+            return Err(value_error("BYTES_PER_SECOND", bytes_per_second));
+        }
+        if obs_offset % data_len as u64 != 0 {
+            return Err(value_error("OBS_OFFSET", obs_offset));
+        }
+        if utc_start.subsec_nanos() != 0 {
+            return Err(value_error("UTC_START", utc_start));
+        }
+        let start = UtcTime::from_unix(
+            utc_start.unix_seconds() + (obs_offset / bytes_per_second) as i64,
+            0,
+        );
+        let ref_epoch = epoch_at(start).ok_or_else(|| value_error("UTC_START", utc_start))?;
+        Ok(Self {
+            header: VdifHeader {
+                seconds: (start.unix_seconds() - epoch_start(ref_epoch).unix_seconds()) as u32,
+                ref_epoch,
+                frame_number: ((obs_offset % bytes_per_second) / data_len as u64) as u32,
+                log2_channels: channels.trailing_zeros() as u8,
+                frame_length: (HEADER_LEN + data_len) as u32,
+                complex: dada.ndim == Some(2),
+                bits_per_sample: nbit as u8,
+                ..Default::default()
+            },
+            frames_per_second: (bytes_per_second / data_len as u64) as u32,
+            data: Vec::with_capacity(data_len),
+            frames: 0,
+        })
+    }
+
+    /// Set the station ID of every frame
+    pub fn station(mut self, station_id: u16) -> Self {
+        self.header.station_id = station_id;
+        self
+    }
+
+    /// Set the thread ID of every frame
+    pub fn thread(mut self, thread_id: u16) -> Self {
+        self.header.thread_id = thread_id;
+        self
+    }
+
+    /// Make legacy frames, with 16 byte headers
+    pub fn legacy(mut self) -> Self {
+        let data_len = self.header.data_len();
+        self.header.legacy = true;
+        self.header.frame_length = (LEGACY_HEADER_LEN + data_len) as u32;
+        self
+    }
+
+    /// The number of frames made so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn write_frame(&mut self, data: &[u8], out: &mut impl Write) -> PsrdadaResult<()> {
+        let mut frame = self.header.to_bytes();
+        frame.extend_from_slice(data);
+        out.write_all(&frame)?;
+        self.header.frame_number += 1;
+        if self.header.frame_number == self.frames_per_second {
+            self.header.frame_number = 0;
+            self.header.seconds += 1;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Add data, writing out every frame that fills up. Each frame is written with a single `write_all`, so a writer that
+    /// sends every write as a packet sends a frame per packet.
+    pub fn push(&mut self, mut data: &[u8], out: &mut impl Write) -> PsrdadaResult<()> {
+        let data_len = self.header.data_len();
+        while !data.is_empty() {
+            if self.data.is_empty() && data.len() >= data_len {
+                let (frame, rest) = data.split_at(data_len);
+                self.write_frame(frame, out)?;
+                data = rest;
+                continue;
+            }
+            let n = (data_len - self.data.len()).min(data.len());
+            self.data.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.data.len() == data_len {
+                let frame = std::mem::take(&mut self.data);
+                self.write_frame(&frame, out)?;
+                self.data = frame;
+                self.data.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out what's left as a last frame, padded with zeros and marked invalid, returning the number of frames made
+    pub fn finish(mut self, out: &mut impl Write) -> PsrdadaResult<u64> {
+        if !self.data.is_empty() {
+            let mut frame = std::mem::take(&mut self.data);
+            frame.resize(self.header.data_len(), 0);
+            self.header.invalid = true;
+            self.write_frame(&frame, out)?;
+        }
+        out.flush()?;
+        Ok(self.frames)
+    }
+}
+
+/// Reads assembled data, pulling frames from a stream of them as needed
+struct Assembled<R> {
+    input: R,
+    assembler: VdifAssembler,
+    frame: Vec<u8>,
+    data: Vec<u8>,
+    read: usize,
+    done: bool,
+}
+
+impl<R: Read> Assembled<R> {
+    fn fill(&mut self) -> PsrdadaResult<()> {
+        self.data.clear();
+        self.read = 0;
+        while self.data.is_empty() && !self.done {
+            match read_frame(&mut self.input, &mut self.frame)? {
+                Some(_) => self.assembler.push(&self.frame, &mut self.data)?,
+                None => {
+                    self.assembler.finish(&mut self.data)?;
+                    self.done = true;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Assembled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read == self.data.len() {
+            self.fill()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}")))?;
+        }
+        let n = (&self.data[self.read..]).read(buf)?;
+        self.read += n;
+        Ok(n)
+    }
+}
+
+/// Assemble a stream of back-to-back VDIF frames into a ring as a single transfer, returning the counters of the assembler.
+///
+/// The ring's header is `header`, with `UTC_START`, `OBS_OFFSET`, `BYTES_PER_SECOND`, `NBIT` and `NDIM` set from the first
+/// frame (of the assembler's thread), and `NCHAN` too if it isn't there.
+pub fn play_vdif(
+    input: impl Read,
+    mut header: StandardHeader,
+    assembler: VdifAssembler,
+    header_client: &mut HeaderClient,
+    data_client: &mut DataClient,
+) -> PsrdadaResult<VdifStats> {
+    let mut assembled = Assembled {
+        input,
+        assembler,
+        frame: vec![],
+        data: vec![],
+        read: 0,
+        done: false,
+    };
+    assembled.fill()?;
+    let first = match assembled.assembler.first() {
+        Some(first) => *first,
+        None => return Ok(assembled.assembler.stats()),
+    };
+    let fps = assembled.assembler.frames_per_second;
+    let data_len = first.data_len() as u64;
+    header.utc_start = Some(UtcTime::from_unix(first.time(fps).unix_seconds(), 0));
+    header.obs_offset = Some(first.frame_number as u64 * data_len);
+    header.bytes_per_second = Some(fps * data_len);
+    header.nbit = Some(first.bits_per_sample as u32);
+    header.ndim = Some(if first.complex { 2 } else { 1 });
+    header.nchan.get_or_insert(first.channels());
+    header_client.write_header(&header.into())?;
+    let mut reader = BufReader::new(assembled);
+    data_client.writer()?.write_from(&mut reader, |_| ())?;
+    Ok(reader.into_inner().assembler.stats())
+}
+
+/// Read the next transfer from a ring and write it to `out` as VDIF frames with `data_len` bytes of data, returning the number
+/// of frames written. See [`VdifPacketizer::new`] for what the header needs.
+pub fn record_vdif(
+    header_client: &mut HeaderClient,
+    data_client: &mut DataClient,
+    data_len: usize,
+    out: &mut impl Write,
+) -> PsrdadaResult<u64> {
+    let dada = StandardHeader::try_from(header_client.read_header()?)?;
+    let mut packetizer = VdifPacketizer::new(&dada, data_len)?;
+    let mut reader = data_client.reader()?;
+    while let Some(mut block) = reader.next() {
+        packetizer.push(block.block(), out)?;
+    }
+    packetizer.finish(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use test_log::test;
+
+    use super::*;
+    use crate::{builder::DadaClientBuilder, headers::bytes_to_header, tests::next_key};
+
+    const DADA: &[u8] = b"UTC_START 2023-08-01-00:00:10
+OBS_OFFSET 64
+BYTES_PER_SECOND 256
+NCHAN 2
+NPOL 2
+NBIT 2
+NDIM 2
+";
+
+    fn standard() -> StandardHeader {
+        StandardHeader::try_from(bytes_to_header(DADA).unwrap()).unwrap()
+    }
+
+    fn frames(data: &[u8], packetizer: VdifPacketizer) -> Vec<Vec<u8>> {
+        let mut packetizer = packetizer;
+        let mut out = vec![];
+        packetizer.push(data, &mut out).unwrap();
+        let len = packetizer.header.frame_length as usize;
+        packetizer.finish(&mut out).unwrap();
+        out.chunks(len).map(<[u8]>::to_vec).collect()
+    }
+
+    #[test]
+    fn test_header() {
+        let header = VdifHeader {
+            invalid: true,
+            seconds: 12345,
+            ref_epoch: 47,
+            frame_number: 99,
+            log2_channels: 3,
+            frame_length: 8032,
+            complex: true,
+            bits_per_sample: 2,
+            thread_id: 5,
+            station_id: 0x4142,
+            extended: [1, 2, 3, 4],
+            ..Default::default()
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN);
+        assert_eq!(&bytes[..4], &(12345u32 | 1 << 31).to_le_bytes());
+        assert_eq!(
+            &bytes[12..16],
+            &(1u32 << 31 | 1 << 26 | 5 << 16 | 0x4142).to_le_bytes()
+        );
+        assert_eq!(VdifHeader::parse(&bytes).unwrap(), header);
+        assert_eq!(header.data_len(), 8000);
+        assert_eq!(header.channels(), 8);
+
+        let legacy = VdifHeader {
+            legacy: true,
+            extended: [0; 4],
+            frame_length: 8016,
+            ..header
+        };
+        let bytes = legacy.to_bytes();
+        assert_eq!(bytes.len(), LEGACY_HEADER_LEN);
+        assert_eq!(VdifHeader::parse(&bytes).unwrap(), legacy);
+        assert_eq!(legacy.data_len(), 8000);
+
+        assert_eq!(
+            VdifHeader::parse(&header.to_bytes()[..16]),
+            Err(PsrdadaError::VdifMalformedFrame)
+        );
+    }
+
+    #[test]
+    fn test_time() {
+        assert_eq!(epoch_start(47).to_string(), "2023-07-01-00:00:00");
+        let header = VdifHeader {
+            ref_epoch: 47,
+            seconds: 86400 * 31 + 10,
+            frame_number: 3,
+            ..Default::default()
+        };
+        assert_eq!(header.time(4).to_string(), "2023-08-01-00:00:10.75");
+        assert_eq!(epoch_at("2023-08-01-00:00:10".parse().unwrap()), Some(47));
+        assert_eq!(epoch_at("1999-08-01-00:00:10".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_packetize() {
+        let data: Vec<u8> = (0..200).collect();
+        let frames = frames(
+            &data,
+            VdifPacketizer::new(&standard(), 64).unwrap().thread(3),
+        );
+        assert_eq!(frames.len(), 4);
+        let headers: Vec<_> = frames
+            .iter()
+            .map(|f| VdifHeader::parse(f).unwrap())
+            .collect();
+        // OBS_OFFSET puts the first frame a quarter second in
+        let times: Vec<_> = headers.iter().map(|h| h.time(4).to_string()).collect();
+        assert_eq!(times, [
+            "2023-08-01-00:00:10.25",
+            "2023-08-01-00:00:10.5",
+            "2023-08-01-00:00:10.75",
+            "2023-08-01-00:00:11",
+        ]);
+        assert!(headers
+            .iter()
+            .all(|h| h.thread_id == 3 && h.complex && h.bits_per_sample == 2));
+        assert_eq!(headers[0].channels(), 4);
+        // The last frame is padded and invalid
+        assert!(!headers[2].invalid && headers[3].invalid);
+        assert_eq!(&frames[3][HEADER_LEN..HEADER_LEN + 8], &data[192..]);
+
+        let mut header = standard();
+        header.bytes_per_second = Some(100);
+        assert_eq!(
+            VdifPacketizer::new(&header, 64).err(),
+            Some(value_error("BYTES_PER_SECOND", 100))
+        );
+    }
+
+    #[test]
+    fn test_assemble() {
+        let data: Vec<u8> = (0..=255).collect();
+        let mut frames = frames(
+            &data,
+            VdifPacketizer::new(&standard(), 32).unwrap().legacy(),
+        );
+        assert_eq!(frames.len(), 8);
+        // Lose frame 2, invalidate frame 4, swap 5 and 6, and send 1 again at the end
+        let mut invalid = VdifHeader::parse(&frames[4]).unwrap();
+        invalid.invalid = true;
+        frames[4][..LEGACY_HEADER_LEN].copy_from_slice(&invalid.to_bytes());
+        frames.swap(5, 6);
+        let late = frames[1].clone();
+        frames.remove(2);
+        frames.push(late);
+
+        let mut assembler = VdifAssembler::new(8).window(2).fill(0xff);
+        let mut out = vec![];
+        for frame in &frames {
+            assembler.push(frame, &mut out).unwrap();
+        }
+        assembler.finish(&mut out).unwrap();
+        let mut expected = data.clone();
+        expected[64..96].fill(0xff);
+        expected[128..160].fill(0xff);
+        assert_eq!(out, expected);
+        assert_eq!(assembler.stats(), VdifStats {
+            frames: 8,
+            invalid: 1,
+            missing: 1,
+            late: 1,
+            other_threads: 0,
+        });
+
+        let mut assembler = VdifAssembler::new(8).thread(1);
+        assembler.push(&frames[0], &mut out).unwrap();
+        assert_eq!(assembler.stats().other_threads, 1);
+    }
+
+    #[test]
+    fn test_max_gap() {
+        let data: Vec<u8> = (0..=255).collect();
+        let frames = frames(
+            &data,
+            VdifPacketizer::new(&standard(), 32).unwrap().legacy(),
+        );
+        // A frame from a thousand seconds later, which would otherwise be filled in for
+        let mut far = frames[2].clone();
+        let mut header = VdifHeader::parse(&far).unwrap();
+        header.seconds += 1000;
+        far[..LEGACY_HEADER_LEN].copy_from_slice(&header.to_bytes());
+
+        let mut assembler = VdifAssembler::new(8).max_gap(4);
+        let mut out = vec![];
+        assembler.push(&frames[0], &mut out).unwrap();
+        assembler.push(&frames[1], &mut out).unwrap();
+        assert_eq!(
+            assembler.push(&far, &mut out),
+            Err(PsrdadaError::VdifMalformedFrame)
+        );
+        // Skipping up to the limit is fine
+        assembler.push(&frames[6], &mut out).unwrap();
+        assembler.finish(&mut out).unwrap();
+        let mut expected = data[..224].to_vec();
+        expected[64..192].fill(0);
+        assert_eq!(out, expected);
+        assert_eq!(assembler.stats(), VdifStats {
+            frames: 3,
+            missing: 4,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_ring_round_trip() {
+        let data: Vec<u8> = (0..1024).map(|i| (i * 3) as u8).collect();
+        let mut stream = vec![];
+        let mut packetizer = VdifPacketizer::new(&standard(), 64).unwrap().station(7);
+        packetizer.push(&data, &mut stream).unwrap();
+        packetizer.finish(&mut stream).unwrap();
+
+        let mut client = DadaClientBuilder::new(next_key())
+            .num_bufs(8)
+            .buf_size(256)
+            .build()
+            .unwrap();
+        let (mut hc, mut dc) = client.split();
+        let header = StandardHeader {
+            npol: Some(2),
+            nchan: Some(2),
+            ..Default::default()
+        };
+        let stats = play_vdif(
+            Cursor::new(stream.clone()),
+            header,
+            VdifAssembler::new(4),
+            &mut hc,
+            &mut dc,
+        )
+        .unwrap();
+        assert_eq!(stats.frames, 16);
+        let mut out = vec![];
+        assert_eq!(record_vdif(&mut hc, &mut dc, 64, &mut out).unwrap(), 16);
+        let mut frame = vec![];
+        let mut cursor = Cursor::new(&out);
+        let first = read_frame(&mut cursor, &mut frame).unwrap().unwrap();
+        assert_eq!(first.time(4).to_string(), "2023-08-01-00:00:10.25");
+        // Everything but the station ID made it through
+        let strip = |frames: &[u8]| -> Vec<u8> {
+            let mut cursor = Cursor::new(frames);
+            let mut frame = vec![];
+            let mut all = vec![];
+            while let Some(mut header) = read_frame(&mut cursor, &mut frame).unwrap() {
+                header.station_id = 0;
+                all.extend(header.to_bytes());
+                all.extend(&frame[header.header_len()..]);
+            }
+            all
+        };
+        assert_eq!(strip(&out), strip(&stream));
+    }
+}